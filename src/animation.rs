@@ -0,0 +1,1455 @@
+use bevy_animation::{AnimationClip, AnimationPlayer, EntityPath, Keyframes, VariableCurve};
+use bevy_asset::{AssetServer, Handle};
+use bevy_core::Name;
+use bevy_ecs::{component::Component, system::Resource};
+use bevy_math::{EulerRot, Mat4, Quat, Vec3};
+use blend::{Blend, Instance};
+use std::collections::HashMap;
+
+use crate::{
+    armature,
+    coordinates::Conversion,
+    fcurve::{self, FCurve},
+    get_int, light,
+    marker::{self, BlenderMarker},
+    object, parenting,
+    property::{
+        BlenderPropertyChannel, BlenderPropertyClip, BlenderPropertyCurve, BlenderPropertyPlayer,
+    },
+    root_motion::{self, BlenderRootMotion},
+    scene::{self, BlenderSceneTiming},
+    vertex_animation::BlenderVertexAnimationBake,
+    BevyBlenderError,
+};
+
+// Object.rotmode values (DNA_action_types.h)
+const ROT_MODE_QUAT: i16 = 0;
+const ROT_MODE_XZY: i16 = 2;
+const ROT_MODE_YXZ: i16 = 3;
+const ROT_MODE_YZX: i16 = 4;
+const ROT_MODE_ZXY: i16 = 5;
+const ROT_MODE_ZYX: i16 = 6;
+const ROT_MODE_AXISANGLE: i16 = -1;
+
+// NlaStrip.type values
+const NLASTRIP_TYPE_CLIP: i32 = 0;
+const NLASTRIP_TYPE_META: i32 = 2;
+
+/// Takes a .blend file location and an action name and generates
+/// an appropriate asset_loader string. For example,
+/// blender_action!("demo.blend", "DoorOpen") turns to "demo.blend#ACDoorOpen".
+#[macro_export]
+macro_rules! blender_action {
+    ($blend_file:literal, $action_name:literal) => {
+        format!("{}#AC{}", $blend_file, $action_name).as_str()
+    };
+}
+
+/// How the F-curves of actions are converted to the keyframes of animation clips, whose values
+/// are interpolated linearly by Bevy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlenderAnimationSampling {
+    /// Keeps the keyframes of the F-curves, and adds samples between them wherever interpolating
+    /// linearly deviates from the F-curve (Bezier, easing...) by more than "tolerance"
+    Keyframes {
+        /// Largest difference allowed between a F-curve and its linear interpolation
+        tolerance: f32,
+    },
+    /// Samples the F-curves at a fixed rate, from their first to their last keyframe
+    Resample {
+        /// Number of samples per second of animation
+        samples_per_second: f32,
+    },
+}
+
+impl Default for BlenderAnimationSampling {
+    fn default() -> Self {
+        BlenderAnimationSampling::Keyframes { tolerance: 0.001 }
+    }
+}
+
+/// Settings of the animation clips loaded from .blend files. Insert this resource before adding
+/// the BlenderPlugin to change them.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct BlenderAnimationSettings {
+    /// How F-curves are converted to keyframes
+    pub sampling: BlenderAnimationSampling,
+    /// Whether the clips of actions only play the frames within the frame range of the scene,
+    /// instead of all of their keyframes
+    pub clip_to_scene_range: bool,
+    /// Name of the root bone whose horizontal translation and yaw are stripped from the clips,
+    /// and loaded as a separate BlenderRootMotion, if any
+    pub root_motion_bone: Option<String>,
+    /// Mesh objects whose deformation is baked to vertex animation textures when their .blend file
+    /// is loaded
+    pub vertex_animations: Vec<BlenderVertexAnimationBake>,
+    /// Path of the .blend file (e.g. "demo.blend") whose timing is inserted as the
+    /// BlenderSceneTiming resource, the first .blend file loaded if None
+    pub timing_file: Option<String>,
+}
+
+/// The animation clips of a spawned object and its children, by name: their actions, the strips of
+/// their NLA tracks and the actions of the action library of their armatures. Added to the spawned
+/// root object, whose AnimationPlayer can play all of them.
+#[derive(Component, Debug, Clone, Default)]
+pub struct BlenderAnimationClips {
+    /// Handles of the clips, by action or NLA strip name
+    pub clips: HashMap<String, Handle<AnimationClip>>,
+    /// Handles of the property clips, by action or NLA strip name
+    pub properties: HashMap<String, Handle<BlenderPropertyClip>>,
+}
+
+impl BlenderAnimationClips {
+    /// Returns the clip of the action or NLA strip named "name", if any
+    pub fn get(&self, name: &str) -> Option<&Handle<AnimationClip>> {
+        self.clips.get(name)
+    }
+
+    /// Plays the clip and the property clip of the action or NLA strip named "name" on repeat.
+    /// Returns false if there is no such clip.
+    pub fn play(
+        &self,
+        name: &str,
+        player: &mut AnimationPlayer,
+        property_player: &mut BlenderPropertyPlayer,
+    ) -> bool {
+        let Some(clip) = self.clips.get(name) else {
+            return false;
+        };
+        player.play(clip.clone()).repeat();
+        property_player.clip = self.properties.get(name).cloned();
+        property_player.repeat = true;
+        true
+    }
+}
+
+/// Location, rotation and scale channels of an object or a pose bone, in the Blender coordinate
+/// system. Objects also have delta channels, which are applied on top of the regular ones.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TransformChannels {
+    pub location: Vec3,
+    pub rotation_euler: Vec3,
+    /// Stored as w, x, y, z like in Blender
+    pub rotation_quaternion: [f32; 4],
+    /// Stored as angle, x, y, z like in Blender
+    pub rotation_axis_angle: [f32; 4],
+    pub scale: Vec3,
+    /// Which of the rotation channels is used, one of the Object.rotmode values
+    pub rotation_mode: i16,
+    pub delta_location: Vec3,
+    pub delta_rotation_euler: Vec3,
+    /// Stored as w, x, y, z like in Blender
+    pub delta_rotation_quaternion: [f32; 4],
+    /// Stored as angle, x, y, z like in Blender
+    pub delta_rotation_axis_angle: [f32; 4],
+    pub delta_scale: Vec3,
+}
+
+impl Default for TransformChannels {
+    fn default() -> Self {
+        Self {
+            location: Vec3::ZERO,
+            rotation_euler: Vec3::ZERO,
+            rotation_quaternion: [1.0, 0.0, 0.0, 0.0],
+            rotation_axis_angle: [0.0, 0.0, 1.0, 0.0],
+            scale: Vec3::ONE,
+            rotation_mode: 1,
+            delta_location: Vec3::ZERO,
+            delta_rotation_euler: Vec3::ZERO,
+            delta_rotation_quaternion: [1.0, 0.0, 0.0, 0.0],
+            delta_rotation_axis_angle: [0.0, 0.0, 1.0, 0.0],
+            delta_scale: Vec3::ONE,
+        }
+    }
+}
+
+impl TransformChannels {
+    /// Reads the channels of a Blend::Instance object, along with its delta channels
+    pub fn from_object(obj: &Instance) -> Self {
+        let quat = obj.get_f32_vec("quat");
+        let axis = obj.get_f32_vec("rotAxis");
+        let delta_quat = obj.get_f32_vec("dquat");
+        let delta_axis = obj.get_f32_vec("drotAxis");
+        // The scale is called "size" in the DNA of most versions of Blender
+        let scale = if obj.fields.contains_key("scale") {
+            obj.get_f32_vec("scale")
+        } else {
+            obj.get_f32_vec("size")
+        };
+
+        Self {
+            location: Vec3::from_slice(&obj.get_f32_vec("loc")),
+            rotation_euler: Vec3::from_slice(&obj.get_f32_vec("rot")),
+            rotation_quaternion: [quat[0], quat[1], quat[2], quat[3]],
+            rotation_axis_angle: [obj.get_f32("rotAngle"), axis[0], axis[1], axis[2]],
+            scale: Vec3::from_slice(&scale),
+            rotation_mode: obj.get_i16("rotmode"),
+            delta_location: Vec3::from_slice(&obj.get_f32_vec("dloc")),
+            delta_rotation_euler: Vec3::from_slice(&obj.get_f32_vec("drot")),
+            delta_rotation_quaternion: [delta_quat[0], delta_quat[1], delta_quat[2], delta_quat[3]],
+            delta_rotation_axis_angle: [
+                obj.get_f32("drotAngle"),
+                delta_axis[0],
+                delta_axis[1],
+                delta_axis[2],
+            ],
+            delta_scale: Vec3::from_slice(&obj.get_f32_vec("dscale")),
+        }
+    }
+
+    /// Reads the channels of a Blend::Instance pose bone (bPoseChannel), which are relative to the
+    /// rest pose of the bone
+    pub fn from_pose_channel(channel: &Instance) -> Self {
+        let quat = channel.get_f32_vec("quat");
+        let axis = channel.get_f32_vec("rotAxis");
+
+        Self {
+            location: Vec3::from_slice(&channel.get_f32_vec("loc")),
+            rotation_euler: Vec3::from_slice(&channel.get_f32_vec("eul")),
+            rotation_quaternion: [quat[0], quat[1], quat[2], quat[3]],
+            rotation_axis_angle: [channel.get_f32("rotAngle"), axis[0], axis[1], axis[2]],
+            scale: Vec3::from_slice(&channel.get_f32_vec("size")),
+            rotation_mode: channel.get_i16("rotmode"),
+            ..Default::default()
+        }
+    }
+
+    /// Returns true if the property is one of the transform channels
+    pub fn has_property(property: &str) -> bool {
+        matches!(
+            property,
+            "location"
+                | "rotation_euler"
+                | "rotation_quaternion"
+                | "rotation_axis_angle"
+                | "scale"
+                | "delta_location"
+                | "delta_rotation_euler"
+                | "delta_rotation_quaternion"
+                | "delta_scale"
+        )
+    }
+
+    /// Sets a component of an animated property. Returns false if the property is not one of the
+    /// transform channels.
+    pub fn set(&mut self, property: &str, index: usize, value: f32) -> bool {
+        match (property, index) {
+            ("location", 0..=2) => self.location[index] = value,
+            ("rotation_euler", 0..=2) => self.rotation_euler[index] = value,
+            ("rotation_quaternion", 0..=3) => self.rotation_quaternion[index] = value,
+            ("rotation_axis_angle", 0..=3) => self.rotation_axis_angle[index] = value,
+            ("scale", 0..=2) => self.scale[index] = value,
+            ("delta_location", 0..=2) => self.delta_location[index] = value,
+            ("delta_rotation_euler", 0..=2) => self.delta_rotation_euler[index] = value,
+            ("delta_rotation_quaternion", 0..=3) => self.delta_rotation_quaternion[index] = value,
+            ("delta_scale", 0..=2) => self.delta_scale[index] = value,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Returns the rotation of the channel used by the rotation mode, after its delta rotation
+    /// (like BKE_object_rot_to_mat3)
+    pub fn rotation(&self) -> Quat {
+        let rotation = |euler: Vec3, quaternion: [f32; 4], axis_angle: [f32; 4]| match self
+            .rotation_mode
+        {
+            ROT_MODE_QUAT => {
+                let [w, x, y, z] = quaternion;
+                Quat::from_xyzw(x, y, z, w).normalize()
+            }
+            ROT_MODE_AXISANGLE => {
+                let [angle, x, y, z] = axis_angle;
+                Quat::from_axis_angle(Vec3::new(x, y, z).try_normalize().unwrap_or(Vec3::Y), angle)
+            }
+            rotation_mode => euler_to_quat(euler, rotation_mode),
+        };
+        rotation(
+            self.delta_rotation_euler,
+            self.delta_rotation_quaternion,
+            self.delta_rotation_axis_angle,
+        ) * rotation(
+            self.rotation_euler,
+            self.rotation_quaternion,
+            self.rotation_axis_angle,
+        )
+    }
+
+    /// Returns the local transformation matrix of the channels (like BKE_object_to_mat4)
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(
+            self.scale * self.delta_scale,
+            self.rotation(),
+            self.location + self.delta_location,
+        )
+    }
+}
+
+/// Converts Blender euler angles to a quaternion. Blender's rotation modes name the axes in the
+/// order they are applied, so XYZ rotates around X first.
+fn euler_to_quat(euler: Vec3, rotation_mode: i16) -> Quat {
+    let Vec3 { x, y, z } = euler;
+    match rotation_mode {
+        ROT_MODE_XZY => Quat::from_euler(EulerRot::YZX, y, z, x),
+        ROT_MODE_YXZ => Quat::from_euler(EulerRot::ZXY, z, x, y),
+        ROT_MODE_YZX => Quat::from_euler(EulerRot::XZY, x, z, y),
+        ROT_MODE_ZXY => Quat::from_euler(EulerRot::YXZ, y, x, z),
+        ROT_MODE_ZYX => Quat::from_euler(EulerRot::XYZ, x, y, z),
+        _ => Quat::from_euler(EulerRot::ZYX, z, y, x),
+    }
+}
+
+/// Returns the name of the action assigned to an object, or any other datablock with animation
+/// data (with its "AC" prefix), if any
+pub(crate) fn object_action(obj: &Instance) -> Option<String> {
+    if !obj.is_valid("adt") {
+        return None;
+    }
+    let adt = obj.get("adt");
+    if !adt.is_valid("action") {
+        return None;
+    }
+    Some(adt.get("action").get("id").get_string("name"))
+}
+
+/// Returns the F-curves of the action assigned to an object, for the slot the object uses
+pub(crate) fn object_fcurves(obj: &Instance) -> Vec<FCurve> {
+    if !obj.is_valid("adt") || !obj.get("adt").is_valid("action") {
+        return Vec::new();
+    }
+    let adt = obj.get("adt");
+    let slot = slot_handle(&adt, "slot_handle");
+    fcurve::instance_to_fcurves(&adt.get("action"))
+        .into_iter()
+        .filter(|fcurve| {
+            slot.is_none() || fcurve.slot_handle.is_none() || fcurve.slot_handle == slot
+        })
+        .collect()
+}
+
+/// Returns the transform channels of "owner" (see FCurve::owner) with its F-curves evaluated at
+/// "frame". Channels without F-curves keep their "rest" value.
+pub(crate) fn evaluate_owner_channels(
+    fcurves: &[FCurve],
+    owner: &str,
+    rest: TransformChannels,
+    frame: f32,
+) -> TransformChannels {
+    let fcurves: Vec<&FCurve> = fcurves
+        .iter()
+        .filter(|fcurve| {
+            fcurve.owner() == owner && TransformChannels::has_property(fcurve.property())
+        })
+        .collect();
+    evaluate_channels(&fcurves, rest, frame)
+}
+
+/// Returns the "rest" channels with the transform F-curves evaluated at "frame"
+fn evaluate_channels(
+    fcurves: &[&FCurve],
+    rest: TransformChannels,
+    frame: f32,
+) -> TransformChannels {
+    let mut channels = rest;
+    for fcurve in fcurves {
+        channels.set(
+            fcurve.property(),
+            fcurve.array_index,
+            fcurve.evaluate(frame),
+        );
+    }
+    channels
+}
+
+/// Returns true if an object or one of its parents has an action
+fn is_animated(obj: &Instance) -> bool {
+    object_action(obj).is_some() || (obj.is_valid("parent") && is_animated(&obj.get("parent")))
+}
+
+/// Returns the matrix of a Blend::Instance object relative to its parent, which is its parent
+/// inverse matrix followed by its transform channels, with the F-curves of its action evaluated
+/// at "frame" if given
+/// This will be in Blender coordinate system (Right Handed, Z-up)
+pub(crate) fn evaluated_local_matrix(obj: &Instance, frame: Option<f32>) -> Mat4 {
+    let mut channels = TransformChannels::from_object(obj);
+    if let Some(frame) = frame {
+        channels = evaluate_owner_channels(&object_fcurves(obj), "", channels, frame);
+    }
+    if obj.is_valid("parent") {
+        object::get_matrix(obj, "parentinv") * channels.matrix()
+    } else {
+        channels.matrix()
+    }
+}
+
+/// Returns the world matrix of a Blend::Instance object with the F-curves of its action, and of
+/// the actions of its parents, evaluated at "frame". Objects that are not animated keep their
+/// Blender world matrix.
+/// This will be in Blender coordinate system (Right Handed, Z-up)
+pub(crate) fn evaluated_world_matrix(obj: &Instance, frame: f32) -> Mat4 {
+    if !is_animated(obj) {
+        return object::get_world_matrix(obj);
+    }
+
+    let local_matrix = evaluated_local_matrix(obj, Some(frame));
+    if obj.is_valid("parent") {
+        let parent_world = evaluated_world_matrix(&obj.get("parent"), frame);
+        parenting::parent_matrix(obj, parent_world, Some(frame)) * local_matrix
+    } else {
+        local_matrix
+    }
+}
+
+/// Returns the NLA strips of an object that play an action, including the strips of meta strips.
+/// Strips of muted tracks are included, as that is where actions are stashed.
+pub(crate) fn nla_strips<'a>(obj: &Instance<'a>) -> Vec<Instance<'a>> {
+    fn add_strips<'a>(strips: impl Iterator<Item = Instance<'a>>, result: &mut Vec<Instance<'a>>) {
+        for strip in strips {
+            match get_int(&strip, "type") {
+                NLASTRIP_TYPE_CLIP if strip.is_valid("act") => result.push(strip),
+                NLASTRIP_TYPE_META if strip.is_valid("strips") => {
+                    add_strips(strip.get_iter("strips"), result)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut strips = Vec::new();
+    if !obj.is_valid("adt") || !obj.get("adt").is_valid("nla_tracks") {
+        return strips;
+    }
+    for track in obj.get("adt").get_iter("nla_tracks") {
+        if track.is_valid("strips") {
+            add_strips(track.get_iter("strips"), &mut strips);
+        }
+    }
+    strips
+}
+
+/// Returns the label of the clip of a NLA strip of an object, e.g. "OBRobot/NLA/Run"
+pub(crate) fn nla_strip_label(obj: &Instance, strip: &Instance) -> String {
+    format!(
+        "{}/NLA/{}",
+        obj.get("id").get_string("name"),
+        strip.get_string("name")
+    )
+}
+
+/// Returns the slot handle stored in "field" of an AnimData or NLA strip, for actions with slots
+/// (Blender 4.4 and later)
+fn slot_handle(instance: &Instance, field: &str) -> Option<i32> {
+    instance
+        .fields
+        .contains_key(field)
+        .then(|| instance.get_i32(field))
+}
+
+/// The datablock an action animates, which determines the properties its F-curves animate
+#[derive(Clone)]
+enum AnimatedData<'a> {
+    /// The object itself
+    Object,
+    /// The shape keys of the mesh of the object
+    ShapeKeys,
+    /// The light of the object
+    Light(Instance<'a>),
+    /// The camera of the object
+    Camera,
+    /// The (first) material of the mesh of the object
+    Material,
+    /// The node tree of the (first) material of the mesh of the object
+    MaterialNodes(Instance<'a>),
+}
+
+/// An action used by an object, or by one of its datablocks
+#[derive(Clone)]
+struct ActionUse<'a> {
+    /// Name of the action, with its "AC" prefix
+    action: String,
+    /// Slot of the action used, for actions with slots
+    slot: Option<i32>,
+    /// The datablock animated by the action
+    data: AnimatedData<'a>,
+}
+
+/// Returns the actions assigned to an object and to its datablocks: the shape keys and the first
+/// material of its mesh, or its light or camera
+fn assigned_actions<'a>(obj: &Instance<'a>) -> Vec<ActionUse<'a>> {
+    let mut actions = Vec::new();
+    let mut add = |id: &Instance, data: AnimatedData<'a>| {
+        if let Some(action) = object_action(id) {
+            actions.push(ActionUse {
+                action,
+                slot: slot_handle(&id.get("adt"), "slot_handle"),
+                data,
+            });
+        }
+    };
+
+    add(obj, AnimatedData::Object);
+    if !obj.is_valid("data") {
+        return actions;
+    }
+    let data = obj.get("data");
+
+    match obj.get_i16("type") {
+        object::OB_MESH => {
+            // Shape keys are animated by the action of the Key datablock of the mesh
+            if data.is_valid("key") {
+                add(&data.get("key"), AnimatedData::ShapeKeys);
+            }
+            let material = data
+                .is_valid("mat")
+                .then(|| data.get_iter("mat").next())
+                .flatten();
+            if let Some(material) = material {
+                add(&material, AnimatedData::Material);
+                if material.is_valid("nodetree") {
+                    let tree = material.get("nodetree");
+                    add(&tree, AnimatedData::MaterialNodes(tree.clone()));
+                }
+            }
+        }
+        object::OB_LAMP => add(&data, AnimatedData::Light(data.clone())),
+        object::OB_CAMERA => add(&data, AnimatedData::Camera),
+        _ => {}
+    }
+    actions
+}
+
+/// Returns the actions used by an object, through the animation data of the object and its
+/// datablocks or its NLA strips
+fn object_actions<'a>(obj: &Instance<'a>) -> Vec<ActionUse<'a>> {
+    let mut actions = assigned_actions(obj);
+    for strip in nla_strips(obj) {
+        actions.push(ActionUse {
+            action: strip.get("act").get("id").get_string("name"),
+            slot: slot_handle(&strip, "action_slot_handle"),
+            data: AnimatedData::Object,
+        });
+    }
+    actions
+}
+
+/// Returns the objects using the action named "name" (with its "AC" prefix), along with how they
+/// use it
+fn action_users<'a>(blend: &'a Blend, name: &str) -> Vec<(Instance<'a>, ActionUse<'a>)> {
+    blend
+        .get_by_code(*b"OB")
+        .into_iter()
+        .filter_map(|obj| {
+            let action = object_actions(&obj)
+                .into_iter()
+                .find(|action| action.action == name)?;
+            Some((obj, action))
+        })
+        .collect()
+}
+
+/// Which frames of an action a clip plays, how fast and how many times
+#[derive(Debug, Clone, Copy)]
+struct ActionTiming {
+    /// First frame of the action played by the clip
+    start_frame: f32,
+    /// Last frame of the action played by the clip
+    end_frame: f32,
+    /// Duration of a frame of the action, in frames
+    scale: f32,
+    /// Number of times the frames are played
+    repeat: f32,
+    /// Frame rate of the scene, to convert frames to seconds
+    frames_per_second: f32,
+}
+
+impl ActionTiming {
+    /// Plays an action once, from its first to its last keyframe, or only the keyframes within
+    /// the frame range of the scene if "clip_to_scene_range" is set
+    fn from_fcurves(
+        fcurves: &[FCurve],
+        scene: &BlenderSceneTiming,
+        clip_to_scene_range: bool,
+    ) -> Self {
+        let frames = fcurves
+            .iter()
+            .flat_map(|fcurve| fcurve.keyframes.iter().map(|keyframe| keyframe.frame));
+        let (start_frame, end_frame) = frames
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |range, frame| {
+                (range.0.min(frame), range.1.max(frame))
+            });
+        let (start_frame, end_frame) = if clip_to_scene_range {
+            let start_frame = start_frame.max(scene.start_frame as f32);
+            (
+                start_frame,
+                end_frame.min(scene.end_frame as f32).max(start_frame),
+            )
+        } else {
+            (start_frame, end_frame)
+        };
+        Self {
+            start_frame,
+            end_frame,
+            scale: 1.0,
+            repeat: 1.0,
+            frames_per_second: scene.frames_per_second,
+        }
+    }
+
+    /// Plays the action of a Blend::Instance NLA strip like the strip does
+    fn from_nla_strip(strip: &Instance, scene: &BlenderSceneTiming) -> Self {
+        Self {
+            start_frame: strip.get_f32("actstart"),
+            end_frame: strip.get_f32("actend"),
+            scale: strip.get_f32("scale").max(f32::EPSILON),
+            repeat: strip.get_f32("repeat").max(f32::EPSILON),
+            frames_per_second: scene.frames_per_second,
+        }
+    }
+
+    /// Returns the duration of the clip, in seconds
+    fn duration(&self) -> f32 {
+        let length = self.end_frame - self.start_frame;
+        if length > 0.0 {
+            length * self.repeat * self.scale / self.frames_per_second
+        } else {
+            0.0
+        }
+    }
+
+    /// Returns the times of the clip, in seconds, at which a frame of the action is played
+    fn times(&self, frame: f32) -> Vec<f32> {
+        let length = self.end_frame - self.start_frame;
+        if !(self.start_frame..=self.end_frame).contains(&frame) {
+            return Vec::new();
+        }
+        if length <= 0.0 {
+            return vec![0.0];
+        }
+        (0..self.repeat.ceil() as usize)
+            .map(|repetition| repetition as f32 * length + frame - self.start_frame)
+            .filter(|offset| *offset <= length * self.repeat)
+            .map(|offset| offset * self.scale / self.frames_per_second)
+            .collect()
+    }
+}
+
+/// Takes a Blend::Instance action and converts its object and pose bone transform F-curves to an
+/// AnimationClip, and its other F-curves (e.g. shape key values) to a BlenderPropertyClip. Every
+/// object the action is assigned to (directly or through a NLA strip) is
+/// animated, targeted by the names of the object and its parents starting with the top most one,
+/// or with the parent whose ID name is "root" if there is one, so the clip can be played by the
+/// AnimationPlayer of the spawned root object. Objects that are not below "root" are left out. Bones are
+/// targeted by the names of their parent bones below their armature object. Actions that are not
+/// assigned to any object animate the first armature with all of their bones, or the entity of the
+/// AnimationPlayer itself. If the settings name a root motion bone animated by the action, its
+/// horizontal translation and yaw are stripped and returned as a BlenderRootMotion.
+pub(crate) fn instance_to_animation_clip(
+    instance: Instance,
+    blend: &Blend,
+    settings: &BlenderAnimationSettings,
+    conversion: &Conversion,
+    _blend_version: (u8, u8, u8),
+    root: Option<&str>,
+) -> anyhow::Result<(
+    AnimationClip,
+    BlenderPropertyClip,
+    Option<BlenderRootMotion>,
+)> {
+    // Don't process instances of types other than action
+    if instance.type_name != "bAction" {
+        return Err(anyhow::Error::new(BevyBlenderError::InvalidInstanceType {
+            expected: String::from("bAction"),
+            found: instance.type_name,
+        }));
+    }
+
+    let name = instance.get("id").get_string("name");
+    let fcurves = fcurve::instance_to_fcurves(&instance);
+    let scene = scene::blend_scene_timing(blend);
+    let users = clip_users(blend, &name, &fcurves);
+
+    Ok(build_clips(
+        &instance,
+        &fcurves,
+        users,
+        &name[2..],
+        ActionTiming::from_fcurves(&fcurves, &scene, settings.clip_to_scene_range),
+        settings,
+        conversion,
+        root,
+    ))
+}
+
+/// Returns the objects animated by the clip of the action named "name": its users, or the first
+/// armature with all of the bones it animates
+fn clip_users<'a>(
+    blend: &'a Blend,
+    name: &str,
+    fcurves: &[FCurve],
+) -> Vec<(Instance<'a>, ActionUse<'a>)> {
+    let mut users = action_users(blend, name);
+
+    // Actions of the action library of a character are usually not assigned to its armature
+    if users.is_empty() {
+        users.extend(matching_armature(blend, fcurves).map(|armature| {
+            (
+                armature,
+                ActionUse {
+                    action: name.to_string(),
+                    slot: None,
+                    data: AnimatedData::Object,
+                },
+            )
+        }));
+    }
+    users
+}
+
+/// Returns the ID names of the objects with a parent that the clip of a Blend::Instance action can
+/// be played from, the objects it animates and their parents, which get a clip of their own
+pub(crate) fn action_clip_roots(action: &Instance, blend: &Blend) -> Vec<String> {
+    let name = action.get("id").get_string("name");
+    let fcurves = fcurve::instance_to_fcurves(action);
+    let users: Vec<Instance> = clip_users(blend, &name, &fcurves)
+        .into_iter()
+        .map(|(user, _)| user)
+        .collect();
+    clip_roots(&users)
+}
+
+/// Returns the ID names of "objects" and of their parents, except for the top most parents
+pub(crate) fn clip_roots(objects: &[Instance]) -> Vec<String> {
+    let mut roots: Vec<String> = Vec::new();
+    for obj in objects {
+        let mut current = obj.clone();
+        while current.is_valid("parent") {
+            let name = current.get("id").get_string("name");
+            if !roots.contains(&name) {
+                roots.push(name);
+            }
+            current = current.get("parent");
+        }
+    }
+    roots
+}
+
+/// Returns the label of the clip "label" played from the spawned object "root": objects with a
+/// parent play a clip of their own, e.g. "ACDoorOpen/OBDoor", which targets the entities below
+/// them
+pub(crate) fn rooted_clip_label(label: &str, root: &Instance) -> String {
+    if root.is_valid("parent") {
+        format!("{}/{}", label, root.get("id").get_string("name"))
+    } else {
+        label.to_string()
+    }
+}
+
+/// Takes a Blend::Instance NLA strip of an object and converts the F-curves of its action to an
+/// AnimationClip and a BlenderPropertyClip animating the object. The clip plays the frame range of the action
+/// used by the strip, scaled and repeated like the strip, and starts at the start of the strip.
+/// Like actions, the motion of the root motion bone is returned separately if there is one, and
+/// the object is targeted from "root" if there is one.
+pub(crate) fn nla_strip_to_animation_clip(
+    strip: &Instance,
+    obj: &Instance,
+    blend: &Blend,
+    settings: &BlenderAnimationSettings,
+    conversion: &Conversion,
+    root: Option<&str>,
+) -> (
+    AnimationClip,
+    BlenderPropertyClip,
+    Option<BlenderRootMotion>,
+) {
+    let action = strip.get("act");
+    let fcurves = fcurve::instance_to_fcurves(&action);
+    build_clips(
+        &action,
+        &fcurves,
+        vec![(
+            obj.clone(),
+            ActionUse {
+                action: action.get("id").get_string("name"),
+                slot: slot_handle(strip, "action_slot_handle"),
+                data: AnimatedData::Object,
+            },
+        )],
+        &object::object_name(obj),
+        ActionTiming::from_nla_strip(strip, &scene::blend_scene_timing(blend)),
+        settings,
+        conversion,
+        root,
+    )
+}
+
+/// Creates the AnimationClip and the BlenderPropertyClip of a Blend::Instance action, with its
+/// F-curves and pose markers, played with "timing" by its users, the objects it is used by and how
+/// they use it. Without users, the entity of the AnimationPlayer named "name" is animated. Users
+/// are targeted from the object whose ID name is "root" if there is one.
+#[allow(clippy::too_many_arguments)]
+fn build_clips(
+    action: &Instance,
+    fcurves: &[FCurve],
+    users: Vec<(Instance, ActionUse)>,
+    name: &str,
+    timing: ActionTiming,
+    settings: &BlenderAnimationSettings,
+    conversion: &Conversion,
+    root: Option<&str>,
+) -> (
+    AnimationClip,
+    BlenderPropertyClip,
+    Option<BlenderRootMotion>,
+) {
+    let mut clip = AnimationClip::default();
+    let mut property_clip = BlenderPropertyClip::default();
+    let mut root_motion = None;
+    property_clip.extend_duration(timing.duration());
+
+    // Pose markers are reached every time their frame is played
+    for marker in marker::instance_to_markers(action) {
+        for time in timing.times(marker.frame as f32) {
+            property_clip.add_marker(BlenderMarker {
+                time,
+                ..marker.clone()
+            });
+        }
+    }
+
+    if users.is_empty() {
+        let sampler = ClipSampler {
+            fcurves,
+            timing,
+            sampling: settings.sampling,
+            conversion,
+        };
+        let path = vec![Name::new(name.to_string())];
+        add_transform_curves(
+            &mut clip,
+            EntityPath {
+                parts: path.clone(),
+            },
+            &sampler,
+            "",
+            TransformChannels::default(),
+            Mat4::IDENTITY,
+            None,
+        );
+        add_property_curves(&mut property_clip, path, &sampler, &AnimatedData::Object);
+    }
+
+    for (user, ActionUse { slot, data, .. }) in users {
+        // Every slot of an action animates its own users
+        let slot_fcurves: Vec<FCurve> = fcurves
+            .iter()
+            .filter(|fcurve| {
+                slot.is_none() || fcurve.slot_handle.is_none() || fcurve.slot_handle == slot
+            })
+            .cloned()
+            .collect();
+        let sampler = ClipSampler {
+            fcurves: &slot_fcurves,
+            timing,
+            sampling: settings.sampling,
+            conversion,
+        };
+
+        let Some(path) = object::object_path(&user, root) else {
+            continue;
+        };
+        let path: Vec<Name> = path.into_iter().map(Name::new).collect();
+        add_property_curves(&mut property_clip, path.clone(), &sampler, &data);
+
+        // Only actions of objects animate their transform
+        if !matches!(data, AnimatedData::Object) {
+            continue;
+        }
+
+        let parent = user.is_valid("parent").then(|| {
+            (
+                object::get_world_matrix(&user.get("parent")),
+                object::get_matrix(&user, "parentinv"),
+            )
+        });
+        let is_root = root == Some(user.get("id").get_string("name").as_str());
+
+        add_transform_curves(
+            &mut clip,
+            EntityPath {
+                parts: path.clone(),
+            },
+            &sampler,
+            "",
+            TransformChannels::from_object(&user),
+            object_base(parent, is_root),
+            None,
+        );
+
+        if user.get_i16("type") != armature::OB_ARMATURE || !user.is_valid("data") {
+            continue;
+        }
+
+        // Pose bones are keyed relative to their rest pose, which is relative to their parent
+        for bone in armature::bones(&user.get("data")) {
+            let bone_name = bone.get_string("name");
+            let mut bone_path = path.clone();
+            bone_path.extend(bone_chain(&bone).into_iter().map(Name::new));
+
+            add_transform_curves(
+                &mut clip,
+                EntityPath { parts: bone_path },
+                &sampler,
+                pose_bone_path(&bone_name).as_str(),
+                armature::pose_channels(&user, &bone_name).unwrap_or_default(),
+                armature::bone_rest_matrix(&bone),
+                (settings.root_motion_bone.as_ref() == Some(&bone_name))
+                    .then_some(&mut root_motion),
+            );
+        }
+    }
+
+    (clip, property_clip, root_motion)
+}
+
+/// Returns the matrix the transform channels of an object are relative to, from the world matrix
+/// of its parent and its parent inverse matrix if it has a parent. Objects are placed relative to
+/// their parent's world matrix at the time of parenting, and the spawned root object, whose entity
+/// is not the child of its parent's, relative to the world matrix of its parent too.
+fn object_base(parent: Option<(Mat4, Mat4)>, is_root: bool) -> Mat4 {
+    match parent {
+        Some((parent_matrix, parent_inverse)) if is_root => parent_matrix * parent_inverse,
+        Some((_, parent_inverse)) => parent_inverse,
+        None => Mat4::IDENTITY,
+    }
+}
+
+/// Returns the channel animated by a F-curve of an action animating "data" that is not a
+/// transform channel, if it is supported, along with the factor converting its values
+fn property_channel(
+    fcurve: &FCurve,
+    data: &AnimatedData,
+    conversion: &Conversion,
+) -> Option<(BlenderPropertyChannel, f32)> {
+    let index = fcurve.array_index;
+    let channel = match (data, fcurve.owner(), fcurve.property()) {
+        (AnimatedData::Object, "", "hide_render") => BlenderPropertyChannel::Hidden,
+        (AnimatedData::ShapeKeys, owner, "value") => {
+            BlenderPropertyChannel::MorphWeight(quoted_name(owner, "key_blocks")?)
+        }
+        (AnimatedData::Light(_), "", "color") => BlenderPropertyChannel::LightColor(index),
+        (AnimatedData::Light(light), "", "energy") => {
+            return Some((
+                BlenderPropertyChannel::LightIntensity,
+                light::intensity_factor(light),
+            ))
+        }
+        (AnimatedData::Camera, "", "lens") => BlenderPropertyChannel::CameraFocalLength,
+        // Lengths are scaled like the rest of the scene
+        (AnimatedData::Camera, "", "ortho_scale") => {
+            return Some((
+                BlenderPropertyChannel::CameraOrthographicScale,
+                conversion.length(1.0),
+            ))
+        }
+        (AnimatedData::Camera, "", "clip_start") => {
+            return Some((BlenderPropertyChannel::CameraNear, conversion.length(1.0)))
+        }
+        (AnimatedData::Camera, "", "clip_end") => {
+            return Some((BlenderPropertyChannel::CameraFar, conversion.length(1.0)))
+        }
+        (AnimatedData::Material, "", "diffuse_color") => {
+            BlenderPropertyChannel::MaterialBaseColor(index)
+        }
+        (AnimatedData::Material, "", "metallic") => BlenderPropertyChannel::MaterialMetallic,
+        (AnimatedData::Material, "", "roughness") => BlenderPropertyChannel::MaterialRoughness,
+        (AnimatedData::Material, "", "specular_intensity") => {
+            BlenderPropertyChannel::MaterialReflectance
+        }
+        (AnimatedData::MaterialNodes(tree), owner, "default_value") => {
+            principled_channel(tree, owner, index)?
+        }
+        _ => return None,
+    };
+    Some((channel, 1.0))
+}
+
+/// Returns the material channel of an input socket of the Principled BSDF node of a node tree,
+/// from its RNA path, e.g. nodes["Principled BSDF"].inputs[0]
+fn principled_channel(
+    tree: &Instance,
+    owner: &str,
+    index: usize,
+) -> Option<BlenderPropertyChannel> {
+    let (node_path, input) = owner.rsplit_once(".inputs[")?;
+    let node_name = quoted_name(node_path, "nodes")?;
+    let input: usize = input.strip_suffix(']')?.parse().ok()?;
+
+    let node = tree
+        .get_iter("nodes")
+        .find(|node| node.get_string("name") == node_name)
+        .filter(|node| node.get_string("idname") == "ShaderNodeBsdfPrincipled")?;
+    let socket = node.get_iter("inputs").nth(input)?;
+
+    // Sockets were renamed in Blender 4.0
+    Some(match socket.get_string("name").as_str() {
+        "Base Color" => BlenderPropertyChannel::MaterialBaseColor(index),
+        "Alpha" => BlenderPropertyChannel::MaterialBaseColor(3),
+        "Metallic" => BlenderPropertyChannel::MaterialMetallic,
+        "Roughness" => BlenderPropertyChannel::MaterialRoughness,
+        "Specular" | "Specular IOR Level" => BlenderPropertyChannel::MaterialReflectance,
+        "Emission" | "Emission Color" => BlenderPropertyChannel::MaterialEmissive(index),
+        _ => return None,
+    })
+}
+
+/// Returns the name in an RNA path like collection["Name"], if the collection is "collection"
+pub(crate) fn quoted_name(path: &str, collection: &str) -> Option<String> {
+    let quoted = path
+        .strip_prefix(collection)?
+        .strip_prefix("[\"")?
+        .strip_suffix("\"]")?;
+    Some(quoted.replace("\\\"", "\"").replace("\\\\", "\\"))
+}
+
+/// Adds a curve for every F-curve of a property supported by BlenderPropertyChannel, animating
+/// the entity at "path"
+fn add_property_curves(
+    property_clip: &mut BlenderPropertyClip,
+    path: Vec<Name>,
+    sampler: &ClipSampler,
+    data: &AnimatedData,
+) {
+    for fcurve in sampler.fcurves {
+        let Some((channel, factor)) = property_channel(fcurve, data, sampler.conversion) else {
+            continue;
+        };
+        let samples = sampler.samples(&[fcurve]);
+        property_clip.add_curve(BlenderPropertyCurve {
+            path: EntityPath {
+                parts: path.clone(),
+            },
+            channel,
+            keyframe_timestamps: samples.iter().map(|(time, _)| *time).collect(),
+            values: samples
+                .iter()
+                .map(|(_, frame)| fcurve.evaluate(*frame) * factor)
+                .collect(),
+        });
+    }
+}
+
+/// Returns the RNA path of a pose bone, e.g. pose.bones["Arm"]
+pub(crate) fn pose_bone_path(bone_name: &str) -> String {
+    format!(
+        "pose.bones[\"{}\"]",
+        bone_name.replace('\\', "\\\\").replace('"', "\\\"")
+    )
+}
+
+/// Returns the names of a bone and its parent bones, starting with the root bone
+fn bone_chain(bone: &Instance) -> Vec<String> {
+    let mut chain = vec![bone.get_string("name")];
+    let mut current = bone.clone();
+    while current.is_valid("parent") {
+        current = current.get("parent");
+        chain.push(current.get_string("name"));
+    }
+    chain.reverse();
+    chain
+}
+
+/// Returns the first armature object that has all of the bones animated by the F-curves, if any
+/// of them animates a pose bone
+pub(crate) fn matching_armature<'a>(blend: &'a Blend, fcurves: &[FCurve]) -> Option<Instance<'a>> {
+    let owners: Vec<&str> = fcurves
+        .iter()
+        .map(|fcurve| fcurve.owner())
+        .filter(|owner| owner.starts_with("pose.bones["))
+        .collect();
+    if owners.is_empty() {
+        return None;
+    }
+
+    blend.get_by_code(*b"OB").into_iter().find(|obj| {
+        if obj.get_i16("type") != armature::OB_ARMATURE || !obj.is_valid("data") {
+            return false;
+        }
+        let bone_paths: Vec<String> = armature::bones(&obj.get("data"))
+            .iter()
+            .map(|bone| pose_bone_path(&bone.get_string("name")))
+            .collect();
+        owners
+            .iter()
+            .all(|owner| bone_paths.iter().any(|path| path == owner))
+    })
+}
+
+/// The F-curves of an action, and how they are sampled into the keyframes of a clip
+struct ClipSampler<'a> {
+    fcurves: &'a [FCurve],
+    timing: ActionTiming,
+    sampling: BlenderAnimationSampling,
+    conversion: &'a Conversion,
+}
+
+// Limits how finely the space between two keyframes is divided to match an F-curve
+const MAX_SUBDIVISIONS: u32 = 8;
+
+impl<'a> ClipSampler<'a> {
+    /// Returns the F-curves animating "property" of "owner" (see FCurve::owner and
+    /// FCurve::property)
+    fn curves(&self, owner: &str, property: impl Fn(&str) -> bool) -> Vec<&'a FCurve> {
+        self.fcurves
+            .iter()
+            .filter(|fcurve| fcurve.owner() == owner && property(fcurve.property()))
+            .collect()
+    }
+
+    /// Returns the frames of the action at which the F-curves are sampled, within the frames
+    /// played by the clip
+    fn frames(&self, fcurves: &[&FCurve]) -> Vec<f32> {
+        let ActionTiming {
+            start_frame: first,
+            end_frame: last,
+            ..
+        } = self.timing;
+        let mut keyframes: Vec<f32> = fcurves
+            .iter()
+            .flat_map(|fcurve| fcurve.keyframes.iter().map(|keyframe| keyframe.frame))
+            .filter(|frame| *frame > first && *frame < last)
+            .chain([first, last])
+            .collect();
+        keyframes.sort_by(f32::total_cmp);
+        keyframes.dedup();
+
+        match self.sampling {
+            BlenderAnimationSampling::Keyframes { tolerance } => {
+                let mut frames = vec![first];
+                for pair in keyframes.windows(2) {
+                    subdivide(fcurves, pair[0], pair[1], tolerance, 0, &mut frames);
+                }
+                frames
+            }
+            BlenderAnimationSampling::Resample { samples_per_second } => {
+                let step = self.timing.frames_per_second
+                    / (samples_per_second * self.timing.scale).max(f32::EPSILON);
+                let count = ((last - first) / step).ceil() as usize;
+                (0..=count)
+                    .map(|i| (first + i as f32 * step).min(last))
+                    .collect()
+            }
+        }
+    }
+
+    /// Returns the times of the clip, in seconds, at which the F-curves are sampled, along with
+    /// the frame of the action they are sampled at
+    fn samples(&self, fcurves: &[&FCurve]) -> Vec<(f32, f32)> {
+        let ActionTiming {
+            start_frame,
+            end_frame,
+            scale,
+            repeat,
+            frames_per_second,
+        } = self.timing;
+        let frames = self.frames(fcurves);
+        let length = end_frame - start_frame;
+        let time = |offset: f32| offset * scale / frames_per_second;
+        if length <= 0.0 {
+            return frames.into_iter().map(|frame| (0.0, frame)).collect();
+        }
+
+        let end = length * repeat;
+        let mut samples: Vec<(f32, f32)> = Vec::new();
+        for repetition in 0..repeat.ceil() as usize {
+            let repetition_start = repetition as f32 * length;
+            for &frame in &frames {
+                // The first frame of a repetition is sampled as the last frame of the previous one
+                if repetition > 0 && frame == start_frame {
+                    continue;
+                }
+                let offset = repetition_start + frame - start_frame;
+                if offset > end {
+                    // The last repetition stops in the middle of the action
+                    if samples.last().map_or(true, |(last, _)| *last < time(end)) {
+                        samples.push((time(end), start_frame + end - repetition_start));
+                    }
+                    break;
+                }
+                samples.push((time(offset), frame));
+            }
+        }
+        samples
+    }
+}
+
+/// Adds the frames needed for the linear interpolation from frame "a" to frame "b" to match the
+/// F-curves, followed by "b"
+fn subdivide(
+    fcurves: &[&FCurve],
+    a: f32,
+    b: f32,
+    tolerance: f32,
+    depth: u32,
+    frames: &mut Vec<f32>,
+) {
+    if depth < MAX_SUBDIVISIONS
+        && !fcurves
+            .iter()
+            .all(|fcurve| fcurve.is_linear(a, b, tolerance))
+    {
+        let middle = (a + b) * 0.5;
+        subdivide(fcurves, a, middle, tolerance, depth + 1, frames);
+        subdivide(fcurves, middle, b, tolerance, depth + 1, frames);
+    } else {
+        frames.push(b);
+    }
+}
+
+/// Adds translation, rotation and scale curves for the entity at "path", sampled from the
+/// transform F-curves of "owner" (see FCurve::owner). Channels without F-curves keep their "rest"
+/// value. The channels are relative to the "base" matrix, which is the parent inverse matrix of
+/// objects and the rest matrix of bones. If "root_motion" is given, the horizontal translation
+/// and the yaw of the entity are moved to it.
+fn add_transform_curves(
+    clip: &mut AnimationClip,
+    path: EntityPath,
+    sampler: &ClipSampler,
+    owner: &str,
+    rest: TransformChannels,
+    base: Mat4,
+    root_motion: Option<&mut Option<BlenderRootMotion>>,
+) {
+    let fcurves = sampler.curves(owner, TransformChannels::has_property);
+    if fcurves.is_empty() {
+        return;
+    }
+    let samples = sampler.samples(&fcurves);
+
+    let mut translations = Vec::with_capacity(samples.len());
+    let mut rotations = Vec::with_capacity(samples.len());
+    let mut scales = Vec::with_capacity(samples.len());
+    for (_, frame) in &samples {
+        let channels = evaluate_channels(&fcurves, rest, *frame);
+        let matrix = sampler.conversion.matrix(&(base * channels.matrix()));
+        let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+        translations.push(translation);
+        rotations.push(rotation);
+        scales.push(scale);
+    }
+
+    let timestamps: Vec<f32> = samples.iter().map(|(time, _)| *time).collect();
+    if let Some(root_motion) = root_motion {
+        *root_motion = Some(root_motion::extract_root_motion(
+            &timestamps,
+            &mut translations,
+            &mut rotations,
+        ));
+    }
+    for keyframes in [
+        Keyframes::Translation(translations),
+        Keyframes::Rotation(rotations),
+        Keyframes::Scale(scales),
+    ] {
+        clip.add_curve_to_path(
+            path.clone(),
+            VariableCurve {
+                keyframe_timestamps: timestamps.clone(),
+                keyframes,
+            },
+        );
+    }
+}
+
+/// Creates the AnimationPlayer and the BlenderPropertyPlayer of a spawned root object. If the
+/// object (or else one of its datablocks) has an action, it is played on repeat.
+pub(crate) fn animation_player(
+    asset_server: &AssetServer,
+    blender_file: &str,
+    obj: &Instance,
+) -> (AnimationPlayer, BlenderPropertyPlayer) {
+    let mut player = AnimationPlayer::default();
+    let mut property_player = BlenderPropertyPlayer::default();
+    if let Some(ActionUse { action, .. }) = assigned_actions(obj).into_iter().next() {
+        let label = rooted_clip_label(&action, obj);
+        player
+            .play(asset_server.load(format!("{}#{}", blender_file, label).as_str()))
+            .repeat();
+        property_player.clip = Some(
+            asset_server.load(format!("{}#{}", blender_file, property_clip_label(&label)).as_str()),
+        );
+        property_player.repeat = true;
+    }
+    (player, property_player)
+}
+
+/// Returns the label of the BlenderPropertyClip of an action or NLA strip, from the label of its
+/// AnimationClip
+pub(crate) fn property_clip_label(label: &str) -> String {
+    format!("{}/Properties", label)
+}
+
+/// Creates the BlenderAnimationClips of a spawned "root" object, from the clips that animate
+/// "objects", the root object and its spawned children
+pub(crate) fn animation_clips(
+    asset_server: &AssetServer,
+    blender_file: &str,
+    blend: &Blend,
+    root: &Instance,
+    objects: &[Instance],
+) -> BlenderAnimationClips {
+    let object_names: Vec<String> = objects
+        .iter()
+        .map(|obj| obj.get("id").get_string("name"))
+        .collect();
+    let mut clips = BlenderAnimationClips::default();
+    let mut insert = |name: String, label: &str| {
+        clips.clips.insert(
+            name.clone(),
+            asset_server.load(format!("{}#{}", blender_file, label).as_str()),
+        );
+        clips.properties.insert(
+            name,
+            asset_server.load(format!("{}#{}", blender_file, property_clip_label(label)).as_str()),
+        );
+    };
+
+    // Actions that are not used by any object, like the action library of a character, belong to
+    // the armature they animate. Object actions animate the entity of the AnimationPlayer itself.
+    for action in blend.get_by_code(*b"AC") {
+        let name = action.get("id").get_string("name");
+        if name.starts_with("AC_") || !action_users(blend, &name).is_empty() {
+            continue;
+        }
+        let fcurves = fcurve::instance_to_fcurves(&action);
+        match matching_armature(blend, &fcurves) {
+            Some(armature) if object_names.contains(&armature.get("id").get_string("name")) => {
+                insert(name[2..].to_string(), &rooted_clip_label(&name, root))
+            }
+            Some(_) => {}
+            None if !fcurves
+                .iter()
+                .any(|fcurve| fcurve.owner().starts_with("pose.bones[")) =>
+            {
+                insert(name[2..].to_string(), &name)
+            }
+            None => {}
+        }
+    }
+
+    for obj in objects {
+        for ActionUse { action, .. } in object_actions(obj) {
+            if !action.starts_with("AC_") {
+                insert(action[2..].to_string(), &rooted_clip_label(&action, root));
+            }
+        }
+        // Strips are named after their action by default, and then replace it
+        for strip in nla_strips(obj) {
+            insert(
+                strip.get_string("name"),
+                &rooted_clip_label(&nla_strip_label(obj, &strip), root),
+            );
+        }
+    }
+
+    clips
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fcurve::{Easing, Interpolation, Keyframe};
+    use bevy_math::Vec2;
+
+    // A curve of a location axis of an object, keyed to "value" at frames 1 and 10
+    fn location(array_index: usize, value: f32) -> FCurve {
+        let keyframe = |frame: f32| Keyframe {
+            frame,
+            value,
+            left_handle: Vec2::new(frame - 1.0, value),
+            right_handle: Vec2::new(frame + 1.0, value),
+            interpolation: Interpolation::Linear,
+            easing: Easing::Auto,
+            back: 1.70158,
+            amplitude: 0.8,
+            period: 4.1,
+        };
+        FCurve {
+            rna_path: String::from("location"),
+            array_index,
+            keyframes: vec![keyframe(1.0), keyframe(10.0)],
+            linear_extrapolation: false,
+            discrete: false,
+            integer: false,
+            cycles: None,
+            ignored_modifiers: 0,
+            slot_handle: None,
+        }
+    }
+
+    // Returns the first keyframe of the transform curves of "clip" at "path", as a matrix
+    fn first_keyframe(clip: &AnimationClip, path: &EntityPath) -> Mat4 {
+        let (mut translation, mut rotation, mut scale) = (Vec3::ZERO, Quat::IDENTITY, Vec3::ONE);
+        for curve in clip.get_curves_by_path(path).unwrap() {
+            match &curve.keyframes {
+                Keyframes::Translation(values) => translation = values[0],
+                Keyframes::Rotation(values) => rotation = values[0],
+                Keyframes::Scale(values) => scale = values[0],
+            }
+        }
+        Mat4::from_scale_rotation_translation(scale, rotation, translation)
+    }
+
+    #[test]
+    fn parented_objects_are_animated_where_they_are_spawned() {
+        let conversion = Conversion::default();
+        let fcurves = [location(0, 1.0), location(1, 2.0), location(2, 3.0)];
+        let sampler = ClipSampler {
+            fcurves: &fcurves,
+            timing: ActionTiming {
+                start_frame: 1.0,
+                end_frame: 10.0,
+                scale: 1.0,
+                repeat: 1.0,
+                frames_per_second: 24.0,
+            },
+            sampling: BlenderAnimationSampling::Keyframes { tolerance: 0.001 },
+            conversion: &conversion,
+        };
+
+        // The parent was moved and turned after the object was parented to it
+        let parent_matrix =
+            Mat4::from_rotation_translation(Quat::from_rotation_z(1.0), Vec3::new(4.0, 5.0, 6.0));
+        let parent_inverse = Mat4::from_translation(Vec3::new(0.0, 0.0, -2.0));
+        let object_matrix =
+            parent_matrix * parent_inverse * Mat4::from_translation(Vec3::new(1.0, 2.0, 3.0));
+
+        // The spawned root object is placed at its world matrix, and a spawned child relative to
+        // the entity of its parent, placed at the world matrix of the parent
+        for (is_root, expected) in [
+            (true, conversion.matrix(&object_matrix)),
+            (
+                false,
+                conversion.matrix(&parent_matrix).inverse() * conversion.matrix(&object_matrix),
+            ),
+        ] {
+            let mut clip = AnimationClip::default();
+            let path = EntityPath {
+                parts: vec![Name::new("Object")],
+            };
+            add_transform_curves(
+                &mut clip,
+                path.clone(),
+                &sampler,
+                "",
+                TransformChannels::default(),
+                object_base(Some((parent_matrix, parent_inverse)), is_root),
+                None,
+            );
+            let keyframe = first_keyframe(&clip, &path);
+            assert!(
+                keyframe.abs_diff_eq(expected, 1e-4),
+                "root {is_root}: {keyframe} != {expected}"
+            );
+        }
+    }
+}
@@ -0,0 +1,425 @@
+use bevy_asset::{AssetServer, Handle};
+use bevy_core::Name;
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    system::{Commands, Query},
+};
+use bevy_hierarchy::{BuildChildren, ChildBuilder, Children, HierarchyQueryExt, Parent};
+use bevy_math::{Mat4, Vec3};
+use bevy_render::{
+    mesh::{
+        skinning::{SkinnedMesh, SkinnedMeshInverseBindposes},
+        Mesh, VertexAttributeValues,
+    },
+    prelude::SpatialBundle,
+};
+use bevy_transform::prelude::Transform;
+use blend::{Blend, Instance};
+use std::collections::HashMap;
+
+use crate::{
+    animation::{self, TransformChannels},
+    coordinates::Conversion,
+    fcurve::FCurve,
+    ik, object, parenting,
+};
+
+// Object.type value of armatures (DNA_object_types.h)
+pub(crate) const OB_ARMATURE: i16 = 25;
+
+/// A mesh entity skinned by an armature, whose joint entities are looked up by name once the
+/// armature is spawned
+#[derive(Component)]
+pub(crate) struct PendingSkinnedMesh {
+    /// Name of the armature object
+    armature: String,
+    /// Names of the bones, in joint index order. The last joint is the mesh itself.
+    joints: Vec<String>,
+    inverse_bindposes: Handle<SkinnedMeshInverseBindposes>,
+}
+
+/// Returns the bones of a Blend::Instance armature, parents before their children. The position
+/// of a bone in this list is its joint index.
+pub(crate) fn bones<'a>(armature: &Instance<'a>) -> Vec<Instance<'a>> {
+    fn add_bones<'a>(bone: Instance<'a>, bones: &mut Vec<Instance<'a>>) {
+        let children = bone
+            .is_valid("childbase")
+            .then(|| bone.get_iter("childbase"));
+        bones.push(bone);
+        for child in children.into_iter().flatten() {
+            add_bones(child, bones);
+        }
+    }
+
+    let mut bones = Vec::new();
+    if armature.is_valid("bonebase") {
+        for bone in armature.get_iter("bonebase") {
+            add_bones(bone, &mut bones);
+        }
+    }
+    bones
+}
+
+/// Returns the armature object deforming a mesh object, either through an Armature modifier or an
+/// armature deform parent
+pub(crate) fn object_armature<'a>(obj: &Instance<'a>) -> Option<Instance<'a>> {
+    if obj.is_valid("modifiers") {
+        let armature = obj
+            .get_iter("modifiers")
+            .filter(|modifier| modifier.type_name == "ArmatureModifierData")
+            .find(|modifier| modifier.is_valid("object"))
+            .map(|modifier| modifier.get("object"));
+        if armature.is_some() {
+            return armature;
+        }
+    }
+
+    if obj.is_valid("parent") && parenting::parent_type(obj) == parenting::PARSKEL {
+        let parent = obj.get("parent");
+        if parent.get_i16("type") == OB_ARMATURE {
+            return Some(parent);
+        }
+    }
+
+    None
+}
+
+/// Returns the first object using the mesh named "mesh_name" that is deformed by an armature,
+/// along with its armature object
+pub(crate) fn skinned_mesh_object<'a>(
+    blend: &'a Blend,
+    mesh_name: &str,
+) -> Option<(Instance<'a>, Instance<'a>)> {
+    blend.get_by_code(*b"OB").into_iter().find_map(|obj| {
+        if obj.get_i16("type") != object::OB_MESH
+            || !obj.is_valid("data")
+            || obj.get("data").get("id").get_string("name") != mesh_name
+        {
+            return None;
+        }
+        object_armature(&obj).map(|armature| (obj, armature))
+    })
+}
+
+/// Adds the joint indices and weights of the vertices of a mesh deformed by "armature". Every
+/// vertex keeps its four largest bone weights, normalized. Vertex groups that are not bones are
+/// ignored, and vertices without bone weights are assigned to the last joint, the mesh itself.
+pub(crate) fn insert_joint_attributes(
+    bevy_mesh: &mut Mesh,
+    mesh: &Instance,
+    obj: &Instance,
+    armature: &Instance,
+) {
+    let vertex_count = mesh.get_i32("totvert").max(0) as usize;
+    let bone_names: Vec<String> = bones(&armature.get("data"))
+        .iter()
+        .map(|bone| bone.get_string("name"))
+        .collect();
+
+    // Vertex group names are stored in the mesh since Blender 3.0, and in the object before
+    let group_list = if mesh.is_valid("vertex_group_names") {
+        mesh.get_iter("vertex_group_names")
+    } else if obj.is_valid("defbase") {
+        obj.get_iter("defbase")
+    } else {
+        return;
+    };
+    let group_joints: Vec<Option<u16>> = group_list
+        .map(|group| {
+            let name = group.get_string("name");
+            bone_names
+                .iter()
+                .position(|bone| *bone == name)
+                .map(|index| index as u16)
+        })
+        .collect();
+
+    let mut joint_indices = vec![[bone_names.len() as u16, 0, 0, 0]; vertex_count];
+    let mut joint_weights = vec![[1.0, 0.0, 0.0, 0.0]; vertex_count];
+
+    if mesh.is_valid("dvert") {
+        for (vertex, dvert) in mesh.get_iter("dvert").take(vertex_count).enumerate() {
+            if !dvert.is_valid("dw") {
+                continue;
+            }
+
+            let mut weights: Vec<(u16, f32)> = dvert
+                .get_iter("dw")
+                .take(dvert.get_i32("totweight").max(0) as usize)
+                .filter_map(|dw| {
+                    let joint = group_joints
+                        .get(dw.get_i32("def_nr").max(0) as usize)
+                        .copied()
+                        .flatten()?;
+                    Some((joint, dw.get_f32("weight")))
+                })
+                .filter(|(_, weight)| *weight > 0.0)
+                .collect();
+            weights.sort_by(|a, b| b.1.total_cmp(&a.1));
+            weights.truncate(4);
+
+            let total: f32 = weights.iter().map(|(_, weight)| weight).sum();
+            if total <= 0.0 {
+                continue;
+            }
+            for (i, (joint, weight)) in weights.into_iter().enumerate() {
+                joint_indices[vertex][i] = joint;
+                joint_weights[vertex][i] = weight / total;
+            }
+        }
+    }
+
+    bevy_mesh.insert_attribute(
+        Mesh::ATTRIBUTE_JOINT_INDEX,
+        VertexAttributeValues::Uint16x4(joint_indices),
+    );
+    bevy_mesh.insert_attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT, joint_weights);
+}
+
+/// Takes a Blend::Instance mesh object and its armature object and computes the inverse bind
+/// poses of its joints, which bring the vertices of the mesh into the space of each bone at rest
+pub(crate) fn instance_to_inverse_bindposes(
+    obj: &Instance,
+    armature: &Instance,
+    conversion: &Conversion,
+) -> SkinnedMeshInverseBindposes {
+    let mesh_matrix = object::get_world_matrix(obj);
+    let armature_matrix = object::get_world_matrix(armature);
+
+    let mut inverse_bindposes: Vec<Mat4> = bones(&armature.get("data"))
+        .iter()
+        .map(|bone| {
+            let bone_matrix = armature_matrix * object::get_matrix(bone, "arm_mat");
+            conversion.matrix(&(bone_matrix.inverse() * mesh_matrix))
+        })
+        .collect();
+    // The last joint is the mesh itself, for vertices without bone weights
+    inverse_bindposes.push(Mat4::IDENTITY);
+
+    inverse_bindposes.into()
+}
+
+/// Returns the label of the inverse bind poses of a skinned mesh object, e.g.
+/// "OBRobot/InverseBindposes"
+pub(crate) fn inverse_bindposes_label(obj: &Instance) -> String {
+    format!("{}/InverseBindposes", obj.get("id").get_string("name"))
+}
+
+/// Creates the PendingSkinnedMesh of a mesh object deformed by "armature"
+pub(crate) fn pending_skinned_mesh(
+    asset_server: &AssetServer,
+    blender_file: &str,
+    obj: &Instance,
+    armature: &Instance,
+) -> PendingSkinnedMesh {
+    PendingSkinnedMesh {
+        armature: object::object_name(armature),
+        joints: bones(&armature.get("data"))
+            .iter()
+            .map(|bone| bone.get_string("name"))
+            .collect(),
+        inverse_bindposes: asset_server
+            .load(format!("{}#{}", blender_file, inverse_bindposes_label(obj)).as_str()),
+    }
+}
+
+/// Returns the pose channels of a bone of an armature object
+pub(crate) fn pose_channels(obj: &Instance, bone_name: &str) -> Option<TransformChannels> {
+    if !obj.is_valid("pose") {
+        return None;
+    }
+
+    obj.get("pose")
+        .get_iter("chanbase")
+        .find(|channel| channel.get_string("name") == bone_name)
+        .map(|channel| TransformChannels::from_pose_channel(&channel))
+}
+
+/// Returns the pose channels of a bone of an armature object, with the F-curves of the action of
+/// the armature evaluated at a frame if "animation" is given
+fn evaluated_pose_channels(
+    obj: &Instance,
+    bone_name: &str,
+    animation: Option<(&[FCurve], f32)>,
+) -> TransformChannels {
+    let pose = pose_channels(obj, bone_name).unwrap_or_default();
+    match animation {
+        Some((fcurves, frame)) => animation::evaluate_owner_channels(
+            fcurves,
+            &animation::pose_bone_path(bone_name),
+            pose,
+            frame,
+        ),
+        None => pose,
+    }
+}
+
+/// Returns the bone of an armature object named "bone_name", if there is one
+pub(crate) fn find_bone<'a>(obj: &Instance<'a>, bone_name: &str) -> Option<Instance<'a>> {
+    if obj.get_i16("type") != OB_ARMATURE || !obj.is_valid("data") {
+        return None;
+    }
+    bones(&obj.get("data"))
+        .into_iter()
+        .find(|bone| bone.get_string("name") == bone_name)
+}
+
+/// Returns the matrix of the head of a bone relative to its armature object, at its current pose
+/// or with the F-curves of the action of the armature evaluated at "frame", like its joint entity
+pub(crate) fn pose_bone_matrix(obj: &Instance, bone: &Instance, frame: Option<f32>) -> Mat4 {
+    let fcurves = frame
+        .map(|_| animation::object_fcurves(obj))
+        .unwrap_or_default();
+    let animation = frame.map(|frame| (fcurves.as_slice(), frame));
+
+    let mut matrix = Mat4::IDENTITY;
+    let mut current = Some(bone.clone());
+    while let Some(bone) = current {
+        let pose = evaluated_pose_channels(obj, &bone.get_string("name"), animation);
+        matrix = bone_rest_matrix(&bone) * pose.matrix() * matrix;
+        current = bone.is_valid("parent").then(|| bone.get("parent"));
+    }
+    matrix
+}
+
+/// Returns the offset from the head of a bone to its tail, which objects parented to the bone are
+/// relative to
+pub(crate) fn bone_tail_offset(bone: &Instance) -> Mat4 {
+    Mat4::from_translation(Vec3::new(0.0, bone.get_f32("length"), 0.0))
+}
+
+/// Returns the rest matrix of a bone relative to its parent bone, or to the armature for root bones
+pub(crate) fn bone_rest_matrix(bone: &Instance) -> Mat4 {
+    let matrix = object::get_matrix(bone, "arm_mat");
+    if bone.is_valid("parent") {
+        object::get_matrix(&bone.get("parent"), "arm_mat").inverse() * matrix
+    } else {
+        matrix
+    }
+}
+
+/// Spawns the bones of an armature object as a hierarchy of joint entities named like the bones,
+/// placed at their current pose, or at their pose with the F-curves of the action of the armature
+/// evaluated at "frame". "spawn_bone_children" spawns the objects parented to a bone under its
+/// joint entity.
+pub(crate) fn spawn_joints(
+    builder: &mut ChildBuilder,
+    obj: &Instance,
+    frame: Option<f32>,
+    conversion: &Conversion,
+    spawn_bone_children: &mut dyn FnMut(&mut ChildBuilder, &Instance),
+) {
+    fn spawn_joint(
+        builder: &mut ChildBuilder,
+        obj: &Instance,
+        bone: Instance,
+        animation: Option<(&[FCurve], f32)>,
+        conversion: &Conversion,
+        spawn_bone_children: &mut dyn FnMut(&mut ChildBuilder, &Instance),
+    ) {
+        let name = bone.get_string("name");
+        let pose = evaluated_pose_channels(obj, &name, animation);
+        let matrix = bone_rest_matrix(&bone) * pose.matrix();
+        let transform = Transform::from_matrix(conversion.matrix(&matrix));
+
+        let mut joint = builder.spawn((SpatialBundle::from_transform(transform), Name::new(name)));
+
+        // IK constraints are solved on the joints at runtime
+        if let Some(ik) = ik::bone_ik_constraint(obj, &bone, conversion) {
+            joint.insert(ik);
+        }
+
+        joint.with_children(|parent| {
+            if bone.is_valid("childbase") {
+                for child in bone.get_iter("childbase") {
+                    spawn_joint(
+                        parent,
+                        obj,
+                        child,
+                        animation,
+                        conversion,
+                        spawn_bone_children,
+                    );
+                }
+            }
+            spawn_bone_children(parent, &bone);
+        });
+    }
+
+    if obj.get_i16("type") != OB_ARMATURE || !obj.is_valid("data") {
+        return;
+    }
+    let armature = obj.get("data");
+    if !armature.is_valid("bonebase") {
+        return;
+    }
+    let fcurves = frame
+        .map(|_| animation::object_fcurves(obj))
+        .unwrap_or_default();
+    for bone in armature.get_iter("bonebase") {
+        spawn_joint(
+            builder,
+            obj,
+            bone,
+            frame.map(|frame| (fcurves.as_slice(), frame)),
+            conversion,
+            spawn_bone_children,
+        );
+    }
+}
+
+/// Replaces the PendingSkinnedMesh of mesh entities by a SkinnedMesh once the joint entities of
+/// their armature exist. The armature is looked for among the ancestors of the mesh first.
+pub(crate) fn attach_skinned_meshes(
+    mut commands: Commands,
+    pending: Query<(Entity, &PendingSkinnedMesh)>,
+    names: Query<(Entity, &Name)>,
+    parents: Query<&Parent>,
+    children: Query<&Children>,
+) {
+    for (entity, skinned_mesh) in &pending {
+        let is_armature = |entity: Entity| {
+            names
+                .get(entity)
+                .is_ok_and(|(_, name)| name.as_str() == skinned_mesh.armature)
+        };
+
+        let ancestor = parents
+            .iter_ancestors(entity)
+            .find(|ancestor| is_armature(*ancestor));
+        let Some(armature) = ancestor.or_else(|| {
+            names
+                .iter()
+                .map(|(entity, _)| entity)
+                .find(|entity| is_armature(*entity))
+        }) else {
+            continue;
+        };
+
+        // Find the joints among the descendants of the armature
+        let mut descendants: HashMap<&str, Entity> = HashMap::new();
+        for descendant in children.iter_descendants(armature) {
+            if let Ok((_, name)) = names.get(descendant) {
+                descendants.entry(name.as_str()).or_insert(descendant);
+            }
+        }
+        let Some(mut joints) = skinned_mesh
+            .joints
+            .iter()
+            .map(|joint| descendants.get(joint.as_str()).copied())
+            .collect::<Option<Vec<Entity>>>()
+        else {
+            continue;
+        };
+        joints.push(entity);
+
+        commands
+            .entity(entity)
+            .insert(SkinnedMesh {
+                inverse_bindposes: skinned_mesh.inverse_bindposes.clone(),
+                joints,
+            })
+            .remove::<PendingSkinnedMesh>();
+    }
+}
@@ -0,0 +1,161 @@
+use bevy_core_pipeline::prelude::Camera3dBundle;
+use bevy_ecs::{component::Component, system::EntityCommands};
+use bevy_hierarchy::BuildChildren;
+use bevy_render::camera::{
+    Camera, OrthographicProjection, PerspectiveProjection, Projection, ScalingMode,
+};
+use bevy_transform::prelude::Transform;
+use blend::{Blend, Instance};
+
+use crate::{coordinates::Conversion, get_int};
+
+// Camera.type value of orthographic cameras
+const CAM_ORTHO: i32 = 1;
+
+// Camera.sensor_fit values
+const CAMERA_SENSOR_FIT_HOR: i32 = 1;
+const CAMERA_SENSOR_FIT_VERT: i32 = 2;
+
+/// Which dimension of the image the sensor (or orthographic scale) of a Blender camera fits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlenderSensorFit {
+    /// The sensor width fits the larger dimension of the image
+    Auto,
+    /// The sensor width fits the width of the image
+    Horizontal,
+    /// The sensor height fits the height of the image
+    Vertical,
+}
+
+/// Sensor of a Blender camera, which turns its focal length into the field of view of its
+/// Projection. Added to the camera entities spawned for camera objects.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct BlenderCamera {
+    /// Width of the sensor, in millimeters
+    pub sensor_width: f32,
+    /// Height of the sensor, in millimeters
+    pub sensor_height: f32,
+    /// Which dimension of the image the sensor fits
+    pub sensor_fit: BlenderSensorFit,
+}
+
+impl BlenderCamera {
+    /// Returns the vertical field of view, in radians, of a lens of "focal_length" millimeters for
+    /// an image of "aspect_ratio" (width divided by height)
+    pub fn vertical_fov(&self, focal_length: f32, aspect_ratio: f32) -> f32 {
+        let focal_length = focal_length.max(f32::EPSILON);
+        let horizontal = match self.sensor_fit {
+            BlenderSensorFit::Auto => aspect_ratio >= 1.0,
+            BlenderSensorFit::Horizontal => true,
+            BlenderSensorFit::Vertical => false,
+        };
+        if horizontal {
+            (self.sensor_width * 0.5 / focal_length / aspect_ratio.max(f32::EPSILON)).atan() * 2.0
+        } else if self.sensor_fit == BlenderSensorFit::Auto {
+            (self.sensor_width * 0.5 / focal_length).atan() * 2.0
+        } else {
+            (self.sensor_height * 0.5 / focal_length).atan() * 2.0
+        }
+    }
+
+    /// Returns the scaling mode of an orthographic projection of "scale" world units along the
+    /// dimension the sensor fits
+    pub fn scaling_mode(&self, scale: f32) -> ScalingMode {
+        match self.sensor_fit {
+            BlenderSensorFit::Auto => ScalingMode::AutoMax {
+                max_width: scale,
+                max_height: scale,
+            },
+            BlenderSensorFit::Horizontal => ScalingMode::FixedHorizontal(scale),
+            BlenderSensorFit::Vertical => ScalingMode::FixedVertical(scale),
+        }
+    }
+}
+
+/// Returns the aspect ratio of the images rendered by the first scene of a blend file
+pub(crate) fn scene_aspect_ratio(blend: &Blend) -> f32 {
+    let Some(scene) = blend.get_by_code(*b"SC").into_iter().next() else {
+        return 16.0 / 9.0;
+    };
+    let render = scene.get("r");
+    let width = render.get_i32("xsch") as f32 * render.get_f32("xasp");
+    let height = render.get_i32("ysch") as f32 * render.get_f32("yasp");
+    if width > 0.0 && height > 0.0 {
+        width / height
+    } else {
+        16.0 / 9.0
+    }
+}
+
+/// Returns true if "obj" is the active camera of the first scene of a blend file
+pub(crate) fn is_scene_camera(blend: &Blend, obj: &Instance) -> bool {
+    blend
+        .get_by_code(*b"SC")
+        .into_iter()
+        .next()
+        .filter(|scene| scene.is_valid("camera"))
+        .is_some_and(|scene| {
+            scene.get("camera").get("id").get_string("name") == obj.get("id").get_string("name")
+        })
+}
+
+/// Takes a Blend::Instance camera and returns its BlenderCamera
+pub(crate) fn instance_to_blender_camera(camera: &Instance) -> BlenderCamera {
+    BlenderCamera {
+        sensor_width: camera.get_f32("sensor_x"),
+        sensor_height: camera.get_f32("sensor_y"),
+        sensor_fit: match get_int(camera, "sensor_fit") {
+            CAMERA_SENSOR_FIT_HOR => BlenderSensorFit::Horizontal,
+            CAMERA_SENSOR_FIT_VERT => BlenderSensorFit::Vertical,
+            _ => BlenderSensorFit::Auto,
+        },
+    }
+}
+
+/// Spawns the Bevy camera of a Blend::Instance camera as a child of its object's entity. Only the
+/// camera of the scene is active.
+pub(crate) fn spawn_camera(
+    entity: &mut EntityCommands,
+    camera: &Instance,
+    aspect_ratio: f32,
+    is_active: bool,
+    conversion: &Conversion,
+) {
+    let blender_camera = instance_to_blender_camera(camera);
+    let near = conversion.length(camera.get_f32("clipsta"));
+    let far = conversion.length(camera.get_f32("clipend"));
+
+    let projection = if get_int(camera, "type") == CAM_ORTHO {
+        Projection::Orthographic(OrthographicProjection {
+            near,
+            far,
+            scaling_mode: blender_camera
+                .scaling_mode(conversion.length(camera.get_f32("ortho_scale"))),
+            ..Default::default()
+        })
+    } else {
+        Projection::Perspective(PerspectiveProjection {
+            fov: blender_camera.vertical_fov(camera.get_f32("lens"), aspect_ratio),
+            aspect_ratio,
+            near,
+            far,
+        })
+    };
+
+    entity.with_children(|parent| {
+        parent.spawn((
+            Camera3dBundle {
+                camera: Camera {
+                    is_active,
+                    ..Default::default()
+                },
+                projection,
+                // Cameras look down -Z in Blender, which becomes -Z in Bevy once rotated like
+                // the axes
+                transform: Transform::from_rotation(conversion.basis_rotation()),
+                ..Default::default()
+            },
+            blender_camera,
+        ));
+    });
+}
@@ -0,0 +1,858 @@
+use bevy_core::Name;
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    query::With,
+    system::{Query, SystemParam},
+    world::World,
+};
+use bevy_hierarchy::{Children, HierarchyQueryExt, Parent};
+use bevy_math::{BVec3, EulerRot, Mat3, Mat4, Quat, Vec3};
+use bevy_transform::prelude::{GlobalTransform, Transform};
+use blend::Instance;
+
+use crate::{coordinates::Conversion, get_int, ik::BlenderIkConstraint, object, BlenderAxis};
+
+// bConstraint.type values
+const CONSTRAINT_TYPE_CHILDOF: i32 = 1;
+const CONSTRAINT_TYPE_TRACKTO: i32 = 2;
+const CONSTRAINT_TYPE_ROTLIMIT: i32 = 5;
+const CONSTRAINT_TYPE_ROTLIKE: i32 = 8;
+const CONSTRAINT_TYPE_LOCLIKE: i32 = 9;
+const CONSTRAINT_TYPE_DAMPTRACK: i32 = 21;
+
+// bConstraint.flag bits of constraints that Blender could not evaluate, and of muted ones
+pub(crate) const CONSTRAINT_DISABLE: i32 = 1 << 2;
+pub(crate) const CONSTRAINT_OFF: i32 = 1 << 9;
+
+// bConstraint.ownspace value of the local space
+const CONSTRAINT_SPACE_LOCAL: i32 = 1;
+
+// bTrackToConstraint.flags bit making the Z axis of the target the up direction
+const TARGET_Z_UP: i32 = 1 << 0;
+
+// bLocateLikeConstraint and bRotateLikeConstraint flag bits
+const LIKE_X: i32 = 1 << 0;
+const LIKE_Y: i32 = 1 << 1;
+const LIKE_Z: i32 = 1 << 2;
+const LIKE_X_INVERT: i32 = 1 << 4;
+const LIKE_Y_INVERT: i32 = 1 << 5;
+const LIKE_Z_INVERT: i32 = 1 << 6;
+const LOCLIKE_OFFSET: i32 = 1 << 7;
+
+// bRotateLikeConstraint.mix_mode values (eCopyRotation_MixMode), the legacy offset mode being
+// handled as ADD
+const ROTLIKE_MIX_OFFSET: i32 = 1;
+const ROTLIKE_MIX_ADD: i32 = 2;
+const ROTLIKE_MIX_BEFORE: i32 = 3;
+const ROTLIKE_MIX_AFTER: i32 = 4;
+
+// bChildOfConstraint.flag bits, one per component of the target's transform
+const CHILDOF_LOCX: i32 = 1 << 0;
+const CHILDOF_ROTX: i32 = 1 << 3;
+const CHILDOF_SIZEX: i32 = 1 << 6;
+
+// bRotLimitConstraint.flag bits
+const LIMIT_XROT: i32 = 1 << 0;
+const LIMIT_YROT: i32 = 1 << 1;
+const LIMIT_ZROT: i32 = 1 << 2;
+
+/// The constraint stack of an object, evaluated every frame in Blender's order after transforms
+/// are propagated. Constraints work in Blender coordinates and their result replaces the
+/// GlobalTransform of the entity (and of its descendants), leaving its Transform untouched.
+#[derive(Component, Debug, Clone)]
+pub struct BlenderConstraints {
+    /// Constraints, in evaluation order
+    pub constraints: Vec<BlenderConstraint>,
+    /// Parent inverse matrix of the object, which local space constraints are relative to
+    parent_inverse: Mat4,
+    /// Conversion of the coordinates of the blend file the object comes from
+    conversion: Conversion,
+    /// Root entity spawned by spawn_blender_object along with the object, whose hierarchy targets
+    /// are looked up in before the rest of the world
+    root: Option<Entity>,
+}
+
+/// A constraint of a Blender object
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlenderConstraint {
+    /// Name of the constraint
+    pub name: String,
+    /// Blend factor of the result of the constraint with the transform it constrains, from 0 to 1
+    pub influence: f32,
+    /// Name of the target object, if the constraint has one
+    pub target: Option<String>,
+    /// Name of the target bone of an armature target
+    pub subtarget: Option<String>,
+    /// Space the owner is constrained in
+    pub space: BlenderConstraintSpace,
+    /// What the constraint does
+    pub kind: BlenderConstraintKind,
+}
+
+/// Space a constraint is evaluated in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlenderConstraintSpace {
+    /// World space
+    World,
+    /// Space of the parent of the owner, i.e. the transform channels of the owner
+    Local,
+}
+
+/// How Copy Rotation combines the rotation of the target with the rotation of the owner
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlenderRotationMix {
+    /// The rotation of the target replaces the rotation of the owner
+    Replace,
+    /// The Euler angles of the target are added to the Euler angles of the owner
+    Add,
+    /// The rotation of the target is applied before the rotation of the owner
+    Before,
+    /// The rotation of the target is applied after the rotation of the owner
+    After,
+}
+
+/// The supported Blender constraints, in Blender coordinates
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlenderConstraintKind {
+    /// Track To: points an axis of the owner at the target, keeping another axis up
+    TrackTo {
+        /// Axis pointing at the target
+        track_axis: BlenderAxis,
+        /// Axis pointing up, X, Y or Z
+        up_axis: BlenderAxis,
+        /// Whether up is the Z axis of the target rather than world Z
+        target_up: bool,
+    },
+    /// Damped Track: rotates an axis of the owner towards the target by the shortest rotation
+    DampedTrack {
+        /// Axis pointing at the target
+        track_axis: BlenderAxis,
+    },
+    /// Copy Location: copies the location of the target
+    CopyLocation {
+        /// Copied axes
+        axes: BVec3,
+        /// Inverted axes
+        invert: BVec3,
+        /// Whether the location is added to the location of the owner
+        offset: bool,
+    },
+    /// Copy Rotation: copies the XYZ Euler angles of the target
+    CopyRotation {
+        /// Copied axes
+        axes: BVec3,
+        /// Inverted axes
+        invert: BVec3,
+        /// How the rotations are combined
+        mix: BlenderRotationMix,
+    },
+    /// Child Of: makes the target a parent of the owner
+    ChildOf {
+        /// Inherited location axes
+        location: BVec3,
+        /// Inherited rotation axes
+        rotation: BVec3,
+        /// Inherited scale axes
+        scale: BVec3,
+        /// Inverse matrix set by "Set Inverse" in Blender
+        inverse: Mat4,
+    },
+    /// Limit Rotation: clamps the XYZ Euler angles of the owner
+    LimitRotation {
+        /// Lower limits, in radians
+        min: Vec3,
+        /// Upper limits, in radians
+        max: Vec3,
+        /// Limited axes
+        limit: BVec3,
+    },
+}
+
+/// Reads the supported constraints of a Blend::Instance object, in their evaluation order.
+/// Muted constraints and constraints Blender could not evaluate are left out.
+pub(crate) fn instance_to_constraints(
+    obj: &Instance,
+    conversion: &Conversion,
+) -> Option<BlenderConstraints> {
+    if !obj.is_valid("constraints") {
+        return None;
+    }
+    let constraints: Vec<_> = obj
+        .get_iter("constraints")
+        .filter_map(|constraint| instance_to_constraint(&constraint))
+        .collect();
+    if constraints.is_empty() {
+        return None;
+    }
+
+    let parent_inverse = if obj.is_valid("parent") {
+        object::get_matrix(obj, "parentinv")
+    } else {
+        Mat4::IDENTITY
+    };
+    Some(BlenderConstraints {
+        constraints,
+        parent_inverse,
+        conversion: *conversion,
+        root: None,
+    })
+}
+
+/// Reads a Blend::Instance bConstraint, if it is enabled and supported
+fn instance_to_constraint(constraint: &Instance) -> Option<BlenderConstraint> {
+    if get_int(constraint, "flag") & (CONSTRAINT_DISABLE | CONSTRAINT_OFF) != 0
+        || !constraint.is_valid("data")
+    {
+        return None;
+    }
+    let data = constraint.get("data");
+    let bits =
+        |flag: i32, x: i32, y: i32, z: i32| BVec3::new(flag & x != 0, flag & y != 0, flag & z != 0);
+
+    let kind = match get_int(constraint, "type") {
+        CONSTRAINT_TYPE_TRACKTO => BlenderConstraintKind::TrackTo {
+            track_axis: track_axis(get_int(&data, "reserved1"))?,
+            up_axis: track_axis(get_int(&data, "reserved2"))?,
+            target_up: get_int(&data, "flags") & TARGET_Z_UP != 0,
+        },
+        CONSTRAINT_TYPE_DAMPTRACK => BlenderConstraintKind::DampedTrack {
+            track_axis: track_axis(get_int(&data, "trackflag"))?,
+        },
+        CONSTRAINT_TYPE_LOCLIKE => {
+            let flag = get_int(&data, "flag");
+            BlenderConstraintKind::CopyLocation {
+                axes: bits(flag, LIKE_X, LIKE_Y, LIKE_Z),
+                invert: bits(flag, LIKE_X_INVERT, LIKE_Y_INVERT, LIKE_Z_INVERT),
+                offset: flag & LOCLIKE_OFFSET != 0,
+            }
+        }
+        CONSTRAINT_TYPE_ROTLIKE => {
+            let flag = get_int(&data, "flag");
+            BlenderConstraintKind::CopyRotation {
+                axes: bits(flag, LIKE_X, LIKE_Y, LIKE_Z),
+                invert: bits(flag, LIKE_X_INVERT, LIKE_Y_INVERT, LIKE_Z_INVERT),
+                mix: rotation_mix(get_int(&data, "mix_mode")),
+            }
+        }
+        CONSTRAINT_TYPE_CHILDOF => {
+            let flag = get_int(&data, "flag");
+            let axes = |x: i32| bits(flag, x, x << 1, x << 2);
+            BlenderConstraintKind::ChildOf {
+                location: axes(CHILDOF_LOCX),
+                rotation: axes(CHILDOF_ROTX),
+                scale: axes(CHILDOF_SIZEX),
+                inverse: object::get_matrix(&data, "invmat"),
+            }
+        }
+        CONSTRAINT_TYPE_ROTLIMIT => BlenderConstraintKind::LimitRotation {
+            min: Vec3::new(
+                data.get_f32("xmin"),
+                data.get_f32("ymin"),
+                data.get_f32("zmin"),
+            ),
+            max: Vec3::new(
+                data.get_f32("xmax"),
+                data.get_f32("ymax"),
+                data.get_f32("zmax"),
+            ),
+            limit: bits(get_int(&data, "flag"), LIMIT_XROT, LIMIT_YROT, LIMIT_ZROT),
+        },
+        _ => return None,
+    };
+
+    let target = (data.fields.contains_key("tar") && data.is_valid("tar"))
+        .then(|| object::object_name(&data.get("tar")));
+    let subtarget = data
+        .fields
+        .contains_key("subtarget")
+        .then(|| data.get_string("subtarget"))
+        .filter(|subtarget| !subtarget.is_empty());
+    let space = match get_int(constraint, "ownspace") {
+        CONSTRAINT_SPACE_LOCAL => BlenderConstraintSpace::Local,
+        _ => BlenderConstraintSpace::World,
+    };
+
+    Some(BlenderConstraint {
+        name: constraint.get_string("name"),
+        influence: constraint.get_f32("enforce"),
+        target,
+        subtarget,
+        space,
+        kind,
+    })
+}
+
+/// Returns the mix of a bRotateLikeConstraint.mix_mode value
+fn rotation_mix(mix_mode: i32) -> BlenderRotationMix {
+    match mix_mode {
+        ROTLIKE_MIX_ADD | ROTLIKE_MIX_OFFSET => BlenderRotationMix::Add,
+        ROTLIKE_MIX_BEFORE => BlenderRotationMix::Before,
+        ROTLIKE_MIX_AFTER => BlenderRotationMix::After,
+        _ => BlenderRotationMix::Replace,
+    }
+}
+
+/// Returns the axis of a TRACK_X to TRACK_nZ (or UP_X to UP_Z) value
+fn track_axis(value: i32) -> Option<BlenderAxis> {
+    [
+        BlenderAxis::X,
+        BlenderAxis::Y,
+        BlenderAxis::Z,
+        BlenderAxis::NegX,
+        BlenderAxis::NegY,
+        BlenderAxis::NegZ,
+    ]
+    .get(usize::try_from(value).ok()?)
+    .copied()
+}
+
+/// Returns the index of an axis and whether it is negative
+fn axis_index(axis: BlenderAxis) -> (usize, bool) {
+    match axis {
+        BlenderAxis::X => (0, false),
+        BlenderAxis::Y => (1, false),
+        BlenderAxis::Z => (2, false),
+        BlenderAxis::NegX => (0, true),
+        BlenderAxis::NegY => (1, true),
+        BlenderAxis::NegZ => (2, true),
+    }
+}
+
+impl BlenderConstraint {
+    /// Whether the constraint does nothing without a target
+    pub fn needs_target(&self) -> bool {
+        !matches!(self.kind, BlenderConstraintKind::LimitRotation { .. })
+    }
+
+    /// Constrains the world matrix "owner" of the owner, given the world matrix of the target and
+    /// the matrix the owner is relative to (the world matrix of its parent followed by its parent
+    /// inverse matrix), and blends the result with "owner" by the influence
+    pub fn apply(&self, owner: Mat4, target: Option<Mat4>, parent: Option<Mat4>) -> Mat4 {
+        let target = match target {
+            Some(target) => target,
+            None if self.needs_target() => return owner,
+            None => Mat4::IDENTITY,
+        };
+
+        // Local space constraints work on the transform relative to the parent
+        let space = match (self.space, parent) {
+            (BlenderConstraintSpace::Local, Some(parent)) if parent.determinant() != 0.0 => parent,
+            _ => Mat4::IDENTITY,
+        };
+        let constrained = space * self.kind.evaluate(space.inverse() * owner, target);
+
+        interpolate(owner, constrained, self.influence)
+    }
+}
+
+impl BlenderConstraintKind {
+    /// Returns the constrained matrix of "owner", with the target at "target"
+    fn evaluate(&self, owner: Mat4, target: Mat4) -> Mat4 {
+        let (location, rotation, size) = loc_rot_size(&owner);
+        let target_location = target.w_axis.truncate();
+
+        match self {
+            BlenderConstraintKind::TrackTo {
+                track_axis,
+                up_axis,
+                target_up,
+            } => {
+                let up = if *target_up {
+                    target.z_axis.truncate()
+                } else {
+                    Vec3::Z
+                };
+                let Some(rotation) =
+                    track_rotation(location - target_location, up, *track_axis, *up_axis)
+                else {
+                    return owner;
+                };
+                loc_rot_size_to_matrix(location, rotation, size.abs())
+            }
+            BlenderConstraintKind::DampedTrack { track_axis } => {
+                let from = (rotation * track_axis.vector()).normalize_or_zero();
+                let to = (target_location - location).normalize_or_zero();
+                if from == Vec3::ZERO || to == Vec3::ZERO {
+                    return owner;
+                }
+                let mut constrained = Mat4::from_quat(Quat::from_rotation_arc(from, to)) * owner;
+                constrained.w_axis = owner.w_axis;
+                constrained
+            }
+            BlenderConstraintKind::CopyLocation {
+                axes,
+                invert,
+                offset,
+            } => {
+                let mut copied = location;
+                for i in 0..3 {
+                    if axis_enabled(*axes, i) {
+                        let value = if axis_enabled(*invert, i) {
+                            -target_location[i]
+                        } else {
+                            target_location[i]
+                        };
+                        copied[i] = if *offset { value + location[i] } else { value };
+                    }
+                }
+                let mut constrained = owner;
+                constrained.w_axis = copied.extend(1.0);
+                constrained
+            }
+            BlenderConstraintKind::CopyRotation { axes, invert, mix } => {
+                let owner_euler = to_euler(rotation);
+                let mut euler = to_euler(loc_rot_size(&target).1);
+                // Axes that are not copied keep the owner's angle when replacing, and add nothing
+                // otherwise
+                let default = match mix {
+                    BlenderRotationMix::Replace => owner_euler,
+                    _ => Vec3::ZERO,
+                };
+                for i in 0..3 {
+                    if !axis_enabled(*axes, i) {
+                        euler[i] = default[i];
+                    } else if axis_enabled(*invert, i) {
+                        euler[i] = -euler[i];
+                    }
+                }
+                if *mix == BlenderRotationMix::Add {
+                    euler += owner_euler;
+                }
+
+                let copied = from_euler(euler);
+                let rotation = match mix {
+                    BlenderRotationMix::Before => copied * rotation,
+                    BlenderRotationMix::After => rotation * copied,
+                    _ => copied,
+                };
+                loc_rot_size_to_matrix(location, rotation, size)
+            }
+            BlenderConstraintKind::ChildOf {
+                location: inherit_location,
+                rotation: inherit_rotation,
+                scale: inherit_scale,
+                inverse,
+            } => {
+                // Components that are not inherited are reset in the matrix of the target
+                let mut parent = target;
+                if !(inherit_location.all() && inherit_rotation.all() && inherit_scale.all()) {
+                    let (mut location, rotation, mut size) = loc_rot_size(&target);
+                    let mut euler = to_euler(rotation);
+                    for i in 0..3 {
+                        if !axis_enabled(*inherit_location, i) {
+                            location[i] = 0.0;
+                        }
+                        if !axis_enabled(*inherit_rotation, i) {
+                            euler[i] = 0.0;
+                        }
+                        if !axis_enabled(*inherit_scale, i) {
+                            size[i] = 1.0;
+                        }
+                    }
+                    parent = loc_rot_size_to_matrix(location, from_euler(euler), size);
+                }
+                parent * *inverse * owner
+            }
+            BlenderConstraintKind::LimitRotation { min, max, limit } => {
+                let mut euler = to_euler(rotation);
+                for i in 0..3 {
+                    if axis_enabled(*limit, i) {
+                        euler[i] = euler[i].clamp(min[i], max[i].max(min[i]));
+                    }
+                }
+                loc_rot_size_to_matrix(location, from_euler(euler), size)
+            }
+        }
+    }
+}
+
+/// Returns whether the "index"th axis of "axes" is enabled
+fn axis_enabled(axes: BVec3, index: usize) -> bool {
+    [axes.x, axes.y, axes.z][index]
+}
+
+/// Returns the rotation pointing "track_axis" along the opposite of "vector" (from the target to
+/// the owner), with "up_axis" as close as possible to "up", like Blender's Track To
+fn track_rotation(
+    vector: Vec3,
+    up: Vec3,
+    track_axis: BlenderAxis,
+    up_axis: BlenderAxis,
+) -> Option<Quat> {
+    let (axis, negative) = axis_index(track_axis);
+    let (up_index, _) = axis_index(up_axis);
+    let mut track = vector.try_normalize()?;
+    if !negative {
+        track = -track;
+    }
+    if axis == up_index {
+        return Some(Quat::IDENTITY);
+    }
+
+    // Up is projected on the plane perpendicular to the track axis
+    let projected = (up - track * up.dot(track))
+        .try_normalize()
+        .unwrap_or(Vec3::Y);
+    let right = projected.cross(track).normalize_or_zero();
+
+    // The third axis completes a right handed basis
+    const BASIS_CROSS: [[f32; 3]; 3] = [[0.0, -1.0, 1.0], [1.0, 0.0, -1.0], [-1.0, 1.0, 0.0]];
+    let mut columns = [Vec3::ZERO; 3];
+    columns[3 - axis - up_index] = right * BASIS_CROSS[axis][up_index];
+    columns[up_index] = projected;
+    columns[axis] = track;
+    Some(Quat::from_mat3(&Mat3::from_cols(
+        columns[0], columns[1], columns[2],
+    )))
+}
+
+/// Splits a matrix into its location, rotation and scale, a negative determinant negating the
+/// scale, like Blender's mat4_to_loc_rot_size
+fn loc_rot_size(matrix: &Mat4) -> (Vec3, Quat, Vec3) {
+    let basis = Mat3::from_mat4(*matrix);
+    let mut size = Vec3::new(
+        basis.x_axis.length(),
+        basis.y_axis.length(),
+        basis.z_axis.length(),
+    );
+    if size.cmpeq(Vec3::ZERO).any() {
+        return (matrix.w_axis.truncate(), Quat::IDENTITY, size);
+    }
+    let mut rotation = Mat3::from_cols(
+        basis.x_axis / size.x,
+        basis.y_axis / size.y,
+        basis.z_axis / size.z,
+    );
+    if rotation.determinant() < 0.0 {
+        rotation *= -1.0;
+        size = -size;
+    }
+    (
+        matrix.w_axis.truncate(),
+        Quat::from_mat3(&rotation).normalize(),
+        size,
+    )
+}
+
+/// Builds a matrix from a location, a rotation and a scale
+fn loc_rot_size_to_matrix(location: Vec3, rotation: Quat, size: Vec3) -> Mat4 {
+    Mat4::from_scale_rotation_translation(size, rotation, location)
+}
+
+/// Returns the XYZ Euler angles of a rotation
+fn to_euler(rotation: Quat) -> Vec3 {
+    let (z, y, x) = rotation.to_euler(EulerRot::ZYX);
+    Vec3::new(x, y, z)
+}
+
+/// Returns the rotation of XYZ Euler angles
+fn from_euler(euler: Vec3) -> Quat {
+    Quat::from_euler(EulerRot::ZYX, euler.z, euler.y, euler.x)
+}
+
+/// Blends two matrices by "t", interpolating their location, rotation and scale
+pub(crate) fn interpolate(from: Mat4, to: Mat4, t: f32) -> Mat4 {
+    if t >= 1.0 {
+        return to;
+    }
+    if t <= 0.0 {
+        return from;
+    }
+    let (from_location, from_rotation, from_size) = loc_rot_size(&from);
+    let (to_location, to_rotation, to_size) = loc_rot_size(&to);
+    loc_rot_size_to_matrix(
+        from_location.lerp(to_location, t),
+        from_rotation.slerp(to_rotation, t),
+        from_size.lerp(to_size, t),
+    )
+}
+
+/// Queries of the hierarchy constraints look their targets up in and update
+#[derive(SystemParam)]
+pub(crate) struct ConstraintHierarchy<'w, 's> {
+    names: Query<'w, 's, (Entity, &'static Name)>,
+    children: Query<'w, 's, &'static Children>,
+    pub parents: Query<'w, 's, &'static Parent>,
+    pub transforms: Query<'w, 's, &'static Transform>,
+    pub globals: Query<'w, 's, &'static mut GlobalTransform>,
+    pub constrained: Query<'w, 's, (), With<BlenderConstraints>>,
+}
+
+impl ConstraintHierarchy<'_, '_> {
+    /// Returns the GlobalTransform of the entity named "target" or, for bone targets, of the
+    /// joint named "subtarget" below it. The target is looked up in the hierarchy of "root" first,
+    /// so that every copy of a spawned object follows its own target, then in the whole world.
+    pub fn target_global(
+        &self,
+        root: Option<Entity>,
+        target: &str,
+        subtarget: Option<&str>,
+    ) -> Option<GlobalTransform> {
+        let is_target = |entity: &Entity| {
+            self.names
+                .get(*entity)
+                .is_ok_and(|(_, name)| name.as_str() == target)
+        };
+        let scoped = root.and_then(|root| {
+            std::iter::once(root)
+                .chain(self.children.iter_descendants(root))
+                .find(is_target)
+        });
+        let mut entity = match scoped {
+            Some(entity) => entity,
+            None => self
+                .names
+                .iter()
+                .map(|(entity, _)| entity)
+                .find(is_target)?,
+        };
+        if let Some(bone) = subtarget {
+            entity = self.children.iter_descendants(entity).find(|joint| {
+                self.names
+                    .get(*joint)
+                    .is_ok_and(|(_, name)| name.as_str() == bone)
+            })?;
+        }
+        self.globals.get(entity).ok().copied()
+    }
+
+    /// Returns the Blender world matrix of the target of "constraint"
+    fn target_matrix(
+        &self,
+        constraint: &BlenderConstraint,
+        constraints: &BlenderConstraints,
+    ) -> Option<Mat4> {
+        let global = self.target_global(
+            constraints.root,
+            constraint.target.as_ref()?,
+            constraint.subtarget.as_deref(),
+        )?;
+        Some(
+            constraints
+                .conversion
+                .inverse_matrix(&global.compute_matrix()),
+        )
+    }
+
+    /// Returns the GlobalTransform of "entity" before it is constrained, from its Transform and the
+    /// current GlobalTransform of its parent. Its own GlobalTransform may still hold the result of
+    /// the previous frame, as Bevy only propagates transforms that changed.
+    pub fn pose_global(&self, entity: Entity) -> Option<GlobalTransform> {
+        let transform = *self.transforms.get(entity).ok()?;
+        match self.parents.get(entity) {
+            Ok(parent) => Some(
+                self.globals
+                    .get(parent.get())
+                    .ok()?
+                    .mul_transform(transform),
+            ),
+            Err(_) => Some(GlobalTransform::from(transform)),
+        }
+    }
+
+    /// Sets the GlobalTransform of "entity" and recomputes the ones of its descendants from their
+    /// Transform
+    pub fn set_global(&mut self, entity: Entity, global: GlobalTransform) {
+        if let Ok(mut current) = self.globals.get_mut(entity) {
+            *current = global;
+        }
+        let Ok(children) = self.children.get(entity) else {
+            return;
+        };
+        for child in children.iter().copied().collect::<Vec<_>>() {
+            if let Ok(transform) = self.transforms.get(child) {
+                let child_global = global.mul_transform(*transform);
+                self.set_global(child, child_global);
+            }
+        }
+    }
+}
+
+/// Evaluates the constraints of every entity with BlenderConstraints, parents before their
+/// children, and replaces their GlobalTransform with the result. Every frame starts from the
+/// unconstrained transform of the entity, so that constraints do not build up on their result.
+pub(crate) fn evaluate_constraints(
+    owners: Query<(Entity, &BlenderConstraints, Option<&Parent>)>,
+    mut hierarchy: ConstraintHierarchy,
+) {
+    let mut owners: Vec<_> = owners.iter().collect();
+    owners.sort_by_key(|(entity, ..)| hierarchy.parents.iter_ancestors(*entity).count());
+
+    for (entity, constraints, parent) in owners {
+        let conversion = &constraints.conversion;
+        let Some(global) = hierarchy.pose_global(entity) else {
+            continue;
+        };
+        let mut matrix = conversion.inverse_matrix(&global.compute_matrix());
+        let parent_matrix = parent
+            .and_then(|parent| hierarchy.globals.get(parent.get()).ok())
+            .map(|parent| {
+                conversion.inverse_matrix(&parent.compute_matrix()) * constraints.parent_inverse
+            });
+
+        // Each constraint works on the result of the previous ones
+        for constraint in &constraints.constraints {
+            let target = hierarchy.target_matrix(constraint, constraints);
+            matrix = constraint.apply(matrix, target, parent_matrix);
+        }
+
+        hierarchy.set_global(entity, GlobalTransform::from(conversion.matrix(&matrix)));
+    }
+}
+
+/// Makes "root", spawned by spawn_blender_object, the root entity of the constraints and IK
+/// constraints spawned below it, so that their targets are looked up in its hierarchy first
+pub(crate) fn set_constraint_roots(world: &mut World, root: Entity) {
+    let mut entities = vec![root];
+    while let Some(entity) = entities.pop() {
+        if let Some(mut constraints) = world.get_mut::<BlenderConstraints>(entity) {
+            constraints.root = Some(root);
+        }
+        if let Some(mut ik) = world.get_mut::<BlenderIkConstraint>(entity) {
+            ik.root = Some(root);
+        }
+        if let Some(children) = world.get::<Children>(entity) {
+            entities.extend(children.iter().copied());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_ecs::schedule::Schedule;
+    use bevy_hierarchy::BuildWorldChildren;
+
+    fn constraint(
+        influence: f32,
+        target: Option<&str>,
+        kind: BlenderConstraintKind,
+    ) -> BlenderConstraint {
+        BlenderConstraint {
+            name: String::from("Constraint"),
+            influence,
+            target: target.map(String::from),
+            subtarget: None,
+            space: BlenderConstraintSpace::World,
+            kind,
+        }
+    }
+
+    #[test]
+    fn constraints_do_not_build_up_over_frames() {
+        let mut world = World::new();
+        let target = world
+            .spawn((
+                Name::new("Target"),
+                Transform::from_xyz(2.0, 0.0, 0.0),
+                GlobalTransform::from_xyz(2.0, 0.0, 0.0),
+            ))
+            .id();
+        let owner_transform = Transform::from_xyz(0.0, 1.0, 0.0);
+        let owner = world
+            .spawn((
+                Name::new("Owner"),
+                owner_transform,
+                GlobalTransform::from(owner_transform),
+                BlenderConstraints {
+                    constraints: vec![
+                        constraint(
+                            1.0,
+                            Some("Target"),
+                            BlenderConstraintKind::ChildOf {
+                                location: BVec3::TRUE,
+                                rotation: BVec3::TRUE,
+                                scale: BVec3::TRUE,
+                                inverse: Mat4::IDENTITY,
+                            },
+                        ),
+                        constraint(
+                            0.5,
+                            Some("Target"),
+                            BlenderConstraintKind::CopyLocation {
+                                axes: BVec3::TRUE,
+                                invert: BVec3::FALSE,
+                                offset: true,
+                            },
+                        ),
+                    ],
+                    parent_inverse: Mat4::IDENTITY,
+                    conversion: Conversion::default(),
+                    root: None,
+                },
+            ))
+            .id();
+        let child_transform = Transform::from_xyz(0.0, 0.0, 1.0);
+        let child = world
+            .spawn((child_transform, GlobalTransform::from(child_transform)))
+            .id();
+        world.entity_mut(owner).push_children(&[child]);
+
+        let mut schedule = Schedule::new();
+        schedule.add_system(evaluate_constraints);
+
+        // Transforms do not change, so they are not propagated again between the frames
+        schedule.run(&mut world);
+        let first = *world.get::<GlobalTransform>(owner).unwrap();
+        let first_child = *world.get::<GlobalTransform>(child).unwrap();
+        assert_ne!(first, GlobalTransform::from(owner_transform));
+        schedule.run(&mut world);
+        schedule.run(&mut world);
+
+        let owner_global = world.get::<GlobalTransform>(owner).unwrap();
+        let child_global = world.get::<GlobalTransform>(child).unwrap();
+        assert!(owner_global
+            .compute_matrix()
+            .abs_diff_eq(first.compute_matrix(), 1e-5));
+        assert!(child_global
+            .compute_matrix()
+            .abs_diff_eq(first_child.compute_matrix(), 1e-5));
+        assert_eq!(
+            *world.get::<GlobalTransform>(target).unwrap(),
+            GlobalTransform::from_xyz(2.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn rotation_mix_modes_match_blender() {
+        // eCopyRotation_MixMode: Replace, Offset (legacy), Add, Before and After
+        assert_eq!(rotation_mix(0), BlenderRotationMix::Replace);
+        assert_eq!(rotation_mix(1), BlenderRotationMix::Add);
+        assert_eq!(rotation_mix(2), BlenderRotationMix::Add);
+        assert_eq!(rotation_mix(3), BlenderRotationMix::Before);
+        assert_eq!(rotation_mix(4), BlenderRotationMix::After);
+    }
+
+    #[test]
+    fn copy_rotation_mixes_rotations() {
+        let (x, y, z) = (
+            Quat::from_rotation_x(20f32.to_radians()),
+            Quat::from_rotation_y(40f32.to_radians()),
+            Quat::from_rotation_z(30f32.to_radians()),
+        );
+        // The owner has XYZ Euler angles (20, 0, 30) and the target (0, 40, 0)
+        let location = Vec3::new(1.0, 2.0, 3.0);
+        let owner = Mat4::from_rotation_translation(z * x, location);
+        let target = Mat4::from_rotation_translation(y, Vec3::new(-4.0, 5.0, 6.0));
+
+        for (mix, expected) in [
+            (BlenderRotationMix::Replace, y),
+            (BlenderRotationMix::Add, z * y * x),
+            (BlenderRotationMix::Before, y * z * x),
+            (BlenderRotationMix::After, z * x * y),
+        ] {
+            let kind = BlenderConstraintKind::CopyRotation {
+                axes: BVec3::TRUE,
+                invert: BVec3::FALSE,
+                mix,
+            };
+            let constrained = kind.evaluate(owner, target);
+            let expected = Mat4::from_rotation_translation(expected, location);
+            assert!(
+                constrained.abs_diff_eq(expected, 1e-5),
+                "{mix:?}: {constrained} != {expected}"
+            );
+        }
+    }
+}
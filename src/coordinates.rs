@@ -0,0 +1,347 @@
+use bevy_ecs::system::Resource;
+use bevy_log::warn;
+use bevy_math::{Mat3, Mat4, Quat, Vec3};
+use blend::Blend;
+
+/// An axis of the Blender coordinate system, or its opposite
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlenderAxis {
+    /// +X
+    X,
+    /// +Y
+    Y,
+    /// +Z
+    Z,
+    /// -X
+    NegX,
+    /// -Y
+    NegY,
+    /// -Z
+    NegZ,
+}
+
+impl BlenderAxis {
+    /// Returns the unit vector of the axis
+    pub fn vector(&self) -> Vec3 {
+        match self {
+            BlenderAxis::X => Vec3::X,
+            BlenderAxis::Y => Vec3::Y,
+            BlenderAxis::Z => Vec3::Z,
+            BlenderAxis::NegX => Vec3::NEG_X,
+            BlenderAxis::NegY => Vec3::NEG_Y,
+            BlenderAxis::NegZ => Vec3::NEG_Z,
+        }
+    }
+}
+
+/// How Blender coordinates are converted to Bevy ones. Insert this resource before adding the
+/// BlenderPlugin to change it: the loader reads it when the plugin is added, and
+/// spawn_blender_object when its commands are applied. By default, Blender's Z up becomes Bevy's
+/// Y up and Blender's Y becomes Bevy's forward (-Z), so (x, y, z) becomes (x, z, -y).
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct BlenderCoordinateSettings {
+    /// The Blender axis that becomes Bevy's up axis (+Y)
+    pub up: BlenderAxis,
+    /// The Blender axis that becomes Bevy's forward axis (-Z)
+    pub forward: BlenderAxis,
+    /// Factor applied to every length (positions, light ranges, camera clipping...)
+    pub scale: f32,
+    /// Whether the unit scale of the scene (Unit Scale in the scene's unit settings) is applied
+    /// along with "scale"
+    pub use_scene_unit_scale: bool,
+}
+
+impl BlenderCoordinateSettings {
+    /// Converts Blender's Z up coordinates to Bevy's Y up ones
+    pub const Y_UP: Self = Self {
+        up: BlenderAxis::Z,
+        forward: BlenderAxis::Y,
+        scale: 1.0,
+        use_scene_unit_scale: true,
+    };
+
+    /// Keeps Blender's coordinates as they are, with Z up, e.g. to rotate a root entity instead
+    pub const KEEP_Z_UP: Self = Self {
+        up: BlenderAxis::Y,
+        forward: BlenderAxis::NegZ,
+        ..Self::Y_UP
+    };
+}
+
+impl Default for BlenderCoordinateSettings {
+    fn default() -> Self {
+        Self::Y_UP
+    }
+}
+
+/// Converts the coordinates of a blend file to Bevy ones, following the
+/// BlenderCoordinateSettings. Rotations and directions change basis, and lengths are scaled too.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Conversion {
+    /// Rotation from Blender axes to Bevy axes
+    basis: Mat3,
+    /// Factor applied to lengths
+    scale: f32,
+}
+
+impl Default for Conversion {
+    fn default() -> Self {
+        Self::from_settings(&BlenderCoordinateSettings::default(), 1.0)
+    }
+}
+
+impl Conversion {
+    /// Creates the conversion of "blend", with "settings" and the unit scale of the first scene of
+    /// the file
+    pub fn new(blend: &Blend, settings: &BlenderCoordinateSettings) -> Self {
+        let unit_scale = if settings.use_scene_unit_scale {
+            blend
+                .get_by_code(*b"SC")
+                .into_iter()
+                .next()
+                .map(|scene| scene.get("unit").get_f32("scale_length"))
+                .filter(|scale| *scale > 0.0)
+                .unwrap_or(1.0)
+        } else {
+            1.0
+        };
+        Self::from_settings(settings, unit_scale)
+    }
+
+    fn from_settings(settings: &BlenderCoordinateSettings, unit_scale: f32) -> Self {
+        let (mut up, mut forward) = (settings.up.vector(), settings.forward.vector());
+        if up.dot(forward) != 0.0 {
+            warn!(
+                "The up ({:?}) and forward ({:?}) axes of the Blender coordinate settings are not perpendicular, using Z up and Y forward",
+                settings.up, settings.forward
+            );
+            (up, forward) = (Vec3::Z, Vec3::Y);
+        }
+
+        // The rows of the basis are the Blender directions of the Bevy axes, X being Y cross Z
+        let back = -forward;
+        Self {
+            basis: Mat3::from_cols(up.cross(back), up, back).transpose(),
+            scale: settings.scale * unit_scale,
+        }
+    }
+
+    /// Converts a Blender transformation matrix, e.g. the world matrix of an object, by changing
+    /// its basis (C * M * C⁻¹) and scaling its translation
+    pub fn matrix(&self, matrix: &Mat4) -> Mat4 {
+        let basis = Mat4::from_mat3(self.basis);
+        let mut converted = basis * *matrix * basis.transpose();
+        converted.w_axis = (converted.w_axis.truncate() * self.scale).extend(converted.w_axis.w);
+        converted
+    }
+
+    /// Converts a Bevy transformation matrix back to a Blender one
+    pub fn inverse_matrix(&self, matrix: &Mat4) -> Mat4 {
+        let basis = Mat4::from_mat3(self.basis);
+        let mut converted = basis.transpose() * *matrix * basis;
+        converted.w_axis = (converted.w_axis.truncate() / self.scale).extend(converted.w_axis.w);
+        converted
+    }
+
+    /// Converts a Blender position, or an offset between positions
+    pub fn position(&self, position: Vec3) -> Vec3 {
+        self.basis * position * self.scale
+    }
+
+    /// Converts a Blender direction, e.g. a normal
+    pub fn direction(&self, direction: Vec3) -> Vec3 {
+        self.basis * direction
+    }
+
+    /// Converts a Bevy direction back to a Blender direction
+    pub fn inverse_direction(&self, direction: Vec3) -> Vec3 {
+        self.basis.transpose() * direction
+    }
+
+    /// Converts a Blender rotation, whose axis changes basis while its angle stays the same
+    pub fn rotation(&self, rotation: Quat) -> Quat {
+        let basis = self.basis_rotation();
+        basis * rotation * basis.conjugate()
+    }
+
+    /// Converts a Blender length, e.g. the range of a light
+    pub fn length(&self, length: f32) -> f32 {
+        length * self.scale
+    }
+
+    /// Returns the rotation from Blender axes to Bevy axes. As cameras and lights look down -Z in
+    /// Blender, it is also the rotation of the camera and light entities spawned below their
+    /// objects, so that they look down -Z in Bevy.
+    pub fn basis_rotation(&self) -> Quat {
+        Quat::from_mat3(&self.basis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_math::Vec4;
+    use bevy_transform::prelude::Transform;
+
+    // Deterministic pseudo random numbers in [-1, 1), so failures can be reproduced
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> f32 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((self.0 >> 40) as f32 / (1u64 << 24) as f32) * 2.0 - 1.0
+        }
+
+        fn vector(&mut self) -> Vec3 {
+            Vec3::new(self.next(), self.next(), self.next())
+        }
+
+        fn rotation(&mut self) -> Quat {
+            Quat::from_axis_angle(
+                self.vector().try_normalize().unwrap_or(Vec3::Z),
+                self.next() * std::f32::consts::PI,
+            )
+        }
+
+        // A transform with non-uniform, possibly negative, scale and shear
+        fn matrix(&mut self) -> Mat4 {
+            let shear = Mat4::from_cols(
+                Vec4::X,
+                Vec4::new(self.next() * 0.5, 1.0, 0.0, 0.0),
+                Vec4::new(self.next() * 0.5, self.next() * 0.5, 1.0, 0.0),
+                Vec4::W,
+            );
+            Mat4::from_scale_rotation_translation(
+                self.vector() * 4.0,
+                self.rotation(),
+                self.vector() * 10.0,
+            ) * shear
+        }
+    }
+
+    // The default conversion, and ones with other axes, a scale and a scene unit scale
+    fn conversions() -> Vec<Conversion> {
+        vec![
+            Conversion::default(),
+            Conversion::from_settings(&BlenderCoordinateSettings::KEEP_Z_UP, 0.01),
+            Conversion::from_settings(
+                &BlenderCoordinateSettings {
+                    up: BlenderAxis::NegX,
+                    forward: BlenderAxis::Z,
+                    scale: 2.5,
+                    use_scene_unit_scale: true,
+                },
+                1.0,
+            ),
+            Conversion::from_settings(
+                &BlenderCoordinateSettings {
+                    up: BlenderAxis::Y,
+                    forward: BlenderAxis::NegX,
+                    scale: 0.5,
+                    use_scene_unit_scale: false,
+                },
+                3.0,
+            ),
+        ]
+    }
+
+    #[test]
+    fn converted_matrices_transform_converted_positions() {
+        let mut random = Random(42);
+        for conversion in conversions() {
+            for _ in 0..1000 {
+                let matrix = random.matrix();
+                let position = random.vector() * 5.0;
+
+                let expected = conversion.position(matrix.transform_point3(position));
+                let converted = conversion
+                    .matrix(&matrix)
+                    .transform_point3(conversion.position(position));
+                assert!(
+                    converted.abs_diff_eq(expected, 1e-3),
+                    "{conversion:?}: {converted} != {expected}"
+                );
+                assert!(conversion
+                    .inverse_matrix(&conversion.matrix(&matrix))
+                    .abs_diff_eq(matrix, 1e-3));
+            }
+        }
+    }
+
+    #[test]
+    fn converted_rotations_rotate_converted_directions() {
+        let mut random = Random(7);
+        for conversion in conversions() {
+            for _ in 0..1000 {
+                let rotation = random.rotation();
+                let direction = random.vector();
+
+                let expected = conversion.direction(rotation * direction);
+                let converted = conversion.rotation(rotation) * conversion.direction(direction);
+                assert!(
+                    converted.abs_diff_eq(expected, 1e-4),
+                    "{conversion:?}: {converted} != {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn converted_transforms_match_converted_matrices() {
+        let mut random = Random(1234);
+        for conversion in conversions() {
+            for _ in 0..1000 {
+                // Transforms can not hold shear, so the scale is uniform
+                let scale = random.next().abs() * 4.0 + 0.1;
+                let rotation = random.rotation();
+                let translation = random.vector() * 10.0;
+                let matrix = Mat4::from_scale_rotation_translation(
+                    Vec3::splat(scale),
+                    rotation,
+                    translation,
+                );
+
+                let transform = Transform::from_matrix(conversion.matrix(&matrix));
+                let expected = Transform {
+                    translation: conversion.position(translation),
+                    rotation: conversion.rotation(rotation),
+                    scale: Vec3::splat(scale),
+                };
+                assert!(transform
+                    .compute_matrix()
+                    .abs_diff_eq(expected.compute_matrix(), 1e-3));
+            }
+        }
+    }
+
+    #[test]
+    fn mirrored_transforms_keep_their_reflection() {
+        let mut random = Random(99);
+        for conversion in conversions() {
+            for i in 0..1000 {
+                // Mirror a single axis, or all three
+                let mut scale = random.vector().abs() * 4.0 + 0.1;
+                match i % 4 {
+                    3 => scale = -scale,
+                    axis => scale[axis] = -scale[axis],
+                }
+                let matrix = Mat4::from_scale_rotation_translation(
+                    scale,
+                    random.rotation(),
+                    random.vector() * 10.0,
+                );
+
+                let converted = conversion.matrix(&matrix);
+                assert!(converted.determinant() < 0.0);
+                let transform = Transform::from_matrix(converted);
+                assert!(
+                    transform.compute_matrix().abs_diff_eq(converted, 1e-3),
+                    "{conversion:?}: {transform:?} does not match {converted}"
+                );
+            }
+        }
+    }
+}
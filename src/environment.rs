@@ -0,0 +1,414 @@
+use std::f32::consts::PI;
+
+use bevy_math::{Vec2, Vec3};
+use bevy_render::{
+    render_resource::{
+        Extent3d, TextureDimension, TextureFormat, TextureViewDescriptor, TextureViewDimension,
+    },
+    texture::Image,
+};
+
+use crate::{coordinates::Conversion, BevyBlenderError};
+
+/// Size of every face of the generated diffuse cubemap
+const DIFFUSE_SIZE: u32 = 32;
+/// Size of every face of the first mip level of the generated specular cubemap
+const SPECULAR_SIZE: u32 = 128;
+/// Number of mip levels of the specular cubemap, the last one is for a roughness of 1
+const SPECULAR_MIP_LEVELS: u32 = 6;
+/// Number of GGX samples taken for every texel of the rough specular mip levels
+const SPECULAR_SAMPLES: u32 = 64;
+/// Equirectangular maps are downsampled to this width before their irradiance is computed
+const IRRADIANCE_WIDTH: usize = 256;
+
+/// An equirectangular environment map in linear color space, along with its downsampled versions
+pub(crate) struct Equirectangular {
+    levels: Vec<EquirectangularLevel>,
+    /// Conversion of the Blender directions of the map to Bevy ones
+    conversion: Conversion,
+}
+
+struct EquirectangularLevel {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+}
+
+impl Equirectangular {
+    /// Reads the pixels of a decoded equirectangular image, whose directions are converted with
+    /// "conversion"
+    pub(crate) fn from_image(image: &Image, conversion: Conversion) -> anyhow::Result<Self> {
+        let width = image.texture_descriptor.size.width as usize;
+        let height = image.texture_descriptor.size.height as usize;
+
+        let pixels: Vec<Vec3> = match image.texture_descriptor.format {
+            TextureFormat::Rgba32Float => image
+                .data
+                .chunks_exact(16)
+                .map(|texel| {
+                    let channel = |i: usize| {
+                        f32::from_le_bytes([
+                            texel[i * 4],
+                            texel[i * 4 + 1],
+                            texel[i * 4 + 2],
+                            texel[i * 4 + 3],
+                        ])
+                    };
+                    Vec3::new(channel(0), channel(1), channel(2))
+                })
+                .collect(),
+            TextureFormat::Rgba8UnormSrgb => image
+                .data
+                .chunks_exact(4)
+                .map(|texel| {
+                    Vec3::new(
+                        srgb_to_linear(texel[0]),
+                        srgb_to_linear(texel[1]),
+                        srgb_to_linear(texel[2]),
+                    )
+                })
+                .collect(),
+            TextureFormat::Rgba8Unorm => image
+                .data
+                .chunks_exact(4)
+                .map(|texel| Vec3::new(texel[0] as f32, texel[1] as f32, texel[2] as f32) / 255.0)
+                .collect(),
+            format => {
+                return Err(anyhow::Error::new(BevyBlenderError::UnsupportedAsset {
+                    asset_type: format!("Environment texture of format {:?}", format),
+                }))
+            }
+        };
+
+        // Build a chain of box filtered levels, used to prefilter the rough specular levels
+        let mut levels = vec![EquirectangularLevel {
+            width,
+            height,
+            pixels,
+        }];
+        while levels.last().unwrap().width > 8 && levels.last().unwrap().height > 4 {
+            let level = levels.last().unwrap().downsample();
+            levels.push(level);
+        }
+
+        Ok(Self { levels, conversion })
+    }
+
+    /// Samples the environment in a direction of the Bevy coordinate system, "level" selects the
+    /// (fractional) downsampled level to read from
+    fn sample(&self, direction: Vec3, level: f32) -> Vec3 {
+        let level = level.clamp(0.0, (self.levels.len() - 1) as f32);
+        let lower = level.floor() as usize;
+        let upper = (lower + 1).min(self.levels.len() - 1);
+        let uv = direction_to_equirectangular(direction, &self.conversion);
+
+        self.levels[lower]
+            .sample(uv)
+            .lerp(self.levels[upper].sample(uv), level - lower as f32)
+    }
+}
+
+impl EquirectangularLevel {
+    fn texel(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Bilinear sample, wrapping horizontally and clamping vertically
+    fn sample(&self, uv: Vec2) -> Vec3 {
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = uv.y * self.height as f32 - 0.5;
+        let (fx, fy) = (x - x.floor(), y - y.floor());
+
+        let x0 = (x.floor() as isize).rem_euclid(self.width as isize) as usize;
+        let x1 = (x0 + 1) % self.width;
+        let y0 = (y.floor().max(0.0) as usize).min(self.height - 1);
+        let y1 = (y0 + 1).min(self.height - 1);
+
+        let top = self.texel(x0, y0).lerp(self.texel(x1, y0), fx);
+        let bottom = self.texel(x0, y1).lerp(self.texel(x1, y1), fx);
+        top.lerp(bottom, fy)
+    }
+
+    fn downsample(&self) -> Self {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let (x0, y0) = ((x * 2).min(self.width - 1), (y * 2).min(self.height - 1));
+                let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+                pixels.push(
+                    (self.texel(x0, y0)
+                        + self.texel(x1, y0)
+                        + self.texel(x0, y1)
+                        + self.texel(x1, y1))
+                        / 4.0,
+                );
+            }
+        }
+
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+/// Converts an equirectangular environment to a diffuse (irradiance) cubemap, using a spherical
+/// harmonics approximation of the Lambertian convolution
+pub(crate) fn diffuse_cubemap(environment: &Equirectangular) -> Image {
+    // Project the environment onto the first 9 spherical harmonics
+    let level = environment
+        .levels
+        .iter()
+        .find(|level| level.width <= IRRADIANCE_WIDTH)
+        .unwrap_or_else(|| environment.levels.last().unwrap());
+
+    let mut coefficients = [Vec3::ZERO; 9];
+    for y in 0..level.height {
+        // Solid angle of the texels of this row
+        let theta = PI * (y as f32 + 0.5) / level.height as f32;
+        let solid_angle =
+            (2.0 * PI / level.width as f32) * (PI / level.height as f32) * theta.sin();
+
+        for x in 0..level.width {
+            let uv = Vec2::new(
+                (x as f32 + 0.5) / level.width as f32,
+                (y as f32 + 0.5) / level.height as f32,
+            );
+            let direction = equirectangular_to_direction(uv, &environment.conversion);
+            let radiance = level.texel(x, y) * solid_angle;
+            for (coefficient, basis) in coefficients.iter_mut().zip(sh_basis(direction)) {
+                *coefficient += radiance * basis;
+            }
+        }
+    }
+
+    // Convolve with the clamped cosine lobe (Ramamoorthi and Hanrahan) and divide by PI, so that
+    // the map holds the outgoing radiance of a white Lambertian surface
+    for (i, coefficient) in coefficients.iter_mut().enumerate() {
+        *coefficient *= match i {
+            0 => 1.0,
+            1..=3 => 2.0 / 3.0,
+            _ => 0.25,
+        };
+    }
+
+    let mut data = Vec::new();
+    for face in 0..6 {
+        for_each_texel(DIFFUSE_SIZE, face, |direction| {
+            let irradiance = coefficients
+                .iter()
+                .zip(sh_basis(direction))
+                .map(|(coefficient, basis)| *coefficient * basis)
+                .sum::<Vec3>();
+            push_texel(&mut data, irradiance.max(Vec3::ZERO));
+        });
+    }
+
+    cubemap_image(DIFFUSE_SIZE, 1, data)
+}
+
+/// Converts an equirectangular environment to a specular cubemap, where every mip level is
+/// prefiltered with the GGX distribution for a perceptual roughness of mip / (mip count - 1)
+pub(crate) fn specular_cubemap(environment: &Equirectangular) -> Image {
+    let source = &environment.levels[0];
+    let source_texel_solid_angle = 4.0 * PI / (source.width * source.height) as f32;
+
+    // wgpu expects every face to be followed by all of its mip levels
+    let mut data = Vec::new();
+    for face in 0..6 {
+        for mip in 0..SPECULAR_MIP_LEVELS {
+            let size = (SPECULAR_SIZE >> mip).max(1);
+            let roughness = mip as f32 / (SPECULAR_MIP_LEVELS - 1) as f32;
+
+            for_each_texel(size, face, |normal| {
+                if mip == 0 {
+                    push_texel(&mut data, environment.sample(normal, 0.0));
+                    return;
+                }
+
+                // Split sum prefiltering, assuming the view and normal directions are the same
+                let (tangent, bitangent) = tangent_space(normal);
+                let mut color = Vec3::ZERO;
+                let mut weight = 0.0;
+                for i in 0..SPECULAR_SAMPLES {
+                    let xi = hammersley(i, SPECULAR_SAMPLES);
+                    let h = importance_sample_ggx(xi, roughness);
+                    let h = tangent * h.x + bitangent * h.y + normal * h.z;
+                    let l = 2.0 * normal.dot(h) * h - normal;
+
+                    let n_dot_l = normal.dot(l);
+                    if n_dot_l <= 0.0 {
+                        continue;
+                    }
+
+                    // Read from a downsampled level matching the footprint of the sample
+                    let n_dot_h = normal.dot(h).max(0.0);
+                    let pdf = d_ggx(n_dot_h, roughness) / 4.0;
+                    let sample_solid_angle = 1.0 / (SPECULAR_SAMPLES as f32 * pdf + 1e-4);
+                    let level = 0.5 * (sample_solid_angle / source_texel_solid_angle).log2() + 1.0;
+
+                    color += environment.sample(l, level) * n_dot_l;
+                    weight += n_dot_l;
+                }
+
+                push_texel(&mut data, color / weight.max(1e-4));
+            });
+        }
+    }
+
+    cubemap_image(SPECULAR_SIZE, SPECULAR_MIP_LEVELS, data)
+}
+
+/// Calls "f" with the (Bevy) direction of every texel of a cubemap face, in row order. Faces are
+/// in the wgpu order +X, -X, +Y, -Y, +Z, -Z.
+fn for_each_texel(size: u32, face: u32, mut f: impl FnMut(Vec3)) {
+    for y in 0..size {
+        for x in 0..size {
+            let s = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+            let t = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+            let direction = match face {
+                0 => Vec3::new(1.0, -t, -s),
+                1 => Vec3::new(-1.0, -t, s),
+                2 => Vec3::new(s, 1.0, t),
+                3 => Vec3::new(s, -1.0, -t),
+                4 => Vec3::new(s, -t, 1.0),
+                _ => Vec3::new(-s, -t, -1.0),
+            };
+            f(direction.normalize());
+        }
+    }
+}
+
+/// Creates a cubemap image from Rgba16Float data holding all faces and their mip levels
+fn cubemap_image(size: u32, mip_levels: u32, data: Vec<u8>) -> Image {
+    let mut image = Image {
+        data,
+        ..Default::default()
+    };
+    image.texture_descriptor.size = Extent3d {
+        width: size,
+        height: size,
+        depth_or_array_layers: 6,
+    };
+    image.texture_descriptor.dimension = TextureDimension::D2;
+    image.texture_descriptor.format = TextureFormat::Rgba16Float;
+    image.texture_descriptor.mip_level_count = mip_levels;
+    image.texture_view_descriptor = Some(TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::Cube),
+        ..Default::default()
+    });
+
+    image
+}
+
+fn push_texel(data: &mut Vec<u8>, color: Vec3) {
+    for channel in [color.x, color.y, color.z, 1.0] {
+        data.extend_from_slice(&f32_to_f16(channel).to_le_bytes());
+    }
+}
+
+/// Converts a Bevy direction to equirectangular coordinates, following Blender's
+/// direction_to_equirectangular (with V going down the image)
+fn direction_to_equirectangular(direction: Vec3, conversion: &Conversion) -> Vec2 {
+    let Vec3 { x, y, z } = conversion.inverse_direction(direction);
+    Vec2::new(
+        -y.atan2(x) / (2.0 * PI) + 0.5,
+        0.5 - z.atan2(x.hypot(y)) / PI,
+    )
+}
+
+fn equirectangular_to_direction(uv: Vec2, conversion: &Conversion) -> Vec3 {
+    let phi = PI * (1.0 - 2.0 * uv.x);
+    let theta = PI * (0.5 - uv.y);
+    // Blender direction, converted to Bevy
+    conversion.direction(Vec3::new(
+        theta.cos() * phi.cos(),
+        theta.cos() * phi.sin(),
+        theta.sin(),
+    ))
+}
+
+/// The first 9 real spherical harmonics basis functions
+fn sh_basis(d: Vec3) -> [f32; 9] {
+    [
+        0.282_095,
+        0.488_603 * d.y,
+        0.488_603 * d.z,
+        0.488_603 * d.x,
+        1.092_548 * d.x * d.y,
+        1.092_548 * d.y * d.z,
+        0.315_392 * (3.0 * d.z * d.z - 1.0),
+        1.092_548 * d.x * d.z,
+        0.546_274 * (d.x * d.x - d.y * d.y),
+    ]
+}
+
+fn tangent_space(normal: Vec3) -> (Vec3, Vec3) {
+    let up = if normal.y.abs() < 0.999 {
+        Vec3::Y
+    } else {
+        Vec3::X
+    };
+    let tangent = up.cross(normal).normalize();
+    (tangent, normal.cross(tangent))
+}
+
+fn hammersley(i: u32, count: u32) -> Vec2 {
+    Vec2::new(
+        i as f32 / count as f32,
+        i.reverse_bits() as f32 * 2.328_306_4e-10,
+    )
+}
+
+/// Returns a GGX distributed half vector in tangent space, for a perceptual roughness
+fn importance_sample_ggx(xi: Vec2, roughness: f32) -> Vec3 {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = ((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta)
+}
+
+/// GGX normal distribution function, for a perceptual roughness
+fn d_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let f = (n_dot_h * a2 - n_dot_h) * n_dot_h + 1.0;
+    a2 / (PI * f * f)
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts a float to the bits of a half float, clamping to the largest finite half
+fn f32_to_f16(value: f32) -> u16 {
+    if value.is_nan() {
+        return 0x7e00;
+    }
+    let bits = value.clamp(-65504.0, 65504.0).to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent <= 0 {
+        // Subnormal half, or zero
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = (mantissa | 0x80_0000) >> (1 - exponent);
+        return sign | ((mantissa + 0x1000) >> 13) as u16;
+    }
+
+    // Rounding may carry into the exponent, which is still the correctly rounded value
+    sign | (((exponent as u32) << 10) + ((mantissa + 0x1000) >> 13)) as u16
+}
@@ -0,0 +1,995 @@
+use bevy_log::warn;
+use bevy_math::Vec2;
+use blend::Instance;
+use std::f32::consts::PI;
+
+use crate::get_int;
+
+// FCurve.flag bits (DNA_anim_types.h)
+const FCURVE_INT_VALUES: i32 = 1 << 11;
+const FCURVE_DISCRETE_VALUES: i32 = 1 << 12;
+
+// FCurve.extend values
+const FCURVE_EXTRAPOLATE_LINEAR: i32 = 1;
+
+// FModifier.type value of the Cycles modifier, and FModifier.flag bit of muted modifiers
+const FMODIFIER_TYPE_CYCLES: i32 = 4;
+const FMODIFIER_FLAG_MUTED: i32 = 1 << 3;
+
+// FMod_Cycles.before_mode/after_mode values
+const FCM_EXTRAPOLATE_CYCLIC_OFFSET: i32 = 2;
+const FCM_EXTRAPOLATE_MIRROR: i32 = 3;
+
+/// How the value of an F-curve changes from a keyframe to the next one (BezTriple.ipo)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Interpolation {
+    Constant,
+    Linear,
+    Bezier,
+    Back,
+    Bounce,
+    Circular,
+    Cubic,
+    Elastic,
+    Exponential,
+    Quadratic,
+    Quartic,
+    Quintic,
+    Sine,
+}
+
+impl Interpolation {
+    fn from_ipo(ipo: i32) -> Self {
+        match ipo {
+            0 => Interpolation::Constant,
+            1 => Interpolation::Linear,
+            3 => Interpolation::Back,
+            4 => Interpolation::Bounce,
+            5 => Interpolation::Circular,
+            6 => Interpolation::Cubic,
+            7 => Interpolation::Elastic,
+            8 => Interpolation::Exponential,
+            9 => Interpolation::Quadratic,
+            10 => Interpolation::Quartic,
+            11 => Interpolation::Quintic,
+            12 => Interpolation::Sine,
+            _ => Interpolation::Bezier,
+        }
+    }
+}
+
+/// Which end of an easing interpolation is eased (BezTriple.easing)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Easing {
+    /// Ease in, except for Back, Bounce and Elastic which ease out
+    Auto,
+    In,
+    Out,
+    InOut,
+}
+
+/// A keyframe of an F-curve
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Keyframe {
+    /// Frame of the keyframe
+    pub frame: f32,
+    /// Value of the animated property at the keyframe
+    pub value: f32,
+    /// Left Bezier handle (frame, value)
+    pub left_handle: Vec2,
+    /// Right Bezier handle (frame, value)
+    pub right_handle: Vec2,
+    /// Interpolation towards the next keyframe
+    pub interpolation: Interpolation,
+    /// Easing of the easing interpolations
+    pub easing: Easing,
+    /// Overshoot of the Back interpolation
+    pub back: f32,
+    /// Amplitude of the Elastic interpolation
+    pub amplitude: f32,
+    /// Period of the Elastic interpolation
+    pub period: f32,
+}
+
+/// Settings of a Cycles F-curve modifier, which repeats the keyframes before and after them
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Cycles {
+    before_mode: i32,
+    before_cycles: i32,
+    after_mode: i32,
+    after_cycles: i32,
+}
+
+/// An F-curve, animating one component of a property of an object (or bone, material...)
+#[derive(Debug, Clone)]
+pub(crate) struct FCurve {
+    /// Path of the animated property, e.g. "location" or "pose.bones[\"Arm\"].rotation_quaternion"
+    pub rna_path: String,
+    /// Index of the animated component within the property, e.g. 2 for the Z location
+    pub array_index: usize,
+    /// Keyframes, sorted by frame
+    pub keyframes: Vec<Keyframe>,
+    /// Whether the curve is extended linearly, instead of holding its first and last values
+    pub linear_extrapolation: bool,
+    /// Whether the values are held between keyframes, like for booleans and enums
+    pub discrete: bool,
+    /// Whether the values are rounded to integers
+    pub integer: bool,
+    /// The Cycles modifier of the curve, if it is the first of its modifiers
+    pub cycles: Option<Cycles>,
+    /// Number of enabled modifiers of the curve that are not applied, like Noise or Generator
+    pub ignored_modifiers: usize,
+    /// Handle of the slot of the action the curve belongs to. Actions have slots since Blender
+    /// 4.4, so that a single action can animate several objects.
+    pub slot_handle: Option<i32>,
+}
+
+impl FCurve {
+    /// Returns the name of the animated property, e.g. "location"
+    pub fn property(&self) -> &str {
+        match self.rna_path.rfind('.') {
+            Some(index) => &self.rna_path[index + 1..],
+            None => self.rna_path.as_str(),
+        }
+    }
+
+    /// Returns the path of the struct owning the animated property, e.g. "pose.bones[\"Arm\"]",
+    /// or an empty string for properties of the animated ID itself
+    pub fn owner(&self) -> &str {
+        match self.rna_path.rfind('.') {
+            Some(index) => &self.rna_path[..index],
+            None => "",
+        }
+    }
+
+    /// Returns the value of the curve at the given frame, the way Blender evaluates it
+    pub fn evaluate(&self, frame: f32) -> f32 {
+        let (frame, offset) = match self.cycles {
+            Some(cycles) => self.cycle(frame, cycles),
+            None => (frame, 0.0),
+        };
+
+        let value = self.evaluate_keyframes(frame) + offset;
+        if self.integer {
+            (value + 0.5).floor()
+        } else {
+            value
+        }
+    }
+
+    /// Returns true if linearly interpolating the curve from frame "a" to frame "b" deviates
+    /// from it by at most "tolerance"
+    pub fn is_linear(&self, a: f32, b: f32, tolerance: f32) -> bool {
+        let (value_a, value_b) = (self.evaluate(a), self.evaluate(b));
+        [0.25, 0.5, 0.75].iter().all(|t| {
+            let expected = value_a + (value_b - value_a) * t;
+            (self.evaluate(a + (b - a) * t) - expected).abs() <= tolerance
+        })
+    }
+
+    /// Maps a frame outside of the keyframes into them following the Cycles modifier. Also
+    /// returns the value offset of the cycle for the cyclic with offset mode.
+    fn cycle(&self, frame: f32, cycles: Cycles) -> (f32, f32) {
+        let (Some(first), Some(last)) = (self.keyframes.first(), self.keyframes.last()) else {
+            return (frame, 0.0);
+        };
+
+        let (side, mode, count, origin) = if frame < first.frame && cycles.before_mode != 0 {
+            (-1.0, cycles.before_mode, cycles.before_cycles, first.frame)
+        } else if frame > last.frame && cycles.after_mode != 0 {
+            (1.0, cycles.after_mode, cycles.after_cycles, last.frame)
+        } else {
+            return (frame, 0.0);
+        };
+
+        let length = last.frame - first.frame;
+        if length == 0.0 {
+            return (frame, 0.0);
+        }
+
+        // A cycle count of 0 repeats forever
+        let cycle = side * (frame - origin) / length;
+        if count != 0 && cycle > count as f32 {
+            return (frame, 0.0);
+        }
+
+        let offset = if mode == FCM_EXTRAPOLATE_CYCLIC_OFFSET {
+            let cycle_offset = if side < 0.0 {
+                ((frame - origin) / length).floor()
+            } else {
+                ((frame - origin) / length).ceil()
+            };
+            cycle_offset * (last.value - first.value)
+        } else {
+            0.0
+        };
+
+        // Mirrored cycles play every other cycle backwards
+        let time = (frame - origin) % length;
+        let mut frame = if time == 0.0 {
+            let at_end = (side > 0.0) != (mode == FCM_EXTRAPOLATE_MIRROR && cycle as i32 % 2 != 0);
+            if at_end {
+                last.frame
+            } else {
+                first.frame
+            }
+        } else if mode == FCM_EXTRAPOLATE_MIRROR && (cycle + 1.0) as i32 % 2 != 0 {
+            if side < 0.0 {
+                first.frame - time
+            } else {
+                last.frame - time
+            }
+        } else {
+            first.frame + time
+        };
+        if frame < first.frame {
+            frame += length;
+        }
+
+        (frame, offset)
+    }
+
+    /// Evaluates the keyframes, and extrapolates them outside of their range
+    fn evaluate_keyframes(&self, frame: f32) -> f32 {
+        let (Some(first), Some(last)) = (self.keyframes.first(), self.keyframes.last()) else {
+            return 0.0;
+        };
+
+        if frame <= first.frame {
+            return self.extrapolate(0, 1, frame);
+        }
+        if frame >= last.frame {
+            return self.extrapolate(self.keyframes.len() - 1, -1, frame);
+        }
+
+        let next = self.keyframes.partition_point(|k| k.frame <= frame);
+        let (a, b) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        interpolate(a, b, frame, self.discrete)
+    }
+
+    /// Extends the curve beyond the keyframe at "index", whose neighbor is in "direction"
+    fn extrapolate(&self, index: usize, direction: isize, frame: f32) -> f32 {
+        let endpoint = &self.keyframes[index];
+        if !self.linear_extrapolation
+            || self.discrete
+            || endpoint.interpolation == Interpolation::Constant
+        {
+            return endpoint.value;
+        }
+
+        // Linear keyframes extend towards their neighbor, others along their handle
+        let (handle_frame, handle_value) = if endpoint.interpolation == Interpolation::Linear {
+            if self.keyframes.len() == 1 {
+                return endpoint.value;
+            }
+            let neighbor = &self.keyframes[(index as isize + direction) as usize];
+            (neighbor.frame, neighbor.value)
+        } else if direction > 0 {
+            (endpoint.left_handle.x, endpoint.left_handle.y)
+        } else {
+            (endpoint.right_handle.x, endpoint.right_handle.y)
+        };
+
+        let dx = handle_frame - endpoint.frame;
+        if dx == 0.0 {
+            return endpoint.value;
+        }
+        endpoint.value + (handle_value - endpoint.value) / dx * (frame - endpoint.frame)
+    }
+}
+
+/// Interpolates between keyframes "a" and "b" following the interpolation of "a"
+fn interpolate(a: &Keyframe, b: &Keyframe, frame: f32, discrete: bool) -> f32 {
+    let duration = b.frame - a.frame;
+    if duration <= 0.0 || (frame - b.frame).abs() < f32::EPSILON {
+        return b.value;
+    }
+
+    let time = frame - a.frame;
+    let begin = a.value;
+    let change = b.value - a.value;
+    let (ease_in, ease_out) = match a.easing {
+        Easing::In => (true, false),
+        Easing::Out => (false, true),
+        Easing::InOut => (true, true),
+        Easing::Auto => match a.interpolation {
+            Interpolation::Back | Interpolation::Bounce | Interpolation::Elastic => (false, true),
+            _ => (true, false),
+        },
+    };
+
+    if discrete {
+        return begin;
+    }
+
+    match a.interpolation {
+        Interpolation::Constant => begin,
+        Interpolation::Linear => begin + change * time / duration,
+        Interpolation::Bezier => bezier(a, b, frame),
+        interpolation => {
+            let ease = Ease {
+                interpolation,
+                back: a.back,
+                amplitude: a.amplitude,
+                period: a.period,
+            };
+            match (ease_in, ease_out) {
+                (true, true) => ease.in_out(time, begin, change, duration),
+                (false, true) => ease.ease_out(time, begin, change, duration),
+                _ => ease.ease_in(time, begin, change, duration),
+            }
+        }
+    }
+}
+
+/// Evaluates the Bezier segment from keyframe "a" to keyframe "b" at the given frame
+fn bezier(a: &Keyframe, b: &Keyframe, frame: f32) -> f32 {
+    let p0 = Vec2::new(a.frame, a.value);
+    let p3 = Vec2::new(b.frame, b.value);
+    let mut p1 = a.right_handle;
+    let mut p2 = b.left_handle;
+
+    // Like Blender, shorten handles that would make the curve go back in time
+    let h1 = p0 - p1;
+    let h2 = p3 - p2;
+    let length = p3.x - p0.x;
+    let handles = h1.x.abs() + h2.x.abs();
+    if handles > length && handles > 0.0 {
+        let factor = length / handles;
+        p1 = p0 - h1 * factor;
+        p2 = p3 - h2 * factor;
+    }
+
+    let point = |t: f32| {
+        let s = 1.0 - t;
+        p0 * (s * s * s) + p1 * (3.0 * s * s * t) + p2 * (3.0 * s * t * t) + p3 * (t * t * t)
+    };
+
+    // The frame of the corrected curve is monotonic, so its parameter can be found by bisection
+    let (mut low, mut high) = (0.0, 1.0);
+    for _ in 0..32 {
+        let t = (low + high) * 0.5;
+        if point(t).x < frame {
+            low = t;
+        } else {
+            high = t;
+        }
+    }
+    point((low + high) * 0.5).y
+}
+
+/// Robert Penner's easing equations, as implemented by Blender
+struct Ease {
+    interpolation: Interpolation,
+    back: f32,
+    amplitude: f32,
+    period: f32,
+}
+
+// Exponential easing is scaled to reach exactly 0 and 1
+const POW_MIN: f32 = 0.000_976_562_5;
+const POW_SCALE: f32 = 1.0 / (1.0 - POW_MIN);
+
+impl Ease {
+    fn ease_in(&self, time: f32, begin: f32, change: f32, duration: f32) -> f32 {
+        let t = time / duration;
+        match self.interpolation {
+            Interpolation::Back => change * t * t * ((self.back + 1.0) * t - self.back) + begin,
+            Interpolation::Bounce => {
+                change - bounce_out(duration - time, 0.0, change, duration) + begin
+            }
+            Interpolation::Circular => -change * ((1.0 - t * t).sqrt() - 1.0) + begin,
+            Interpolation::Cubic => change * t * t * t + begin,
+            Interpolation::Elastic => elastic(
+                time,
+                begin,
+                change,
+                duration,
+                self.amplitude,
+                self.period,
+                false,
+            ),
+            Interpolation::Exponential => {
+                if time == 0.0 {
+                    return begin;
+                }
+                change * (2f32.powf(10.0 * (t - 1.0)) - POW_MIN) * POW_SCALE + begin
+            }
+            Interpolation::Quadratic => change * t * t + begin,
+            Interpolation::Quartic => change * t * t * t * t + begin,
+            Interpolation::Quintic => change * t * t * t * t * t + begin,
+            Interpolation::Sine => -change * (t * PI / 2.0).cos() + change + begin,
+            _ => begin + change * t,
+        }
+    }
+
+    fn ease_out(&self, time: f32, begin: f32, change: f32, duration: f32) -> f32 {
+        let t = time / duration - 1.0;
+        match self.interpolation {
+            Interpolation::Back => {
+                change * (t * t * ((self.back + 1.0) * t + self.back) + 1.0) + begin
+            }
+            Interpolation::Bounce => bounce_out(time, begin, change, duration),
+            Interpolation::Circular => change * (1.0 - t * t).sqrt() + begin,
+            Interpolation::Cubic => change * (t * t * t + 1.0) + begin,
+            Interpolation::Elastic => elastic(
+                time,
+                begin,
+                change,
+                duration,
+                self.amplitude,
+                self.period,
+                true,
+            ),
+            Interpolation::Exponential => {
+                if time == 0.0 {
+                    return begin;
+                }
+                change * (1.0 - (2f32.powf(-10.0 * time / duration) - POW_MIN) * POW_SCALE) + begin
+            }
+            Interpolation::Quadratic => {
+                let t = time / duration;
+                -change * t * (t - 2.0) + begin
+            }
+            Interpolation::Quartic => -change * (t * t * t * t - 1.0) + begin,
+            Interpolation::Quintic => change * (t * t * t * t * t + 1.0) + begin,
+            Interpolation::Sine => change * (time / duration * PI / 2.0).sin() + begin,
+            _ => begin + change * time / duration,
+        }
+    }
+
+    fn in_out(&self, time: f32, begin: f32, change: f32, duration: f32) -> f32 {
+        let t = time / (duration / 2.0);
+        match self.interpolation {
+            Interpolation::Back => {
+                let overshoot = self.back * 1.525;
+                if t < 1.0 {
+                    change / 2.0 * (t * t * ((overshoot + 1.0) * t - overshoot)) + begin
+                } else {
+                    let t = t - 2.0;
+                    change / 2.0 * (t * t * ((overshoot + 1.0) * t + overshoot) + 2.0) + begin
+                }
+            }
+            Interpolation::Bounce => {
+                if time < duration / 2.0 {
+                    self.ease_in(time * 2.0, 0.0, change, duration) * 0.5 + begin
+                } else {
+                    bounce_out(time * 2.0 - duration, 0.0, change, duration) * 0.5
+                        + change * 0.5
+                        + begin
+                }
+            }
+            Interpolation::Circular => {
+                if t < 1.0 {
+                    -change / 2.0 * ((1.0 - t * t).sqrt() - 1.0) + begin
+                } else {
+                    let t = t - 2.0;
+                    change / 2.0 * ((1.0 - t * t).sqrt() + 1.0) + begin
+                }
+            }
+            Interpolation::Cubic => {
+                if t < 1.0 {
+                    change / 2.0 * t * t * t + begin
+                } else {
+                    let t = t - 2.0;
+                    change / 2.0 * (t * t * t + 2.0) + begin
+                }
+            }
+            Interpolation::Elastic => {
+                elastic_in_out(time, begin, change, duration, self.amplitude, self.period)
+            }
+            Interpolation::Exponential => {
+                let half = duration / 2.0;
+                if time < half {
+                    self.ease_in(time, begin, change / 2.0, half)
+                } else {
+                    self.ease_out(time - half, begin + change / 2.0, change / 2.0, half)
+                }
+            }
+            Interpolation::Quadratic => {
+                if t < 1.0 {
+                    change / 2.0 * t * t + begin
+                } else {
+                    let t = t - 1.0;
+                    -change / 2.0 * (t * (t - 2.0) - 1.0) + begin
+                }
+            }
+            Interpolation::Quartic => {
+                if t < 1.0 {
+                    change / 2.0 * t * t * t * t + begin
+                } else {
+                    let t = t - 2.0;
+                    -change / 2.0 * (t * t * t * t - 2.0) + begin
+                }
+            }
+            Interpolation::Quintic => {
+                if t < 1.0 {
+                    change / 2.0 * t * t * t * t * t + begin
+                } else {
+                    let t = t - 2.0;
+                    change / 2.0 * (t * t * t * t * t + 2.0) + begin
+                }
+            }
+            Interpolation::Sine => -change / 2.0 * ((PI * time / duration).cos() - 1.0) + begin,
+            _ => begin + change * time / duration,
+        }
+    }
+}
+
+fn bounce_out(time: f32, begin: f32, change: f32, duration: f32) -> f32 {
+    let t = time / duration;
+    if t < 1.0 / 2.75 {
+        change * (7.5625 * t * t) + begin
+    } else if t < 2.0 / 2.75 {
+        let t = t - 1.5 / 2.75;
+        change * (7.5625 * t * t + 0.75) + begin
+    } else if t < 2.5 / 2.75 {
+        let t = t - 2.25 / 2.75;
+        change * (7.5625 * t * t + 0.9375) + begin
+    } else {
+        let t = t - 2.625 / 2.75;
+        change * (7.5625 * t * t + 0.984375) + begin
+    }
+}
+
+/// Blender blends the start of elastic curves whose amplitude is smaller than their change
+fn elastic_blend(time: f32, change: f32, duration: f32, amplitude: f32, s: f32, f: f32) -> f32 {
+    if change == 0.0 {
+        return f;
+    }
+
+    let t = s.abs();
+    let mut f = if amplitude != 0.0 {
+        f * amplitude / change.abs()
+    } else {
+        0.0
+    };
+    if (time * duration).abs() < t {
+        let l = (time * duration).abs() / t;
+        f = f * l + (1.0 - l);
+    }
+    f
+}
+
+/// Elastic ease in, or ease out if "out" is true
+fn elastic(
+    time: f32,
+    begin: f32,
+    change: f32,
+    duration: f32,
+    amplitude: f32,
+    period: f32,
+    out: bool,
+) -> f32 {
+    if time == 0.0 {
+        return begin;
+    }
+    let t = time / duration;
+    if t == 1.0 {
+        return begin + change;
+    }
+    let t = if out { -t } else { t - 1.0 };
+    let period = if period == 0.0 {
+        duration * 0.3
+    } else {
+        period
+    };
+
+    let (s, f, amplitude) = if amplitude == 0.0 || amplitude < change.abs() {
+        let s = period / 4.0;
+        (
+            s,
+            elastic_blend(t, change, duration, amplitude, s, 1.0),
+            change,
+        )
+    } else {
+        (
+            period / (2.0 * PI) * (change / amplitude).asin(),
+            1.0,
+            amplitude,
+        )
+    };
+
+    let wave = amplitude * 2f32.powf(10.0 * t) * ((t * duration - s) * (2.0 * PI) / period).sin();
+    if out {
+        f * wave + change + begin
+    } else {
+        -f * wave + begin
+    }
+}
+
+fn elastic_in_out(
+    time: f32,
+    begin: f32,
+    change: f32,
+    duration: f32,
+    amplitude: f32,
+    period: f32,
+) -> f32 {
+    if time == 0.0 {
+        return begin;
+    }
+    let t = time / (duration / 2.0);
+    if t == 2.0 {
+        return begin + change;
+    }
+    let t = t - 1.0;
+    let period = if period == 0.0 {
+        duration * (0.3 * 1.5)
+    } else {
+        period
+    };
+
+    let (s, f, amplitude) = if amplitude == 0.0 || amplitude < change.abs() {
+        let s = period / 4.0;
+        (
+            s,
+            elastic_blend(t, change, duration, amplitude, s, 1.0),
+            change,
+        )
+    } else {
+        (
+            period / (2.0 * PI) * (change / amplitude).asin(),
+            1.0,
+            amplitude,
+        )
+    };
+
+    if t < 0.0 {
+        let wave =
+            amplitude * 2f32.powf(10.0 * t) * ((t * duration - s) * (2.0 * PI) / period).sin();
+        -0.5 * f * wave + begin
+    } else {
+        let t = -t;
+        let wave =
+            amplitude * 2f32.powf(10.0 * t) * ((t * duration - s) * (2.0 * PI) / period).sin();
+        0.5 * f * wave + change + begin
+    }
+}
+
+/// Reads the F-curves of a Blend::Instance action. Curves without keyframes are skipped.
+pub(crate) fn instance_to_fcurves(action: &Instance) -> Vec<FCurve> {
+    let mut fcurves: Vec<FCurve> = Vec::new();
+    if action.is_valid("curves") {
+        fcurves.extend(
+            action
+                .get_iter("curves")
+                .filter_map(|fcurve| instance_to_fcurve(&fcurve, None)),
+        );
+    }
+
+    // Since Blender 4.4 the F-curves are stored in the keyframe strips of the layers of the
+    // action, in one channel bag per slot
+    if action.is_valid("strip_keyframe_data_array") {
+        let strip_count = action.get_i32("strip_keyframe_data_array_num").max(0) as usize;
+        for strip in action
+            .get_iter("strip_keyframe_data_array")
+            .take(strip_count)
+        {
+            if !strip.is_valid("channelbag_array") {
+                continue;
+            }
+            let channelbag_count = strip.get_i32("channelbag_array_num").max(0) as usize;
+            for channelbag in strip.get_iter("channelbag_array").take(channelbag_count) {
+                if !channelbag.is_valid("fcurve_array") {
+                    continue;
+                }
+                let slot_handle = channelbag.get_i32("slot_handle");
+                let fcurve_count = channelbag.get_i32("fcurve_array_num").max(0) as usize;
+                fcurves.extend(
+                    channelbag
+                        .get_iter("fcurve_array")
+                        .take(fcurve_count)
+                        .filter_map(|fcurve| instance_to_fcurve(&fcurve, Some(slot_handle))),
+                );
+            }
+        }
+    }
+
+    fcurves
+}
+
+/// Reads a Blend::Instance F-curve, if it has keyframes
+fn instance_to_fcurve(fcurve: &Instance, slot_handle: Option<i32>) -> Option<FCurve> {
+    if !fcurve.is_valid("bezt") || !fcurve.is_valid("rna_path") {
+        return None;
+    }
+
+    let count = fcurve.get_i32("totvert").max(0) as usize;
+    let mut keyframes: Vec<Keyframe> = fcurve
+        .get_iter("bezt")
+        .take(count)
+        .map(|bezt| instance_to_keyframe(&bezt))
+        .collect();
+    if keyframes.is_empty() {
+        return None;
+    }
+    keyframes.sort_by(|a, b| a.frame.total_cmp(&b.frame));
+
+    let flag = get_int(fcurve, "flag");
+    let cycles = instance_to_cycles(fcurve);
+    Some(FCurve {
+        rna_path: get_pointer_string(fcurve, "rna_path"),
+        array_index: fcurve.get_i32("array_index").max(0) as usize,
+        keyframes,
+        linear_extrapolation: get_int(fcurve, "extend") == FCURVE_EXTRAPOLATE_LINEAR,
+        discrete: flag & FCURVE_DISCRETE_VALUES != 0,
+        integer: flag & FCURVE_INT_VALUES != 0,
+        ignored_modifiers: enabled_modifiers(fcurve) - cycles.is_some() as usize,
+        cycles,
+        slot_handle,
+    })
+}
+
+/// Reads a Blend::Instance BezTriple
+fn instance_to_keyframe(bezt: &Instance) -> Keyframe {
+    // vec holds the left handle, the keyframe and the right handle
+    let vec = bezt.get_f32_vec("vec");
+    // Easings were added in Blender 2.71
+    let has_easing = bezt.fields.contains_key("easing");
+
+    Keyframe {
+        frame: vec[3],
+        value: vec[4],
+        left_handle: Vec2::new(vec[0], vec[1]),
+        right_handle: Vec2::new(vec[6], vec[7]),
+        interpolation: Interpolation::from_ipo(get_int(bezt, "ipo")),
+        easing: match get_int(bezt, "easing") {
+            1 => Easing::In,
+            2 => Easing::Out,
+            3 => Easing::InOut,
+            _ => Easing::Auto,
+        },
+        back: if has_easing {
+            bezt.get_f32("back")
+        } else {
+            1.70158
+        },
+        amplitude: if has_easing {
+            bezt.get_f32("amplitude")
+        } else {
+            0.8
+        },
+        period: if has_easing {
+            bezt.get_f32("period")
+        } else {
+            4.1
+        },
+    }
+}
+
+/// Reads the Cycles modifier of a Blend::Instance F-curve. Blender only applies it when it is the
+/// first modifier of the curve.
+fn instance_to_cycles(fcurve: &Instance) -> Option<Cycles> {
+    if !fcurve.is_valid("modifiers") {
+        return None;
+    }
+
+    let modifier = fcurve.get_iter("modifiers").next()?;
+    if get_int(&modifier, "type") != FMODIFIER_TYPE_CYCLES
+        || get_int(&modifier, "flag") & FMODIFIER_FLAG_MUTED != 0
+        || !modifier.is_valid("data")
+    {
+        return None;
+    }
+
+    let data = modifier.get("data");
+    Some(Cycles {
+        before_mode: get_int(&data, "before_mode"),
+        before_cycles: get_int(&data, "before_cycles"),
+        after_mode: get_int(&data, "after_mode"),
+        after_cycles: get_int(&data, "after_cycles"),
+    })
+}
+
+/// Counts the modifiers of a Blend::Instance F-curve that are not muted
+fn enabled_modifiers(fcurve: &Instance) -> usize {
+    if !fcurve.is_valid("modifiers") {
+        return 0;
+    }
+    fcurve
+        .get_iter("modifiers")
+        .filter(|modifier| get_int(modifier, "flag") & FMODIFIER_FLAG_MUTED == 0)
+        .count()
+}
+
+/// Warns about the curves of the action "label" with modifiers that are not applied, which make
+/// its animation differ from Blender
+pub(crate) fn warn_ignored_modifiers(label: &str, fcurves: &[FCurve]) {
+    for fcurve in fcurves.iter().filter(|fcurve| fcurve.ignored_modifiers > 0) {
+        warn!(
+            "{} modifiers of the F-curve {}[{}] of {} are not supported, its animation differs from Blender",
+            fcurve.ignored_modifiers, fcurve.rna_path, fcurve.array_index, label
+        );
+    }
+}
+
+/// Reads a string field stored as a pointer (char *), which get_string can not read
+fn get_pointer_string(instance: &Instance, name: &str) -> String {
+    instance
+        .get_u8_vec(name)
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A keyframe with the defaults of Blender, and flat handles one frame long
+    fn keyframe(frame: f32, value: f32, interpolation: Interpolation) -> Keyframe {
+        Keyframe {
+            frame,
+            value,
+            left_handle: Vec2::new(frame - 1.0, value),
+            right_handle: Vec2::new(frame + 1.0, value),
+            interpolation,
+            easing: Easing::Auto,
+            back: 1.70158,
+            amplitude: 0.8,
+            period: 4.1,
+        }
+    }
+
+    fn curve(keyframes: Vec<Keyframe>) -> FCurve {
+        FCurve {
+            rna_path: String::from("location"),
+            array_index: 0,
+            keyframes,
+            linear_extrapolation: false,
+            discrete: false,
+            integer: false,
+            cycles: None,
+            ignored_modifiers: 0,
+            slot_handle: None,
+        }
+    }
+
+    // A curve going from 0 at frame 0 to 10 at frame 10
+    fn ramp(interpolation: Interpolation) -> FCurve {
+        curve(vec![
+            keyframe(0.0, 0.0, interpolation),
+            keyframe(10.0, 10.0, interpolation),
+        ])
+    }
+
+    fn assert_values(fcurve: &FCurve, values: &[(f32, f32)]) {
+        for (frame, expected) in values {
+            let value = fcurve.evaluate(*frame);
+            assert!(
+                (value - expected).abs() < 1e-4,
+                "{:?} at frame {frame}: {value} != {expected}",
+                fcurve.keyframes[0].interpolation
+            );
+        }
+    }
+
+    #[test]
+    fn bezier_follows_handles() {
+        // Auto Clamped handles of two keyframes are flat and a third of the segment long
+        let mut fcurve = ramp(Interpolation::Bezier);
+        fcurve.keyframes[0].right_handle = Vec2::new(10.0 / 3.0, 0.0);
+        fcurve.keyframes[1].left_handle = Vec2::new(20.0 / 3.0, 10.0);
+        assert_values(
+            &fcurve,
+            &[
+                (0.0, 0.0),
+                (2.5, 1.5625),
+                (5.0, 5.0),
+                (7.5, 8.4375),
+                (10.0, 10.0),
+            ],
+        );
+
+        // Handles longer than the segment are shortened so that the curve does not go back in time
+        fcurve.keyframes[0].right_handle = Vec2::new(20.0, 0.0);
+        fcurve.keyframes[1].left_handle = Vec2::new(-10.0, 10.0);
+        assert_values(&fcurve, &[(5.0, 5.0)]);
+        let values: Vec<f32> = (0..=40).map(|i| fcurve.evaluate(i as f32 / 4.0)).collect();
+        assert!(values.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn easings_ease_in_by_default() {
+        for (interpolation, quarter, half) in [
+            (Interpolation::Quadratic, 0.625, 2.5),
+            (Interpolation::Cubic, 0.15625, 1.25),
+            (Interpolation::Quartic, 0.0390625, 0.625),
+            (Interpolation::Quintic, 0.009765625, 0.3125),
+            (Interpolation::Sine, 0.7612047, 2.9289322),
+            (Interpolation::Circular, 0.3175416, 1.3397460),
+            (Interpolation::Exponential, 0.0455215, 0.3030303),
+        ] {
+            assert_values(
+                &ramp(interpolation),
+                &[(0.0, 0.0), (2.5, quarter), (5.0, half), (10.0, 10.0)],
+            );
+        }
+    }
+
+    #[test]
+    fn back_bounce_and_elastic_ease_out_by_default() {
+        for (interpolation, quarter, half) in [
+            (Interpolation::Back, 8.174097, 10.876975),
+            (Interpolation::Bounce, 4.7265625, 7.65625),
+            (Interpolation::Elastic, 10.109105, 9.995240),
+        ] {
+            assert_values(
+                &ramp(interpolation),
+                &[(0.0, 0.0), (2.5, quarter), (5.0, half), (10.0, 10.0)],
+            );
+        }
+    }
+
+    #[test]
+    fn constant_and_linear_interpolate() {
+        assert_values(
+            &ramp(Interpolation::Constant),
+            &[(0.0, 0.0), (5.0, 0.0), (9.99, 0.0), (10.0, 10.0)],
+        );
+        assert_values(
+            &ramp(Interpolation::Linear),
+            &[(0.0, 0.0), (2.5, 2.5), (5.0, 5.0), (10.0, 10.0)],
+        );
+    }
+
+    #[test]
+    fn curves_are_extrapolated() {
+        // Constant extrapolation holds the first and last values
+        assert_values(&ramp(Interpolation::Linear), &[(-5.0, 0.0), (15.0, 10.0)]);
+
+        // Linear extrapolation extends linear keyframes towards their neighbor, and others along
+        // their handle
+        let mut fcurve = ramp(Interpolation::Linear);
+        fcurve.linear_extrapolation = true;
+        assert_values(&fcurve, &[(-5.0, -5.0), (15.0, 15.0)]);
+        let mut fcurve = ramp(Interpolation::Bezier);
+        fcurve.linear_extrapolation = true;
+        fcurve.keyframes[0].left_handle = Vec2::new(-1.0, -2.0);
+        fcurve.keyframes[1].right_handle = Vec2::new(11.0, 10.5);
+        assert_values(&fcurve, &[(-5.0, -10.0), (15.0, 12.5)]);
+
+        // Constant keyframes are never extended
+        let mut fcurve = ramp(Interpolation::Constant);
+        fcurve.linear_extrapolation = true;
+        assert_values(&fcurve, &[(-5.0, 0.0), (15.0, 10.0)]);
+    }
+
+    #[test]
+    fn cycles_repeat_the_keyframes() {
+        let cycles = |mode, count| Cycles {
+            before_mode: mode,
+            before_cycles: count,
+            after_mode: mode,
+            after_cycles: count,
+        };
+        let mut fcurve = ramp(Interpolation::Linear);
+
+        // Repeat
+        fcurve.cycles = Some(cycles(1, 0));
+        assert_values(
+            &fcurve,
+            &[(-7.0, 3.0), (13.0, 3.0), (20.0, 10.0), (33.0, 3.0)],
+        );
+
+        // Repeat with offset
+        fcurve.cycles = Some(cycles(FCM_EXTRAPOLATE_CYCLIC_OFFSET, 0));
+        assert_values(&fcurve, &[(-7.0, -7.0), (13.0, 13.0), (33.0, 33.0)]);
+
+        // Repeat mirrored
+        fcurve.cycles = Some(cycles(FCM_EXTRAPOLATE_MIRROR, 0));
+        assert_values(
+            &fcurve,
+            &[(-7.0, 7.0), (13.0, 7.0), (23.0, 3.0), (-13.0, 7.0)],
+        );
+
+        // A limited number of cycles, after which the curve is extrapolated
+        fcurve.cycles = Some(cycles(1, 1));
+        assert_values(
+            &fcurve,
+            &[(-7.0, 3.0), (13.0, 3.0), (-13.0, 0.0), (23.0, 10.0)],
+        );
+    }
+}
@@ -0,0 +1,564 @@
+use bevy_ecs::{component::Component, entity::Entity, system::Query};
+use bevy_hierarchy::HierarchyQueryExt;
+use bevy_math::{Mat4, Quat, Vec3};
+use bevy_transform::prelude::GlobalTransform;
+use blend::Instance;
+
+use crate::{
+    constraint::{self, ConstraintHierarchy},
+    coordinates::Conversion,
+    get_int, object,
+};
+
+// bConstraint.type value of IK constraints
+const CONSTRAINT_TYPE_KINEMATIC: i32 = 3;
+
+// bKinematicConstraint.flag bit of chains reaching the target with the tail of their bone
+const CONSTRAINT_IK_TIP: i32 = 1 << 0;
+
+/// The IK constraint of a bone, added to its joint entity. After object constraints are
+/// evaluated, the joints of its chain are rotated so that the tail of the bone reaches the target,
+/// and their GlobalTransform (and the ones of their descendants) replaced, leaving their Transform
+/// untouched. Targets are found by Name, first in the hierarchy spawned along with the bone so
+/// that every copy of a rig reaches for its own targets. Moving them at runtime moves the chain.
+#[derive(Component, Debug, Clone)]
+pub struct BlenderIkConstraint {
+    /// Name of the constraint
+    pub name: String,
+    /// Blend factor of the solved chain with its pose, from 0 to 1
+    pub influence: f32,
+    /// Name of the target object
+    pub target: String,
+    /// Name of the target bone of an armature target
+    pub subtarget: Option<String>,
+    /// Name of the pole target object, which the chain bends towards
+    pub pole_target: Option<String>,
+    /// Name of the pole target bone of an armature pole target
+    pub pole_subtarget: Option<String>,
+    /// Angle between the X axis of the root bone and the pole target, around the chain, in radians
+    pub pole_angle: f32,
+    /// Number of bones of the chain, counted from its tip, limited to the bones of the armature
+    pub chain_length: usize,
+    /// Whether the tail of the bone reaches the target, rather than its head (the chain then
+    /// ending at its parent)
+    pub use_tail: bool,
+    /// Maximum number of iterations of the solver
+    pub iterations: usize,
+    /// Solver of chains of more than two bones
+    pub solver: BlenderIkSolver,
+    /// Tail of the tip bone of the chain, in the space of its joint
+    tail: Vec3,
+    /// X axis of bones, in the space of their joint
+    bone_x: Vec3,
+    /// Root entity spawned by spawn_blender_object along with the bone, whose hierarchy targets
+    /// are looked up in before the rest of the world
+    pub(crate) root: Option<Entity>,
+}
+
+/// Solver of the IK chains of more than two bones, chains of two bones being solved analytically
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlenderIkSolver {
+    /// Forward And Backward Reaching Inverse Kinematics
+    #[default]
+    Fabrik,
+    /// Cyclic Coordinate Descent
+    Ccd,
+}
+
+/// Reads the IK constraint of the pose channel of a bone of an armature object, if it has an
+/// enabled one with a target. Targetless (auto) IK is not supported.
+pub(crate) fn bone_ik_constraint(
+    obj: &Instance,
+    bone: &Instance,
+    conversion: &Conversion,
+) -> Option<BlenderIkConstraint> {
+    if !obj.is_valid("pose") {
+        return None;
+    }
+    let bone_name = bone.get_string("name");
+    let channel = obj
+        .get("pose")
+        .get_iter("chanbase")
+        .find(|channel| channel.get_string("name") == bone_name)?;
+    if !channel.is_valid("constraints") {
+        return None;
+    }
+    let constraint = channel.get_iter("constraints").find(|constraint| {
+        get_int(constraint, "type") == CONSTRAINT_TYPE_KINEMATIC
+            && get_int(constraint, "flag")
+                & (constraint::CONSTRAINT_DISABLE | constraint::CONSTRAINT_OFF)
+                == 0
+            && constraint.is_valid("data")
+    })?;
+    let data = constraint.get("data");
+    if !data.is_valid("tar") {
+        return None;
+    }
+
+    // Without its tail, the chain ends at the parent of the bone
+    let use_tail = get_int(&data, "flag") & CONSTRAINT_IK_TIP != 0;
+    let tip = if use_tail {
+        bone.clone()
+    } else if bone.is_valid("parent") {
+        bone.get("parent")
+    } else {
+        return None;
+    };
+    let mut depth = 1;
+    let mut current = tip.clone();
+    while current.is_valid("parent") {
+        current = current.get("parent");
+        depth += 1;
+    }
+    let chain_length = match get_int(&data, "rootbone") {
+        length if length > 0 => (length as usize).min(depth),
+        _ => depth,
+    };
+
+    let name = |field: &str| Some(data.get_string(field)).filter(|subtarget| !subtarget.is_empty());
+    Some(BlenderIkConstraint {
+        name: constraint.get_string("name"),
+        influence: constraint.get_f32("enforce"),
+        target: object::object_name(&data.get("tar")),
+        subtarget: name("subtarget"),
+        pole_target: data
+            .is_valid("poletar")
+            .then(|| object::object_name(&data.get("poletar"))),
+        pole_subtarget: name("polesubtarget"),
+        pole_angle: data.get_f32("poleangle"),
+        chain_length,
+        use_tail,
+        iterations: get_int(&data, "iterations").max(1) as usize,
+        solver: BlenderIkSolver::default(),
+        tail: conversion.position(Vec3::new(0.0, tip.get_f32("length"), 0.0)),
+        bone_x: conversion.direction(Vec3::X),
+        root: None,
+    })
+}
+
+/// Solves the IK constraints of every joint with a BlenderIkConstraint, parents before their
+/// children, and replaces the GlobalTransform of the joints of their chains with the result. Every
+/// frame starts from the pose of the chain, so that solved chains do not build up on their result.
+pub(crate) fn solve_ik_constraints(
+    owners: Query<(Entity, &BlenderIkConstraint)>,
+    mut hierarchy: ConstraintHierarchy,
+) {
+    let mut owners: Vec<_> = owners.iter().collect();
+    owners.sort_by_key(|(entity, _)| hierarchy.parents.iter_ancestors(*entity).count());
+
+    for (entity, ik) in owners {
+        solve_chain(entity, ik, &mut hierarchy);
+    }
+}
+
+/// Solves the chain of the IK constraint of the joint "entity"
+fn solve_chain(
+    entity: Entity,
+    ik: &BlenderIkConstraint,
+    hierarchy: &mut ConstraintHierarchy,
+) -> Option<()> {
+    let target = hierarchy
+        .target_global(ik.root, &ik.target, ik.subtarget.as_deref())?
+        .translation();
+    let pole = ik.pole_target.as_ref().and_then(|pole| {
+        hierarchy
+            .target_global(ik.root, pole, ik.pole_subtarget.as_deref())
+            .map(|pole| pole.translation())
+    });
+
+    // The joints of the chain, from its root to its tip
+    let tip = if ik.use_tail {
+        entity
+    } else {
+        hierarchy.parents.get(entity).ok()?.get()
+    };
+    let mut chain = vec![tip];
+    while chain.len() < ik.chain_length {
+        chain.push(hierarchy.parents.get(*chain.last()?).ok()?.get());
+    }
+    chain.reverse();
+
+    // The pose of the chain, from the Transform of its joints, as their GlobalTransform may still
+    // hold the chain solved in the previous frame. Joints with constraints were evaluated already.
+    let mut globals: Vec<Mat4> = Vec::with_capacity(chain.len());
+    for joint in &chain {
+        let global = match globals.last() {
+            _ if hierarchy.constrained.contains(*joint) => *hierarchy.globals.get(*joint).ok()?,
+            Some(parent) => GlobalTransform::from(*parent)
+                .mul_transform(*hierarchy.transforms.get(*joint).ok()?),
+            None => hierarchy.pose_global(*joint)?,
+        };
+        globals.push(global.compute_matrix());
+    }
+
+    // The heads of the bones, followed by the tail of the tip
+    let mut points: Vec<Vec3> = globals
+        .iter()
+        .map(|global| global.w_axis.truncate())
+        .collect();
+    points.push(globals.last()?.transform_point3(ik.tail));
+    let solved = match (points.len(), ik.solver) {
+        (3, _) => two_bone(&points, target),
+        (_, BlenderIkSolver::Fabrik) => fabrik(&points, target, ik.iterations),
+        (_, BlenderIkSolver::Ccd) => ccd(&points, target, ik.iterations),
+    };
+
+    // Every bone follows its solved parent, then turns its next point towards its solved position
+    let mut solved_globals: Vec<Mat4> = Vec::with_capacity(globals.len());
+    for (i, global) in globals.iter().enumerate() {
+        let current = match solved_globals.last() {
+            Some(parent) => *parent * globals[i - 1].inverse() * *global,
+            None => *global,
+        };
+        let head = current.w_axis.truncate();
+        let next = current.transform_point3(global.inverse().transform_point3(points[i + 1]));
+        let rotation = match (
+            (next - head).try_normalize(),
+            (solved[i + 1] - head).try_normalize(),
+        ) {
+            (Some(from), Some(to)) => Quat::from_rotation_arc(from, to),
+            _ => Quat::IDENTITY,
+        };
+        solved_globals.push(rotate_around(current, rotation, head));
+    }
+
+    // The chain is then twisted around the line from its root to its tip, so that the X axis of
+    // its root bone, rotated by the pole angle, points at the pole target
+    if let Some(pole) = pole {
+        let root = solved[0];
+        let axis = (*solved.last()? - root).try_normalize()?;
+        let project = |vector: Vec3| (vector - axis * vector.dot(axis)).try_normalize();
+        if let (Some(x), Some(pole)) = (
+            project(solved_globals[0].transform_vector3(ik.bone_x)),
+            project(pole - root),
+        ) {
+            let x = Quat::from_axis_angle(axis, ik.pole_angle) * x;
+            let twist = Quat::from_axis_angle(axis, x.cross(pole).dot(axis).atan2(x.dot(pole)));
+            for global in &mut solved_globals {
+                *global = rotate_around(*global, twist, root);
+            }
+        }
+    }
+
+    for ((joint, global), solved) in chain.iter().zip(&globals).zip(solved_globals) {
+        let blended = constraint::interpolate(*global, solved, ik.influence);
+        hierarchy.set_global(*joint, GlobalTransform::from(blended));
+    }
+    Some(())
+}
+
+/// Rotates a matrix by "rotation" around "pivot"
+fn rotate_around(matrix: Mat4, rotation: Quat, pivot: Vec3) -> Mat4 {
+    Mat4::from_translation(pivot)
+        * Mat4::from_quat(rotation)
+        * Mat4::from_translation(-pivot)
+        * matrix
+}
+
+/// Solves a chain of two bones analytically, keeping the plane it bends in. Out of reach targets
+/// straighten the chain towards them.
+fn two_bone(points: &[Vec3], target: Vec3) -> Vec<Vec3> {
+    let (root, joint, end) = (points[0], points[1], points[2]);
+    let (upper, lower) = (root.distance(joint), joint.distance(end));
+    let Some(direction) = (target - root).try_normalize() else {
+        return points.to_vec();
+    };
+    if upper <= 0.0 || lower <= 0.0 {
+        return fabrik(points, target, 1);
+    }
+    let distance = root
+        .distance(target)
+        .clamp((upper - lower).abs().max(f32::EPSILON), upper + lower);
+
+    // The joint stays on the side of the line from the root to the target it bends to
+    let bend = joint - root;
+    let bend = (bend - direction * bend.dot(direction))
+        .try_normalize()
+        .unwrap_or_else(|| direction.any_orthonormal_vector());
+    let cos = ((upper * upper + distance * distance - lower * lower) / (2.0 * upper * distance))
+        .clamp(-1.0, 1.0);
+    vec![
+        root,
+        root + (direction * cos + bend * (1.0 - cos * cos).sqrt()) * upper,
+        root + direction * distance,
+    ]
+}
+
+/// Solves a chain with FABRIK, alternately moving its points from the target to the root and from
+/// the root to the target. Out of reach targets straighten the chain towards them.
+fn fabrik(points: &[Vec3], target: Vec3, iterations: usize) -> Vec<Vec3> {
+    let lengths: Vec<f32> = points
+        .windows(2)
+        .map(|segment| segment[0].distance(segment[1]))
+        .collect();
+    let total: f32 = lengths.iter().sum();
+    let root = points[0];
+    let mut solved = points.to_vec();
+    let last = solved.len() - 1;
+
+    if root.distance(target) >= total {
+        let direction = (target - root).normalize_or_zero();
+        for (i, length) in lengths.iter().enumerate() {
+            solved[i + 1] = solved[i] + direction * *length;
+        }
+        return solved;
+    }
+
+    for _ in 0..iterations {
+        if solved[last].distance(target) <= total * 1e-5 {
+            break;
+        }
+        solved[last] = target;
+        for (i, length) in lengths.iter().enumerate().rev() {
+            solved[i] = solved[i + 1] + (solved[i] - solved[i + 1]).normalize_or_zero() * *length;
+        }
+        solved[0] = root;
+        for (i, length) in lengths.iter().enumerate() {
+            solved[i + 1] = solved[i] + (solved[i + 1] - solved[i]).normalize_or_zero() * *length;
+        }
+    }
+    solved
+}
+
+/// Solves a chain with CCD, rotating its bones from the tip to the root so that the end of the
+/// chain points at the target
+fn ccd(points: &[Vec3], target: Vec3, iterations: usize) -> Vec<Vec3> {
+    let total: f32 = points
+        .windows(2)
+        .map(|segment| segment[0].distance(segment[1]))
+        .sum();
+    let mut solved = points.to_vec();
+    let last = solved.len() - 1;
+
+    for _ in 0..iterations {
+        if solved[last].distance(target) <= total * 1e-5 {
+            break;
+        }
+        for i in (0..last).rev() {
+            let pivot = solved[i];
+            let (from, to) = (solved[last] - pivot, target - pivot);
+
+            // Quat::from_rotation_arc does not rotate by angles below about 1e-3 radians, which
+            // would stop the chain short of the target
+            let cross = from.cross(to);
+            let rotation = match cross.try_normalize() {
+                Some(axis) => Quat::from_axis_angle(axis, cross.length().atan2(from.dot(to))),
+                None => match (from.try_normalize(), to.try_normalize()) {
+                    (Some(from), Some(to)) if from.dot(to) < 0.0 => {
+                        Quat::from_rotation_arc(from, to)
+                    }
+                    _ => continue,
+                },
+            };
+            for point in &mut solved[i + 1..] {
+                *point = pivot + rotation * (*point - pivot);
+            }
+        }
+    }
+    solved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_core::Name;
+    use bevy_ecs::{schedule::Schedule, world::World};
+    use bevy_hierarchy::BuildWorldChildren;
+    use bevy_transform::prelude::Transform;
+
+    // Iterations of the IK solvers by default in Blender
+    const ITERATIONS: usize = 500;
+
+    // A chain of three bones of lengths 1, 2 and 1.5, bent in the XY plane
+    fn chain() -> Vec<Vec3> {
+        vec![
+            Vec3::ZERO,
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(1.0, 1.0 + 3f32.sqrt(), 0.0),
+            Vec3::new(2.5, 1.0 + 3f32.sqrt(), 0.0),
+        ]
+    }
+
+    fn lengths(points: &[Vec3]) -> Vec<f32> {
+        points
+            .windows(2)
+            .map(|segment| segment[0].distance(segment[1]))
+            .collect()
+    }
+
+    fn assert_lengths_kept(points: &[Vec3], solved: &[Vec3]) {
+        for (length, solved_length) in lengths(points).iter().zip(lengths(solved)) {
+            assert!(
+                (length - solved_length).abs() < 1e-3,
+                "{solved_length} != {length}"
+            );
+        }
+    }
+
+    // Whether the points after the root are on a straight line towards "target"
+    fn assert_straight_towards(solved: &[Vec3], target: Vec3) {
+        let direction = (target - solved[0]).normalize();
+        for point in &solved[1..] {
+            let along = (*point - solved[0]).normalize();
+            assert!(along.abs_diff_eq(direction, 1e-3), "{along} != {direction}");
+        }
+    }
+
+    #[test]
+    fn two_bone_reaches_targets_in_reach() {
+        let points = &chain()[..3];
+        for target in [
+            Vec3::new(1.0, 2.0, 0.0),
+            Vec3::new(-1.5, 0.5, 1.0),
+            Vec3::new(0.0, 0.0, -2.5),
+        ] {
+            let solved = two_bone(points, target);
+            assert_eq!(solved[0], points[0]);
+            assert!(
+                solved[2].abs_diff_eq(target, 1e-3),
+                "{} != {target}",
+                solved[2]
+            );
+            assert_lengths_kept(points, &solved);
+        }
+    }
+
+    #[test]
+    fn two_bone_keeps_its_bend_plane() {
+        let points = &chain()[..3];
+        let target = Vec3::new(0.0, 2.0, 1.0);
+        let solved = two_bone(points, target);
+
+        // The joint stays on the side of the line from the root to the target it was bent to
+        let direction = target.normalize();
+        let side = |joint: Vec3| (joint - direction * joint.dot(direction)).normalize();
+        assert!(side(solved[1]).abs_diff_eq(side(points[1]), 1e-3));
+        assert!(solved[1]
+            .cross(solved[2])
+            .normalize()
+            .abs_diff_eq(points[1].cross(target).normalize(), 1e-3));
+    }
+
+    #[test]
+    fn two_bone_straightens_towards_targets_out_of_reach() {
+        let points = &chain()[..3];
+        let target = Vec3::new(3.0, -4.0, 5.0);
+        let solved = two_bone(points, target);
+        assert_lengths_kept(points, &solved);
+        assert_straight_towards(&solved, target);
+    }
+
+    #[test]
+    fn fabrik_reaches_targets_in_reach() {
+        let points = chain();
+        for target in [
+            Vec3::new(2.0, 2.0, 0.0),
+            Vec3::new(-1.0, 1.5, 2.0),
+            Vec3::new(0.5, -3.0, -1.0),
+        ] {
+            let solved = fabrik(&points, target, ITERATIONS);
+            assert_eq!(solved[0], points[0]);
+            assert!(
+                solved[3].abs_diff_eq(target, 1e-3),
+                "{} != {target}",
+                solved[3]
+            );
+            assert_lengths_kept(&points, &solved);
+        }
+    }
+
+    #[test]
+    fn fabrik_straightens_towards_targets_out_of_reach() {
+        let points = chain();
+        let target = Vec3::new(-6.0, 2.0, 3.0);
+        let solved = fabrik(&points, target, ITERATIONS);
+        assert_lengths_kept(&points, &solved);
+        assert_straight_towards(&solved, target);
+    }
+
+    #[test]
+    fn ccd_reaches_targets_in_reach() {
+        let points = chain();
+        for target in [
+            Vec3::new(2.0, 2.0, 0.0),
+            Vec3::new(-1.0, 1.5, 2.0),
+            Vec3::new(0.5, -3.0, -1.0),
+        ] {
+            let solved = ccd(&points, target, ITERATIONS);
+            assert_eq!(solved[0], points[0]);
+            assert!(
+                solved[3].abs_diff_eq(target, 1e-3),
+                "{} != {target}",
+                solved[3]
+            );
+            assert_lengths_kept(&points, &solved);
+        }
+    }
+
+    #[test]
+    fn ccd_straightens_towards_targets_out_of_reach() {
+        let points = chain();
+        let target = Vec3::new(-6.0, 2.0, 3.0);
+        let solved = ccd(&points, target, ITERATIONS);
+        assert_lengths_kept(&points, &solved);
+        assert_straight_towards(&solved, target);
+    }
+
+    #[test]
+    fn solved_chains_do_not_build_up_over_frames() {
+        let mut world = World::new();
+        world.spawn((
+            Name::new("Target"),
+            Transform::from_xyz(1.0, 1.0, 0.0),
+            GlobalTransform::from_xyz(1.0, 1.0, 0.0),
+        ));
+
+        // Two bones of length 1, the lower one slightly bent
+        let upper = world
+            .spawn((Transform::IDENTITY, GlobalTransform::IDENTITY))
+            .id();
+        let lower_transform =
+            Transform::from_xyz(0.0, 1.0, 0.0).with_rotation(Quat::from_rotation_z(-0.2));
+        let lower = world
+            .spawn((
+                lower_transform,
+                GlobalTransform::from(lower_transform),
+                BlenderIkConstraint {
+                    name: String::from("IK"),
+                    influence: 0.5,
+                    target: String::from("Target"),
+                    subtarget: None,
+                    pole_target: None,
+                    pole_subtarget: None,
+                    pole_angle: 0.0,
+                    chain_length: 2,
+                    use_tail: true,
+                    iterations: ITERATIONS,
+                    solver: BlenderIkSolver::default(),
+                    tail: Vec3::Y,
+                    bone_x: Vec3::X,
+                    root: None,
+                },
+            ))
+            .id();
+        world.entity_mut(upper).push_children(&[lower]);
+
+        let mut schedule = Schedule::new();
+        schedule.add_system(solve_ik_constraints);
+
+        // Transforms do not change, so they are not propagated again between the frames
+        schedule.run(&mut world);
+        let first = *world.get::<GlobalTransform>(lower).unwrap();
+        assert_ne!(first, GlobalTransform::from(lower_transform));
+        schedule.run(&mut world);
+        schedule.run(&mut world);
+
+        let global = world.get::<GlobalTransform>(lower).unwrap();
+        assert!(
+            global
+                .compute_matrix()
+                .abs_diff_eq(first.compute_matrix(), 1e-5),
+            "{global:?} != {first:?}"
+        );
+    }
+}
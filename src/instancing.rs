@@ -0,0 +1,143 @@
+use bevy_math::{Mat3, Mat4, Quat, Vec3};
+use blend::Instance;
+
+use crate::{mesh, object::OB_MESH};
+
+// Object.transflag bits used for vertex and face instancing (DNA_object_types.h)
+const OB_DUPLIVERTS: i16 = 1 << 4;
+const OB_DUPLIROT: i16 = 1 << 5;
+const OB_DUPLIFACES: i16 = 1 << 9;
+const OB_DUPLIFACES_SCALE: i16 = 1 << 10;
+
+/// Returns true if the object is a mesh object that instances its children at each of its
+/// vertices or faces
+pub(crate) fn is_instancer(object: &Instance) -> bool {
+    object.get_i16("type") == OB_MESH
+        && object.is_valid("data")
+        && object.get_i16("transflag") & (OB_DUPLIVERTS | OB_DUPLIFACES) != 0
+}
+
+/// Returns the matrices (in Blender coordinates) of every instance of "child" created by the
+/// vertex or face instancing of "parent", relative to the world matrix of "parent", given the
+/// matrix of "child" relative to it. This mirrors the dupli code in Blender, where the child keeps
+/// its own transform and is offset by each vertex/face of the parent mesh.
+pub(crate) fn instance_matrices(
+    parent: &Instance,
+    child: &Instance,
+    child_matrix: Mat4,
+    blend_version: (u8, u8, u8),
+) -> Vec<Mat4> {
+    let transflag = parent.get_i16("transflag");
+    let parent_mesh = parent.get("data");
+    let (positions, normals) = mesh::instance_to_vertices(&parent_mesh, blend_version);
+
+    // Relative transform from parent to child space, used to offset the instances
+    let child_imat = Mat3::from_mat4(child_matrix.inverse());
+
+    // Every instance is placed at a local transform, then moved by the child's own transform
+    let place = |location: Vec3, rotation: Quat, scale: f32| {
+        child_matrix.mul_mat4(&Mat4::from_scale_rotation_translation(
+            Vec3::splat(scale),
+            rotation,
+            child_imat.mul_vec3(location),
+        ))
+    };
+
+    if transflag & OB_DUPLIVERTS != 0 {
+        let use_rotation = transflag & OB_DUPLIROT != 0;
+        let track_axis = flag_to_axis(child.get_i16("trackflag"));
+        let up_axis = flag_to_axis(child.get_i16("upflag"));
+
+        return positions
+            .iter()
+            .zip(normals.iter())
+            .map(|(position, normal)| {
+                let rotation = if use_rotation {
+                    vector_to_quat(*normal, track_axis, up_axis)
+                } else {
+                    Quat::IDENTITY
+                };
+                place(*position, rotation, 1.0)
+            })
+            .collect();
+    }
+
+    // Older versions of Blender call the face scale factor "dupfacesca"
+    let use_scale = transflag & OB_DUPLIFACES_SCALE != 0;
+    let scale_factor = if parent.is_valid("instance_faces_scale") {
+        parent.get_f32("instance_faces_scale")
+    } else {
+        parent.get_f32("dupfacesca")
+    };
+
+    mesh::instance_to_face_loops(&parent_mesh)
+        .iter()
+        .filter(|face| face.len() >= 3)
+        .map(|face| {
+            let coords: Vec<Vec3> = face.iter().map(|v| positions[*v as usize]).collect();
+
+            // Face center is the mean of its vertices
+            let center = coords.iter().copied().sum::<Vec3>() / coords.len() as f32;
+
+            // Newell's method gives both the face normal and (by its length) twice the face area
+            let mut cross = Vec3::ZERO;
+            for i in 0..coords.len() {
+                cross += coords[i].cross(coords[(i + 1) % coords.len()]);
+            }
+            let normal = cross.normalize_or_zero();
+
+            // Z follows the face normal and X follows the first edge of the face
+            let x_axis = (coords[1] - coords[0])
+                .reject_from(normal)
+                .normalize_or_zero();
+            let rotation = if normal == Vec3::ZERO || x_axis == Vec3::ZERO {
+                Quat::IDENTITY
+            } else {
+                Quat::from_mat3(&Mat3::from_cols(x_axis, normal.cross(x_axis), normal))
+            };
+
+            let scale = if use_scale {
+                (0.5 * cross.length()).sqrt() * scale_factor
+            } else {
+                1.0
+            };
+
+            place(center, rotation, scale)
+        })
+        .collect()
+}
+
+/// Converts an Object.trackflag/upflag value (+X, +Y, +Z, -X, -Y, -Z) to a unit vector
+pub(crate) fn flag_to_axis(flag: i16) -> Vec3 {
+    match flag {
+        0 => Vec3::X,
+        1 => Vec3::Y,
+        2 => Vec3::Z,
+        3 => Vec3::NEG_X,
+        4 => Vec3::NEG_Y,
+        _ => Vec3::NEG_Z,
+    }
+}
+
+/// Returns a rotation that points "track_axis" along "vector", then turns around it so that
+/// "up_axis" points as close to Z as possible (Blender's vec_to_quat)
+pub(crate) fn vector_to_quat(vector: Vec3, track_axis: Vec3, up_axis: Vec3) -> Quat {
+    let vector = vector.normalize_or_zero();
+    if vector == Vec3::ZERO {
+        return Quat::IDENTITY;
+    }
+
+    let track = Quat::from_rotation_arc(track_axis, vector);
+
+    // Project the current and the desired up vectors onto the plane perpendicular to the track
+    let current_up = (track * up_axis).reject_from(vector);
+    let desired_up = Vec3::Z.reject_from(vector);
+    if current_up.length_squared() < 1e-12 || desired_up.length_squared() < 1e-12 {
+        return track;
+    }
+
+    let angle = current_up.angle_between(desired_up);
+    let sign = current_up.cross(desired_up).dot(vector).signum();
+
+    Quat::from_axis_angle(vector, sign * angle) * track
+}
@@ -12,6 +12,24 @@
 //! * Meshes (using `AssetServer`)
 //! * Basic. not node-based, materials (using `AssetServer`)
 //! * Objects (using `BlenderObjectBundle`)
+//! * Vertex and face instancing (using `spawn_blender_object`)
+//! * Particle system instances (using `spawn_blender_object`)
+//! * World background, ambient light, mist and environment lighting (using `AssetServer`)
+//! * Light probes (using `spawn_blender_object`)
+//! * Armatures and skinned meshes (using `spawn_blender_object`)
+//! * Object transform and skeletal animations (using `AssetServer` and the `AnimationPlayer` added by `spawn_blender_object`)
+//! * NLA strips and action libraries (using the `BlenderAnimationClips` added by `spawn_blender_object`)
+//! * Shape keys as morph targets, blended on the CPU from the `MorphWeights` added by `spawn_blender_object`, and animated by `BlenderPropertyClip`s
+//! * Lights and cameras (using `spawn_blender_object`), with their material, light, camera and visibility properties animated by `BlenderPropertyClip`s
+//! * Root motion extracted from a root bone, applied by `BlenderRootMotionPlayer`
+//! * Snapshots of animated objects and armature poses at a given frame (using `spawn_blender_object_at_frame`)
+//! * Track To, Damped Track, Copy Location, Copy Rotation, Child Of and Limit Rotation object constraints, evaluated every frame from the `BlenderConstraints` added by `spawn_blender_object`
+//! * IK constraints of bones, with their chain length and pole target, solved every frame on the joints from the `BlenderIkConstraint` added by `spawn_blender_object`
+//! * Pose assets, single frame actions marked as assets, as `BlenderPose`s applied and blended onto armatures by `BlenderPosePlayer`
+//! * Shape key and cloth point cache animations baked to vertex animation textures, as `BlenderVertexAnimation`s played by `BlenderVertexAnimationPlayer`
+//! * Scene frame rate and frame range, as the `BlenderSceneTiming` resource
+//! * Axis remapping and global scale of Blender coordinates, along with the scene unit scale (using the `BlenderCoordinateSettings` resource)
+//! * Scene and pose markers, sent as `BlenderMarkerReached` events by `BlenderPropertyPlayer`s, switching the active camera for markers bound to cameras
 //!
 //! *If the asset name in Blender starts with an underscore, it will not be loaded. You can use this to have extra assets in the .blend file that you do not want loaded to the AssetServer.*
 //!
@@ -45,28 +63,131 @@
 
 // use bevy::prelude::*;
 
+use bevy_animation::AnimationClip;
 use bevy_app::{App, Plugin};
 use bevy_asset::{AddAsset, AssetLoader, LoadContext, LoadedAsset};
+use bevy_ecs::{
+    schedule::IntoSystemConfig,
+    world::{FromWorld, World},
+};
 use bevy_log::{info, warn};
 use bevy_math::{Mat4, Quat, Vec3};
 use bevy_pbr::StandardMaterial;
 use bevy_render::color::Color;
-    
+
 use bevy_utils::BoxedFuture;
-use blend::Blend;
+use blend::{Blend, Instance};
+
+use coordinates::Conversion;
 
+mod animation;
+mod armature;
+mod camera;
+mod constraint;
+mod coordinates;
+mod environment;
+mod fcurve;
+mod ik;
+mod instancing;
+mod light;
+mod light_probe;
+mod marker;
 mod material;
 mod mesh;
+mod morph;
+mod nodes;
 mod object;
+mod parenting;
+mod particles;
+mod pose;
+mod property;
+mod root_motion;
+mod scene;
+mod vertex_animation;
+mod world;
 
-pub use object::{spawn_blender_object, BlenderObjectBundle};
+pub use animation::{BlenderAnimationClips, BlenderAnimationSampling, BlenderAnimationSettings};
+pub use camera::{BlenderCamera, BlenderSensorFit};
+pub use constraint::{
+    BlenderConstraint, BlenderConstraintKind, BlenderConstraintSpace, BlenderConstraints,
+    BlenderRotationMix,
+};
+pub use coordinates::{BlenderAxis, BlenderCoordinateSettings};
+pub use ik::{BlenderIkConstraint, BlenderIkSolver};
+pub use light_probe::{BlenderLightProbe, BlenderLightProbeKind, BlenderLightProbeShape};
+pub use marker::{BlenderMarker, BlenderMarkerReached};
+pub use morph::{BlenderMorphTargets, MorphWeights};
+pub use object::{spawn_blender_object, spawn_blender_object_at_frame, BlenderObjectBundle};
+pub use pose::{BlenderPose, BlenderPosePlayer};
+pub use property::{
+    BlenderPropertyChannel, BlenderPropertyClip, BlenderPropertyCurve, BlenderPropertyPlayer,
+};
+pub use root_motion::{BlenderRootMotion, BlenderRootMotionPlayer};
+pub use scene::BlenderSceneTiming;
+pub use vertex_animation::{
+    BlenderVertexAnimation, BlenderVertexAnimationBake, BlenderVertexAnimationPlayer,
+    ATTRIBUTE_VERTEX_ANIMATION_UV,
+};
+pub use world::BlenderWorld;
 
 /// Plugin for Bevy that allows for interaction with .blend files
 pub struct BlenderPlugin;
 
 impl Plugin for BlenderPlugin {
     fn build(&self, app: &mut App) {
-        app.init_asset_loader::<BlenderLoader>();
+        app.init_resource::<BlenderCoordinateSettings>()
+            .init_resource::<BlenderAnimationSettings>()
+            .init_resource::<BlenderSceneTiming>()
+            .add_asset::<AnimationClip>()
+            .add_asset::<BlenderPropertyClip>()
+            .add_asset::<BlenderMorphTargets>()
+            .add_asset::<BlenderPose>()
+            .add_asset::<BlenderRootMotion>()
+            .add_asset::<BlenderSceneTiming>()
+            .add_asset::<BlenderVertexAnimation>()
+            .add_asset::<BlenderWorld>()
+            .add_event::<BlenderMarkerReached>()
+            .init_asset_loader::<BlenderLoader>()
+            .add_system(world::apply_blender_world)
+            .add_system(scene::insert_scene_timing)
+            .add_system(light_probe::attach_light_probe_environment_maps)
+            .add_system(armature::attach_skinned_meshes)
+            // Property clips follow the AnimationPlayer, which is advanced in PostUpdate
+            .add_system(
+                property::play_property_clips
+                    .in_base_set(bevy_app::CoreSet::PostUpdate)
+                    .after(bevy_animation::animation_player)
+                    .before(bevy_render::view::VisibilitySystems::VisibilityPropagate)
+                    .before(bevy_render::camera::CameraUpdateSystem),
+            )
+            .add_system(
+                morph::apply_morph_weights
+                    .in_base_set(bevy_app::CoreSet::PostUpdate)
+                    .after(property::play_property_clips),
+            )
+            .add_system(
+                marker::switch_marker_cameras
+                    .in_base_set(bevy_app::CoreSet::PostUpdate)
+                    .after(property::play_property_clips),
+            )
+            .add_system(root_motion::apply_root_motion)
+            .add_system(vertex_animation::play_vertex_animations)
+            .add_system(
+                pose::apply_poses
+                    .in_base_set(bevy_app::CoreSet::PostUpdate)
+                    .after(bevy_animation::animation_player)
+                    .before(bevy_transform::TransformSystem::TransformPropagate),
+            )
+            .add_system(
+                constraint::evaluate_constraints
+                    .in_base_set(bevy_app::CoreSet::PostUpdate)
+                    .after(bevy_transform::TransformSystem::TransformPropagate),
+            )
+            .add_system(
+                ik::solve_ik_constraints
+                    .in_base_set(bevy_app::CoreSet::PostUpdate)
+                    .after(constraint::evaluate_constraints),
+            );
     }
 }
 
@@ -106,8 +227,25 @@ pub enum BevyBlenderError {
     },
 }
 
-#[derive(Default)]
-struct BlenderLoader;
+struct BlenderLoader {
+    animation_settings: BlenderAnimationSettings,
+    coordinate_settings: BlenderCoordinateSettings,
+}
+
+impl FromWorld for BlenderLoader {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            animation_settings: world
+                .get_resource::<BlenderAnimationSettings>()
+                .cloned()
+                .unwrap_or_default(),
+            coordinate_settings: world
+                .get_resource::<BlenderCoordinateSettings>()
+                .copied()
+                .unwrap_or_default(),
+        }
+    }
+}
 
 impl AssetLoader for BlenderLoader {
     fn load<'a>(
@@ -115,7 +253,7 @@ impl AssetLoader for BlenderLoader {
         bytes: &'a [u8],
         load_context: &'a mut LoadContext,
     ) -> BoxedFuture<'a, anyhow::Result<()>> {
-        Box::pin(async move { Ok(load_blend_assets(bytes, load_context).await?) })
+        Box::pin(async move { Ok(load_blend_assets(self, bytes, load_context).await?) })
     }
 
     fn extensions(&self) -> &[&str] {
@@ -125,6 +263,7 @@ impl AssetLoader for BlenderLoader {
 }
 
 async fn load_blend_assets<'a, 'b>(
+    loader: &'a BlenderLoader,
     bytes: &'a [u8],
     load_context: &'a mut LoadContext<'b>,
 ) -> anyhow::Result<()> {
@@ -138,6 +277,7 @@ async fn load_blend_assets<'a, 'b>(
     // TODO: check for compressed blend file and decompress if necessary
     let blend = Blend::new(bytes);
     let blend_version = get_blend_version(&blend);
+    let conversion = Conversion::new(&blend, &loader.coordinate_settings);
 
     // Load mesh assets
     for mesh in blend.get_by_code(*b"ME") {
@@ -146,12 +286,30 @@ async fn load_blend_assets<'a, 'b>(
 
         // Skip any mesh whose name starts with underscore
         if !label.starts_with("ME_") {
+            let mut bevy_mesh = mesh::instance_to_mesh(mesh.clone(), blend_version, &conversion)?;
+
+            // Add the joints of meshes deformed by an armature
+            if let Some((obj, armature)) = armature::skinned_mesh_object(&blend, &label) {
+                armature::insert_joint_attributes(&mut bevy_mesh, &mesh, &obj, &armature);
+            }
+
             // Add the created mesh with the proper label
-            load_context.set_labeled_asset(
-                label.as_str(),
-                LoadedAsset::new(mesh::instance_to_mesh(mesh, blend_version)?),
-            );
+            load_context.set_labeled_asset(label.as_str(), LoadedAsset::new(bevy_mesh));
             info!("Loaded Blender mesh asset: {}", label);
+
+            // Add the shape keys of the mesh as morph targets
+            if morph::has_shape_keys(&mesh) {
+                let morph_targets_label = morph::morph_targets_label(&mesh);
+                load_context.set_labeled_asset(
+                    morph_targets_label.as_str(),
+                    LoadedAsset::new(morph::instance_to_morph_targets(
+                        &mesh,
+                        blend_version,
+                        &conversion,
+                    )?),
+                );
+                info!("Loaded Blender shape keys asset: {}", morph_targets_label);
+            }
         }
     }
 
@@ -162,14 +320,19 @@ async fn load_blend_assets<'a, 'b>(
         ..Default::default()
     };
 
+    let missing_material = StandardMaterial {
+        base_color: Color::rgb(1.0, 0.0, 0.5),
+        reflectance: 0.0,
+        perceptual_roughness: 0.0,
+        ..Default::default()
+    };
+    load_context.set_labeled_asset(
+        material::mirrored_label("bevy_blender_missing_material").as_str(),
+        LoadedAsset::new(material::mirrored_material(&missing_material)),
+    );
     load_context.set_labeled_asset(
         "bevy_blender_missing_material",
-        LoadedAsset::new(StandardMaterial {
-            base_color: Color::rgb(1.0, 0.0, 0.5),
-            reflectance: 0.0,
-            perceptual_roughness: 0.0,
-            ..Default::default()
-        }),
+        LoadedAsset::new(missing_material),
     );
     // Load material assets
     for material in blend.get_by_code(*b"MA") {
@@ -179,19 +342,23 @@ async fn load_blend_assets<'a, 'b>(
         // Skip any material whose name starts with underscore
         if !label.starts_with("MA_") {
             let mat = material::instance_to_material(material, blend_version);
-            if mat.is_ok() {
-                load_context.set_labeled_asset(label.as_str(), LoadedAsset::new(mat.unwrap()));
+            let bevy_material = if mat.is_ok() {
                 info!("Loaded Blender material asset: {}", label);
+                mat.unwrap()
             } else {
-                load_context.set_labeled_asset(
-                    label.as_str(),
-                    LoadedAsset::new(unsupported_material.clone()),
-                );
                 warn!(
                     "Attempted to load an unsupported Blender material: {}",
                     label
                 );
-            }
+                unsupported_material.clone()
+            };
+
+            // Objects mirrored by a negative scale use a variant culling their front faces
+            load_context.set_labeled_asset(
+                material::mirrored_label(&label).as_str(),
+                LoadedAsset::new(material::mirrored_material(&bevy_material)),
+            );
+            load_context.set_labeled_asset(label.as_str(), LoadedAsset::new(bevy_material));
             // match mat {
             //     Ok(m) => load_context.set_labeled_asset(label.as_str(), LoadedAsset::new(m)),
             //     Err(e) => println!("Material {} could not be loaded", label),
@@ -204,26 +371,266 @@ async fn load_blend_assets<'a, 'b>(
         }
     }
 
+    // Load world assets, their environment textures are loaded once the blend file is done
+    let mut worlds = Vec::new();
+    for world in blend.get_by_code(*b"WO") {
+        // Get the name of the world
+        let label = world.get("id").get_string("name");
+
+        // Skip any world whose name starts with underscore
+        if !label.starts_with("WO_") {
+            let environment_image =
+                world::instance_to_environment_image(&world, load_context.path());
+            worlds.push((
+                label,
+                world::instance_to_world(world, blend_version, &conversion)?,
+                environment_image,
+            ));
+        }
+    }
+
+    // Find the images of light probes, converted to environment maps once the blend file is done
+    let light_probe_images: Vec<_> = blend
+        .get_by_code(*b"LP")
+        .into_iter()
+        .filter_map(|light_probe| {
+            let image =
+                light_probe::instance_to_environment_image(&light_probe, load_context.path())?;
+            Some((light_probe.get("id").get_string("name"), image))
+        })
+        .collect();
+
+    // Load the inverse bind poses of objects deformed by an armature
+    for obj in blend.get_by_code(*b"OB") {
+        if obj.get_i16("type") != object::OB_MESH {
+            continue;
+        }
+        if let Some(armature) = armature::object_armature(&obj) {
+            let label = armature::inverse_bindposes_label(&obj);
+            load_context.set_labeled_asset(
+                label.as_str(),
+                LoadedAsset::new(armature::instance_to_inverse_bindposes(
+                    &obj,
+                    &armature,
+                    &conversion,
+                )),
+            );
+            info!("Loaded Blender skin asset: {}", label);
+        }
+    }
+
+    // Load action assets as animation clips
+    for action in blend.get_by_code(*b"AC") {
+        // Get the name of the action
+        let label = action.get("id").get_string("name");
+
+        // Skip any action whose name starts with underscore
+        if !label.starts_with("AC_") {
+            let (clip, property_clip, root_motion) = animation::instance_to_animation_clip(
+                action.clone(),
+                &blend,
+                &loader.animation_settings,
+                &conversion,
+                blend_version,
+                None,
+            )?;
+            load_context.set_labeled_asset(label.as_str(), LoadedAsset::new(clip));
+            load_context.set_labeled_asset(
+                animation::property_clip_label(&label).as_str(),
+                LoadedAsset::new(property_clip),
+            );
+            if let Some(root_motion) = root_motion {
+                load_context.set_labeled_asset(
+                    root_motion::root_motion_label(&label).as_str(),
+                    LoadedAsset::new(root_motion),
+                );
+            }
+            info!("Loaded Blender action asset: {}", label);
+            fcurve::warn_ignored_modifiers(&label, &fcurve::instance_to_fcurves(&action));
+
+            // Objects with a parent play the action from themselves when they are spawned
+            for root in animation::action_clip_roots(&action, &blend) {
+                let (clip, property_clip, _) = animation::instance_to_animation_clip(
+                    action.clone(),
+                    &blend,
+                    &loader.animation_settings,
+                    &conversion,
+                    blend_version,
+                    Some(&root),
+                )?;
+                let rooted_label = format!("{}/{}", label, root);
+                load_context.set_labeled_asset(rooted_label.as_str(), LoadedAsset::new(clip));
+                load_context.set_labeled_asset(
+                    animation::property_clip_label(&rooted_label).as_str(),
+                    LoadedAsset::new(property_clip),
+                );
+            }
+
+            // Single frame actions marked as assets are pose assets too
+            if let Some(pose) = pose::instance_to_pose(&action, &blend, &conversion) {
+                let pose_label = pose::pose_label(&label);
+                load_context.set_labeled_asset(pose_label.as_str(), LoadedAsset::new(pose));
+                info!("Loaded Blender pose asset: {}", pose_label);
+            }
+        }
+    }
+
+    // Load the NLA strips of objects as animation clips playing their action like the strip
+    for obj in blend.get_by_code(*b"OB") {
+        for strip in animation::nla_strips(&obj) {
+            let label = animation::nla_strip_label(&obj, &strip);
+            let (clip, property_clip, root_motion) = animation::nla_strip_to_animation_clip(
+                &strip,
+                &obj,
+                &blend,
+                &loader.animation_settings,
+                &conversion,
+                None,
+            );
+            load_context.set_labeled_asset(label.as_str(), LoadedAsset::new(clip));
+            load_context.set_labeled_asset(
+                animation::property_clip_label(&label).as_str(),
+                LoadedAsset::new(property_clip),
+            );
+            if let Some(root_motion) = root_motion {
+                load_context.set_labeled_asset(
+                    root_motion::root_motion_label(&label).as_str(),
+                    LoadedAsset::new(root_motion),
+                );
+            }
+            info!("Loaded Blender NLA strip asset: {}", label);
+
+            for root in animation::clip_roots(std::slice::from_ref(&obj)) {
+                let (clip, property_clip, _) = animation::nla_strip_to_animation_clip(
+                    &strip,
+                    &obj,
+                    &blend,
+                    &loader.animation_settings,
+                    &conversion,
+                    Some(&root),
+                );
+                let rooted_label = format!("{}/{}", label, root);
+                load_context.set_labeled_asset(rooted_label.as_str(), LoadedAsset::new(clip));
+                load_context.set_labeled_asset(
+                    animation::property_clip_label(&rooted_label).as_str(),
+                    LoadedAsset::new(property_clip),
+                );
+            }
+        }
+    }
+
+    // Load the timing and the markers of the timeline of scenes, the timing of the first scene
+    // being the one of the file
+    load_context.set_labeled_asset(
+        scene::BLEND_TIMING_LABEL,
+        LoadedAsset::new(scene::blend_scene_timing(&blend)),
+    );
+    for scene in blend.get_by_code(*b"SC") {
+        let label = scene::scene_timing_label(&scene);
+        load_context.set_labeled_asset(
+            label.as_str(),
+            LoadedAsset::new(scene::instance_to_scene_timing(&scene)),
+        );
+        info!("Loaded Blender scene timing asset: {}", label);
+
+        let label = marker::scene_markers_label(&scene);
+        load_context.set_labeled_asset(
+            label.as_str(),
+            LoadedAsset::new(marker::instance_to_scene_markers(&scene)),
+        );
+        info!("Loaded Blender scene markers asset: {}", label);
+    }
+
+    // Sample the deformation of the objects to bake to vertex animation textures, which are made
+    // once the blend file is done
+    let mut vertex_animations = Vec::new();
+    for bake in &loader.animation_settings.vertex_animations {
+        match vertex_animation::sample_vertex_animation(
+            &blend,
+            bake,
+            load_context.path(),
+            blend_version,
+            &conversion,
+        ) {
+            Ok(Some(pending)) => vertex_animations.push(pending),
+            Ok(None) => {}
+            Err(e) => warn!(
+                "Could not bake the vertex animation of {}: {}",
+                bake.object, e
+            ),
+        }
+    }
+
     // TODO: load other kinds of assets
 
+    // Blend instances can not be held across the awaits below
+    drop(blend);
+
+    for (label, mut blender_world, environment_image) in worlds {
+        if let Some(environment_image) = environment_image {
+            match world::load_environment_map(&label, environment_image, &conversion, load_context)
+                .await
+            {
+                Ok(environment_map) => blender_world.environment_map = Some(environment_map),
+                Err(e) => warn!(
+                    "Could not load the environment texture of Blender world {}: {}",
+                    label, e
+                ),
+            }
+        }
+        load_context.set_labeled_asset(label.as_str(), LoadedAsset::new(blender_world));
+        info!("Loaded Blender world asset: {}", label);
+    }
+
+    for (label, image) in light_probe_images {
+        match world::load_environment_map(&label, image, &conversion, load_context).await {
+            Ok(_) => info!("Loaded Blender light probe environment map: {}", label),
+            Err(e) => warn!(
+                "Could not load the image of Blender light probe {}: {}",
+                label, e
+            ),
+        }
+    }
+
+    for pending in vertex_animations {
+        let label = pending.label().to_string();
+        match vertex_animation::load_vertex_animation(pending, load_context).await {
+            Ok(vertex_animation) => {
+                load_context.set_labeled_asset(label.as_str(), LoadedAsset::new(vertex_animation));
+                info!("Loaded Blender vertex animation asset: {}", label);
+            }
+            Err(e) => warn!("Could not bake the vertex animation {}: {}", label, e),
+        }
+    }
+
     Ok(())
 }
 
 /// Takes a right handed, z up transformation matrix (Blender) and returns a right handed, y up (Bevy) version of it
+#[deprecated(
+    since = "0.10.0",
+    note = "ignores the BlenderCoordinateSettings and the unit scale of the scene"
+)]
 pub fn right_hand_zup_to_right_hand_yup(rhzup: &Mat4) -> Mat4 {
-    let (scale, rotation, translation) = rhzup.to_scale_rotation_translation();
-    let euler_rotation = rotation.to_euler(bevy_math::EulerRot::XYZ);
-
-    Mat4::from_scale_rotation_translation(
-        Vec3::new(scale[0], scale[2], scale[1]),
-        Quat::from_euler(
-            bevy_math::EulerRot::XZY,
-            euler_rotation.0,
-            -euler_rotation.1,
-            euler_rotation.2,
-        ),
-        Vec3::new(translation[0], translation[2], -translation[1]),
-    )
+    Conversion::default().matrix(rhzup)
+}
+
+/// Takes a right handed, z up position or direction (Blender) and returns a right handed, y up (Bevy) version of it
+#[deprecated(
+    since = "0.10.0",
+    note = "ignores the BlenderCoordinateSettings and the unit scale of the scene"
+)]
+pub fn right_hand_zup_to_right_hand_yup_vector(rhzup: Vec3) -> Vec3 {
+    Conversion::default().position(rhzup)
+}
+
+/// Takes a right handed, z up rotation (Blender) and returns a right handed, y up (Bevy) version of it
+#[deprecated(
+    since = "0.10.0",
+    note = "ignores the BlenderCoordinateSettings and the unit scale of the scene"
+)]
+pub fn right_hand_zup_to_right_hand_yup_quat(rhzup: Quat) -> Quat {
+    Conversion::default().rotation(rhzup)
 }
 
 /// Takes a blend::Blend struct and returns the correct version tuple
@@ -236,3 +643,18 @@ pub fn get_blend_version(blend: &Blend) -> (u8, u8, u8) {
         version_raw[2] - 48,
     )
 }
+
+/// Reads an integer field of a Blend::Instance whose type (char, short or int) changed between
+/// versions of Blender. Returns 0 if the field does not exist.
+pub(crate) fn get_int(instance: &Instance, name: &str) -> i32 {
+    match instance
+        .fields
+        .get(name)
+        .map(|field| field.type_name.as_str())
+    {
+        Some("int") => instance.get_i32(name),
+        Some("short") => instance.get_i16(name) as i32,
+        Some("char") => instance.get_u8(name) as i32,
+        _ => 0,
+    }
+}
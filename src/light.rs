@@ -0,0 +1,91 @@
+use bevy_ecs::system::EntityCommands;
+use bevy_hierarchy::BuildChildren;
+use bevy_pbr::{
+    DirectionalLight, DirectionalLightBundle, PointLight, PointLightBundle, SpotLight,
+    SpotLightBundle,
+};
+use bevy_render::color::Color;
+use bevy_transform::prelude::Transform;
+use blend::Instance;
+use std::f32::consts::PI;
+
+use crate::{coordinates::Conversion, get_int};
+
+// Light.type values (DNA_light_types.h)
+const LA_SUN: i32 = 1;
+const LA_SPOT: i32 = 2;
+
+// Light.mode bit of lights casting shadows
+const LA_SHADOW: i32 = 1 << 0;
+
+// Luminous efficacy used to convert the watts of Blender lights to lumens (and the watts per
+// square meter of suns to lux)
+const LUMENS_PER_WATT: f32 = 683.0;
+
+/// Returns the factor converting the power of a Blend::Instance light to the intensity of its
+/// Bevy light: lumens for point and spot lights, lux for suns
+pub(crate) fn intensity_factor(light: &Instance) -> f32 {
+    match get_int(light, "type") {
+        LA_SUN => LUMENS_PER_WATT,
+        // Blender's watts are radiated in every direction, like the candelas of a point light
+        _ => LUMENS_PER_WATT / (4.0 * PI),
+    }
+}
+
+/// Spawns the Bevy light of a Blend::Instance light as a child of its object's entity. Suns
+/// become directional lights, spots become spot lights, and point and area lights become point
+/// lights.
+pub(crate) fn spawn_light(entity: &mut EntityCommands, light: &Instance, conversion: &Conversion) {
+    let color = Color::rgb(light.get_f32("r"), light.get_f32("g"), light.get_f32("b"));
+    let intensity = light.get_f32("energy") * intensity_factor(light);
+    let shadows_enabled = get_int(light, "mode") & LA_SHADOW != 0;
+    // Lights look down -Z in Blender, which becomes -Z in Bevy once rotated like the axes
+    let transform = Transform::from_rotation(conversion.basis_rotation());
+
+    entity.with_children(|parent| match get_int(light, "type") {
+        LA_SUN => {
+            parent.spawn(DirectionalLightBundle {
+                directional_light: DirectionalLight {
+                    color,
+                    illuminance: intensity,
+                    shadows_enabled,
+                    ..Default::default()
+                },
+                transform,
+                ..Default::default()
+            });
+        }
+        LA_SPOT => {
+            // The spot size is the angle of the whole cone
+            let outer_angle = light.get_f32("spotsize") * 0.5;
+            parent.spawn(SpotLightBundle {
+                spot_light: SpotLight {
+                    color,
+                    intensity,
+                    radius: conversion.length(light.get_f32("area_size")),
+                    range: conversion.length(light.get_f32("att_dist")),
+                    shadows_enabled,
+                    outer_angle,
+                    inner_angle: outer_angle * (1.0 - light.get_f32("spotblend")),
+                    ..Default::default()
+                },
+                transform,
+                ..Default::default()
+            });
+        }
+        _ => {
+            parent.spawn(PointLightBundle {
+                point_light: PointLight {
+                    color,
+                    intensity,
+                    radius: conversion.length(light.get_f32("area_size")),
+                    range: conversion.length(light.get_f32("att_dist")),
+                    shadows_enabled,
+                    ..Default::default()
+                },
+                transform,
+                ..Default::default()
+            });
+        }
+    });
+}
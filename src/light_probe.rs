@@ -0,0 +1,194 @@
+use bevy_asset::{AssetServer, Assets};
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    query::With,
+    system::{Commands, Query, Res},
+};
+use bevy_math::UVec3;
+use bevy_pbr::EnvironmentMapLight;
+use bevy_render::{camera::Camera, texture::Image};
+use bevy_transform::components::GlobalTransform;
+use blend::Instance;
+use std::path::Path;
+
+use crate::{
+    coordinates::Conversion,
+    world::{self, EnvironmentImage},
+    BevyBlenderError,
+};
+
+// LightProbe.type values (DNA_lightprobe_types.h)
+const LIGHTPROBE_TYPE_CUBE: u8 = 0;
+const LIGHTPROBE_TYPE_PLANAR: u8 = 1;
+const LIGHTPROBE_TYPE_GRID: u8 = 2;
+
+// LightProbe.attenuation_type values
+const LIGHTPROBE_SHAPE_BOX: u8 = 1;
+
+/// The kind of a Blender light probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlenderLightProbeKind {
+    /// Reflection cubemap (called sphere probe in newer versions of Blender)
+    ReflectionCubemap,
+    /// Irradiance volume (called volume probe in newer versions of Blender)
+    IrradianceVolume,
+    /// Reflection plane (called plane probe in newer versions of Blender)
+    ReflectionPlane,
+}
+
+/// The shape of the influence of a Blender light probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlenderLightProbeShape {
+    /// The influence is a sphere (or an ellipsoid once scaled by the object)
+    Ellipsoid,
+    /// The influence is a box
+    Box,
+}
+
+/// Placement and influence of a Blender light probe object. Bevy does not have light probes, so
+/// this component only carries the settings authored in Blender. Reflection cubemaps with an image
+/// get it as a precomputed environment map, which is then attached to the closest camera.
+#[derive(Component, Clone)]
+pub struct BlenderLightProbe {
+    /// The kind of light probe
+    pub kind: BlenderLightProbeKind,
+    /// The shape of the influence
+    pub shape: BlenderLightProbeShape,
+    /// Influence distance, in the local space of the object
+    pub influence_distance: f32,
+    /// Influence falloff, from 0 (hard) to 1 (smooth)
+    pub falloff: f32,
+    /// Near clipping distance used when capturing the probe
+    pub clip_start: f32,
+    /// Far clipping distance used when capturing the probe
+    pub clip_end: f32,
+    /// Number of samples along each axis of an irradiance volume
+    pub grid_resolution: UVec3,
+    /// Precomputed environment map of a reflection cubemap, attached as an EnvironmentMapLight to
+    /// the cameras that this probe is the closest reflection cubemap of. Reflection cubemaps with
+    /// an image get the cubemaps the loader made from it
+    /// (e.g. demo.blend#LPProbe/EnvironmentSpecular).
+    pub environment_map: Option<EnvironmentMapLight>,
+}
+
+/// Takes a Blend::Instance light probe and converts it to a BlenderLightProbe
+pub(crate) fn instance_to_light_probe(
+    instance: Instance,
+    conversion: &Conversion,
+) -> anyhow::Result<BlenderLightProbe> {
+    // Don't process instances of types other than light probe
+    if instance.type_name != "LightProbe" {
+        return Err(anyhow::Error::new(BevyBlenderError::InvalidInstanceType {
+            expected: String::from("LightProbe"),
+            found: instance.type_name,
+        }));
+    }
+
+    let kind = match instance.get_u8("type") {
+        LIGHTPROBE_TYPE_CUBE => BlenderLightProbeKind::ReflectionCubemap,
+        LIGHTPROBE_TYPE_PLANAR => BlenderLightProbeKind::ReflectionPlane,
+        LIGHTPROBE_TYPE_GRID => BlenderLightProbeKind::IrradianceVolume,
+        probe_type => {
+            return Err(anyhow::Error::new(BevyBlenderError::UnsupportedAsset {
+                asset_type: format!("light probe of type {}", probe_type),
+            }))
+        }
+    };
+
+    let shape = match instance.get_u8("attenuation_type") {
+        LIGHTPROBE_SHAPE_BOX => BlenderLightProbeShape::Box,
+        _ => BlenderLightProbeShape::Ellipsoid,
+    };
+
+    Ok(BlenderLightProbe {
+        kind,
+        shape,
+        influence_distance: conversion.length(instance.get_f32("distinf")),
+        falloff: instance.get_f32("falloff"),
+        clip_start: conversion.length(instance.get_f32("clipsta")),
+        clip_end: conversion.length(instance.get_f32("clipend")),
+        grid_resolution: UVec3::new(
+            instance.get_i32("grid_resolution_x").max(0) as u32,
+            instance.get_i32("grid_resolution_y").max(0) as u32,
+            instance.get_i32("grid_resolution_z").max(0) as u32,
+        ),
+        environment_map: None,
+    })
+}
+
+/// Returns the image of a Blend::Instance reflection cubemap light probe, if it has one
+fn light_probe_image<'a>(instance: &Instance<'a>) -> Option<Instance<'a>> {
+    (instance.get_u8("type") == LIGHTPROBE_TYPE_CUBE
+        && instance.fields.contains_key("image")
+        && instance.is_valid("image"))
+    .then(|| instance.get("image"))
+}
+
+/// Finds the equirectangular image of a Blend::Instance reflection cubemap light probe, which the
+/// loader converts to its environment map. "blend_path" is the path of the .blend file.
+pub(crate) fn instance_to_environment_image(
+    instance: &Instance,
+    blend_path: &Path,
+) -> Option<EnvironmentImage> {
+    light_probe_image(instance).map(|image| world::instance_to_image_file(&image, blend_path))
+}
+
+/// Returns the environment map the loader made from the image of a Blend::Instance reflection
+/// cubemap light probe, if it has one
+pub(crate) fn light_probe_environment_map(
+    asset_server: &AssetServer,
+    blender_file: &str,
+    instance: &Instance,
+) -> Option<EnvironmentMapLight> {
+    light_probe_image(instance)?;
+    let (diffuse_label, specular_label) =
+        world::environment_map_labels(&instance.get("id").get_string("name"));
+    Some(EnvironmentMapLight {
+        diffuse_map: asset_server.load(format!("{}#{}", blender_file, diffuse_label).as_str()),
+        specular_map: asset_server.load(format!("{}#{}", blender_file, specular_label).as_str()),
+    })
+}
+
+/// Attaches the environment map of the closest reflection cubemap probe to every camera, once its
+/// cubemaps are loaded. Cameras without such a probe keep their current environment map.
+pub(crate) fn attach_light_probe_environment_maps(
+    mut commands: Commands,
+    images: Res<Assets<Image>>,
+    probes: Query<(&BlenderLightProbe, &GlobalTransform)>,
+    cameras: Query<(Entity, &GlobalTransform, Option<&EnvironmentMapLight>), With<Camera>>,
+) {
+    for (camera, camera_transform, current) in &cameras {
+        let closest = probes
+            .iter()
+            .filter(|(probe, _)| probe.kind == BlenderLightProbeKind::ReflectionCubemap)
+            .filter_map(|(probe, transform)| {
+                let environment_map = probe.environment_map.as_ref().filter(|environment_map| {
+                    images.contains(&environment_map.diffuse_map)
+                        && images.contains(&environment_map.specular_map)
+                });
+                environment_map.map(|environment_map| {
+                    (
+                        environment_map,
+                        transform
+                            .translation()
+                            .distance_squared(camera_transform.translation()),
+                    )
+                })
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        let Some((environment_map, _)) = closest else {
+            continue;
+        };
+
+        // Only insert the environment map when it changes, to keep change detection meaningful
+        let unchanged = current.is_some_and(|current| {
+            current.diffuse_map == environment_map.diffuse_map
+                && current.specular_map == environment_map.specular_map
+        });
+        if !unchanged {
+            commands.entity(camera).insert(environment_map.clone());
+        }
+    }
+}
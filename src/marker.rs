@@ -0,0 +1,106 @@
+use bevy_core::Name;
+use bevy_ecs::{entity::Entity, event::EventReader, query::With, system::Query};
+use bevy_hierarchy::Parent;
+use bevy_render::camera::Camera;
+use blend::Instance;
+
+use crate::{camera::BlenderCamera, property::BlenderPropertyClip, scene};
+
+/// A marker of the timeline of a Blender scene, or a pose marker of an action, played by a
+/// BlenderPropertyClip
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlenderMarker {
+    /// Name of the marker
+    pub name: String,
+    /// Frame of the marker
+    pub frame: i32,
+    /// Time of the clip at which the marker is reached, in seconds
+    pub time: f32,
+    /// Name of the camera object the marker is bound to, which becomes the active camera when the
+    /// marker is reached
+    pub camera: Option<String>,
+}
+
+/// Sent when a BlenderPropertyPlayer reaches a marker of its clip
+#[derive(Debug, Clone)]
+pub struct BlenderMarkerReached {
+    /// Name of the marker
+    pub name: String,
+    /// Frame of the marker
+    pub frame: i32,
+    /// The entity of the BlenderPropertyPlayer
+    pub entity: Entity,
+    /// Name of the camera object the marker is bound to, if any
+    pub camera: Option<String>,
+}
+
+/// Returns the markers of a Blend::Instance scene or action (whose markers are its pose markers),
+/// at time 0
+pub(crate) fn instance_to_markers(instance: &Instance) -> Vec<BlenderMarker> {
+    if !instance.is_valid("markers") {
+        return Vec::new();
+    }
+    instance
+        .get_iter("markers")
+        .map(|marker| BlenderMarker {
+            name: marker.get_string("name"),
+            frame: marker.get_i32("frame"),
+            time: 0.0,
+            camera: marker
+                .is_valid("camera")
+                .then(|| marker.get("camera").get("id").get_string("name")[2..].to_string()),
+        })
+        .collect()
+}
+
+/// Returns the label of the markers of a scene, e.g. "SCScene/Markers"
+pub(crate) fn scene_markers_label(scene: &Instance) -> String {
+    format!("{}/Markers", scene.get("id").get_string("name"))
+}
+
+/// Takes a Blend::Instance scene and converts the markers of its timeline to a BlenderPropertyClip
+/// playing the frame range of the scene. Markers outside of the frame range are left out.
+pub(crate) fn instance_to_scene_markers(scene: &Instance) -> BlenderPropertyClip {
+    let timing = scene::instance_to_scene_timing(scene);
+
+    let mut clip = BlenderPropertyClip::default();
+    clip.extend_duration(timing.duration());
+    for mut marker in instance_to_markers(scene) {
+        if (timing.start_frame..=timing.end_frame).contains(&marker.frame) {
+            marker.time = timing.frame_time(marker.frame as f32);
+            clip.add_marker(marker);
+        }
+    }
+    clip
+}
+
+/// Makes the camera bound to the last marker reached the only active camera, if it was spawned
+pub(crate) fn switch_marker_cameras(
+    mut reached: EventReader<BlenderMarkerReached>,
+    mut cameras: Query<(&mut Camera, &Parent), With<BlenderCamera>>,
+    names: Query<&Name>,
+) {
+    let Some(camera_name) = reached
+        .iter()
+        .filter_map(|marker| marker.camera.clone())
+        .last()
+    else {
+        return;
+    };
+
+    // Camera entities are children of the entity of their object
+    let is_bound = |parent: &Parent| {
+        names
+            .get(parent.get())
+            .is_ok_and(|name| name.as_str() == camera_name)
+    };
+    if !cameras.iter().any(|(_, parent)| is_bound(parent)) {
+        return;
+    }
+    for (mut camera, parent) in &mut cameras {
+        let is_active = is_bound(parent);
+        if camera.is_active != is_active {
+            camera.is_active = is_active;
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use bevy_pbr::prelude::StandardMaterial;
-use bevy_render::color::Color;
+use bevy_render::{color::Color, render_resource::Face};
 use blend::runtime::Instance;
 
 use crate::BevyBlenderError;
@@ -14,6 +14,26 @@ macro_rules! blender_material {
     };
 }
 
+/// Returns the label of the variant of a material used by mirrored objects, e.g.
+/// "MAMaterial/Mirrored"
+pub(crate) fn mirrored_label(label: &str) -> String {
+    format!("{}/Mirrored", label)
+}
+
+/// Returns the variant of a material used by mirrored objects (with a negative scale). Their
+/// triangles wind the other way once transformed, so their front faces are culled instead of
+/// their back faces.
+pub(crate) fn mirrored_material(material: &StandardMaterial) -> StandardMaterial {
+    StandardMaterial {
+        cull_mode: match material.cull_mode {
+            Some(Face::Back) => Some(Face::Front),
+            Some(Face::Front) => Some(Face::Back),
+            None => None,
+        },
+        ..material.clone()
+    }
+}
+
 /// Takes a Blend::Instance material and converts it to a Bevy material. If the Blender material
 /// is a basic material (not nodes based), the bevy_pbr::StandardMaterial will be used.
 pub(crate) fn instance_to_material(
@@ -5,7 +5,7 @@ use bevy_render::{
 };
 use blend::runtime::Instance;
 
-use crate::BevyBlenderError;
+use crate::{coordinates::Conversion, BevyBlenderError};
 
 /// Takes a .blend file location and a mesh name and generates
 /// an appropriate asset_loader string. For example,
@@ -21,6 +21,7 @@ macro_rules! blender_mesh {
 pub(crate) fn instance_to_mesh(
     instance: Instance,
     blend_version: (u8, u8, u8),
+    conversion: &Conversion,
 ) -> anyhow::Result<Mesh> {
     // Don't process instances of types other than mesh
     if instance.type_name != "Mesh" {
@@ -30,15 +31,6 @@ pub(crate) fn instance_to_mesh(
         }));
     }
 
-    // Takes a normalized i16 vector from instance, and converts it to a normalized f32 vector
-    fn no_to_f32(no: Vec<i16>) -> Vec<f32> {
-        let mut v = Vec::new();
-        for i in no {
-            v.push((i as f32) / (i16::MAX as f32));
-        }
-        v
-    }
-
     // Extract Blender DNA blocks from instance
     let blender_faces = instance.get_iter("mpoly").collect::<Vec<_>>();
     let blender_loops = instance.get_iter("mloop").collect::<Vec<_>>();
@@ -57,40 +49,7 @@ pub(crate) fn instance_to_mesh(
             faceloop.push(blender_loops[i as usize].get_i32("v") as u32);
         }
 
-        let mut faces: Vec<Vec<u32>> = Vec::new();
-
-        // triangulate ngons using ear clipping method
-        let mut i = 0;
-        while faceloop.len() > 3 {
-            if i >= faceloop.len() {
-                i = 0;
-            }
-
-            let mut face = Vec::new();
-            face.push(faceloop[i]);
-
-            i += 1;
-            if i >= faceloop.len() {
-                i = 0;
-            }
-
-            face.push(faceloop[i]);
-
-            let mut j = i + 1;
-            if j >= faceloop.len() {
-                j = 0;
-            }
-
-            face.push(faceloop[j]);
-
-            faces.push(face);
-
-            faceloop.remove(i);
-        }
-
-        faces.push(faceloop);
-
-        for face in faces {
+        for face in triangulate(faceloop) {
             indices.push(face[2]);
             indices.push(face[0]);
             indices.push(face[1]);
@@ -105,21 +64,21 @@ pub(crate) fn instance_to_mesh(
     let mut normals: Vec<[f32; 3]> = Vec::new();
     let mut uvs: Vec<[f32; 2]> = vec![[0.0, 0.0]; blender_verts.len()];
 
-    // Fill position and normal attributes from blender_verts, swapping Y and Z
+    // Fill position and normal attributes from blender_verts, converted to Bevy coordinates
     for vert in &blender_verts {
-        let p = vert.get_f32_vec("co");
-        positions.push([p[0], p[2], -p[1]]);
+        let p = Vec3::from_slice(&vert.get_f32_vec("co"));
+        positions.push(conversion.position(p).into());
     }
 
     match blend_version {
         (0..=2, _, _) => {
             for vert in &blender_verts {
-                let n = no_to_f32(vert.get_i16_vec("no"));
-                normals.push([n[0], n[2], -n[1]]);
+                let n = Vec3::from_slice(&no_to_f32(vert.get_i16_vec("no")));
+                normals.push(conversion.direction(n).into());
             }
         }
         (3.., _, _) => {
-            normals = calculate_vertex_normals(&blender_faces, &blender_loops, &positions);
+            normals = calculate_vertex_normals(&instance, &positions);
         }
     }
 
@@ -140,53 +99,173 @@ pub(crate) fn instance_to_mesh(
     Ok(mesh)
 }
 
-// Blender version 3+ does not include precalculated vertex normals in the .blend file
-fn calculate_vertex_normals(
-    blender_faces: &Vec<Instance>,
-    blender_loops: &Vec<Instance>,
-    positions: &Vec<[f32; 3]>,
-) -> Vec<[f32; 3]> {
-    let mut normals: Vec<Vec3> = Vec::new();
-
-    for blender_face in blender_faces {
-        let start = blender_face.get_i32("loopstart");
-        let end = start + blender_face.get_i32("totloop");
-        let mut faceloop: Vec<u32> = Vec::new();
-        for i in start..end {
-            faceloop.push(blender_loops[i as usize].get_i32("v") as u32);
+/// Takes a Blend::Instance mesh and returns its vertex positions and vertex normals in the
+/// Blender coordinate system (Right Handed, Z-up)
+pub(crate) fn instance_to_vertices(
+    instance: &Instance,
+    blend_version: (u8, u8, u8),
+) -> (Vec<Vec3>, Vec<Vec3>) {
+    let blender_verts = instance.get_iter("mvert").collect::<Vec<_>>();
+
+    let positions: Vec<[f32; 3]> = blender_verts
+        .iter()
+        .map(|vert| {
+            let p = vert.get_f32_vec("co");
+            [p[0], p[1], p[2]]
+        })
+        .collect();
+
+    let normals: Vec<[f32; 3]> = match blend_version {
+        (0..=2, _, _) => blender_verts
+            .iter()
+            .map(|vert| {
+                let n = no_to_f32(vert.get_i16_vec("no"));
+                [n[0], n[1], n[2]]
+            })
+            .collect(),
+        (3.., _, _) => calculate_vertex_normals(instance, &positions),
+    };
+
+    (
+        positions.into_iter().map(Vec3::from).collect(),
+        normals.into_iter().map(Vec3::from).collect(),
+    )
+}
+
+/// Returns the position of a vertex of a Blend::Instance mesh, in the Blender coordinate system
+pub(crate) fn vertex_position(instance: &Instance, index: usize) -> Option<Vec3> {
+    instance
+        .get_iter("mvert")
+        .nth(index)
+        .map(|vert| Vec3::from_slice(&vert.get_f32_vec("co")))
+}
+
+/// Takes a Blend::Instance mesh and returns the vertex indices of each of its faces, in loop order
+pub(crate) fn instance_to_face_loops(instance: &Instance) -> Vec<Vec<u32>> {
+    let blender_loops = instance.get_iter("mloop").collect::<Vec<_>>();
+
+    instance
+        .get_iter("mpoly")
+        .map(|blender_face| {
+            let start = blender_face.get_i32("loopstart");
+            let end = start + blender_face.get_i32("totloop");
+            (start..end)
+                .map(|i| blender_loops[i as usize].get_i32("v") as u32)
+                .collect()
+        })
+        .collect()
+}
+
+/// Splits the vertex indices of a face into triangles, using ear clipping for ngons
+fn triangulate(mut faceloop: Vec<u32>) -> Vec<Vec<u32>> {
+    let mut faces: Vec<Vec<u32>> = Vec::new();
+
+    // triangulate ngons using ear clipping method
+    let mut i = 0;
+    while faceloop.len() > 3 {
+        if i >= faceloop.len() {
+            i = 0;
+        }
+
+        let mut face = Vec::new();
+        face.push(faceloop[i]);
+
+        i += 1;
+        if i >= faceloop.len() {
+            i = 0;
+        }
+
+        face.push(faceloop[i]);
+
+        let mut j = i + 1;
+        if j >= faceloop.len() {
+            j = 0;
+        }
+
+        face.push(faceloop[j]);
+
+        faces.push(face);
+
+        faceloop.remove(i);
+    }
+
+    faces.push(faceloop);
+    faces
+}
+
+/// Takes a Blend::Instance mesh and returns the vertex indices of its tessellated faces (MFace),
+/// which Blender indexes particles and their interpolation weights by. Triangles and quads are
+/// kept and ngons are split into triangles, in face order.
+pub(crate) fn instance_to_tessellated_faces(instance: &Instance) -> Vec<Vec<u32>> {
+    instance_to_face_loops(instance)
+        .into_iter()
+        .flat_map(|faceloop| match faceloop.len() {
+            0..=4 => vec![faceloop],
+            _ => triangulate(faceloop),
+        })
+        .map(|mut face| {
+            // Like Blender, move vertex 0 away from the last corner, where it would mark a triangle
+            match face.len() {
+                3 if face[2] == 0 => face.rotate_left(1),
+                4 if face[2] == 0 || face[3] == 0 => face.rotate_left(2),
+                _ => {}
+            }
+            face
+        })
+        .collect()
+}
+
+/// Computes the vertex normals of a mesh from the vertex indices of its faces (see
+/// instance_to_face_loops) and its vertex positions, like the normals of Blender 3+ meshes
+pub(crate) fn vertex_normals(face_loops: &[Vec<u32>], positions: &[Vec3]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::ZERO; positions.len()];
+
+    for faceloop in face_loops {
+        if faceloop.len() < 3 {
+            continue;
         }
         // Calculate face normal as cross product of first and last edge in loop
         let v1 = positions[faceloop[0] as usize];
         let v2 = positions[faceloop[1] as usize];
         let v3 = positions[faceloop[faceloop.len() - 1] as usize];
-
-        let e1 = Vec3::new(v2[0] - v1[0], v2[1] - v1[1], v2[2] - v1[2]);
-        let e2 = Vec3::new(v1[0] - v3[0], v1[1] - v3[1], v1[2] - v3[2]);
-
-        let n = e2.cross(e1).normalize();
+        let n = (v1 - v3).cross(v2 - v1).normalize_or_zero();
 
         for vertex in faceloop {
-            // Make sure vertex index exists in normals
-            while vertex >= normals.len() as u32 {
-                normals.push(Vec3::new(0.0, 0.0, 0.0));
-            }
-            // Add face normal to vertex index
-            normals[vertex as usize] += n;
+            normals[*vertex as usize] += n;
         }
     }
 
-    let mut normals_out: Vec<[f32; 3]> = Vec::new();
-    // Normalize vertex normals and cast to proper type
-    for normal in normals {
-        normals_out.push(normal.normalize().into());
+    normals
+        .into_iter()
+        .map(|normal| normal.normalize_or_zero())
+        .collect()
+}
+
+// Takes a normalized i16 vector from instance, and converts it to a normalized f32 vector
+fn no_to_f32(no: Vec<i16>) -> Vec<f32> {
+    let mut v = Vec::new();
+    for i in no {
+        v.push((i as f32) / (i16::MAX as f32));
     }
+    v
+}
 
-    return normals_out;
+// Blender version 3+ does not include precalculated vertex normals in the .blend file
+// (see vertex_normals)
+fn calculate_vertex_normals(instance: &Instance, positions: &[[f32; 3]]) -> Vec<[f32; 3]> {
+    let face_loops = instance_to_face_loops(instance);
+    let positions: Vec<Vec3> = positions.iter().copied().map(Vec3::from).collect();
+
+    vertex_normals(&face_loops, &positions)
+        .into_iter()
+        .map(Vec3::into)
+        .collect()
 }
 
 #[cfg(nightly)]
 mod tests {
     use super::instance_to_mesh;
+    use crate::coordinates::Conversion;
     use blend::{Blend, Instance};
 
     extern crate test;
@@ -219,7 +298,7 @@ mod tests {
         let mesh_instance = get_mesh_by_name(&blend, "MECube_192").unwrap();
 
         b.iter(|| {
-            instance_to_mesh(mesh_instance.clone(), version).unwrap();
+            instance_to_mesh(mesh_instance.clone(), version, &Conversion::default()).unwrap();
         });
     }
 
@@ -240,7 +319,7 @@ mod tests {
         let mesh_instance = get_mesh_by_name(&blend, "MECube_768").unwrap();
 
         b.iter(|| {
-            instance_to_mesh(mesh_instance.clone(), version).unwrap();
+            instance_to_mesh(mesh_instance.clone(), version, &Conversion::default()).unwrap();
         });
     }
 
@@ -261,7 +340,7 @@ mod tests {
         let mesh_instance = get_mesh_by_name(&blend, "MECube_3072").unwrap();
 
         b.iter(|| {
-            instance_to_mesh(mesh_instance.clone(), version).unwrap();
+            instance_to_mesh(mesh_instance.clone(), version, &Conversion::default()).unwrap();
         });
     }
 
@@ -282,7 +361,7 @@ mod tests {
         let mesh_instance = get_mesh_by_name(&blend, "MECube_12288").unwrap();
 
         b.iter(|| {
-            instance_to_mesh(mesh_instance.clone(), version).unwrap();
+            instance_to_mesh(mesh_instance.clone(), version, &Conversion::default()).unwrap();
         });
     }
 
@@ -303,7 +382,7 @@ mod tests {
         let mesh_instance = get_mesh_by_name(&blend, "MECube_49125").unwrap();
 
         b.iter(|| {
-            instance_to_mesh(mesh_instance.clone(), version).unwrap();
+            instance_to_mesh(mesh_instance.clone(), version, &Conversion::default()).unwrap();
         });
     }
 }
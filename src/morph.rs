@@ -0,0 +1,304 @@
+use bevy_asset::{Assets, Handle};
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    query::{Changed, Or, Without},
+    system::{Commands, Query, Res, ResMut},
+};
+use bevy_math::Vec3;
+use bevy_reflect::TypeUuid;
+use bevy_render::mesh::Mesh;
+use blend::Instance;
+
+use crate::{animation, coordinates::Conversion, get_int, mesh, BevyBlenderError};
+
+// KeyBlock.flag bit of muted shape keys
+const KEYBLOCK_MUTE: i32 = 1 << 0;
+
+/// The shape keys of a Blender mesh, as morph targets. Each target moves the vertices of the mesh
+/// by its deltas, multiplied by the weight of the target in the MorphWeights of the entity.
+#[derive(Debug, Clone, TypeUuid)]
+#[uuid = "5d0f6a43-25f4-4c1e-9b8a-4b7c8f0c2d91"]
+pub struct BlenderMorphTargets {
+    /// Names of the targets, which are the names of the shape keys
+    pub names: Vec<String>,
+    /// Vertex positions of the mesh without any target applied
+    pub positions: Vec<Vec3>,
+    /// Vertex normals of the mesh without any target applied
+    pub normals: Vec<Vec3>,
+    /// Offsets of the vertex positions of each target
+    pub position_deltas: Vec<Vec<Vec3>>,
+    /// Offsets of the vertex normals of each target, derived from its positions
+    pub normal_deltas: Vec<Vec<Vec3>>,
+}
+
+/// Weights of the morph targets of a mesh entity, in the order of its BlenderMorphTargets. Mesh
+/// entities with shape keys are spawned with the values of their shape keys, and their mesh is
+/// updated whenever the weights change.
+#[derive(Component, Debug, Clone, Default)]
+pub struct MorphWeights {
+    /// Names of the targets
+    pub names: Vec<String>,
+    /// Weight of each target
+    pub weights: Vec<f32>,
+}
+
+impl MorphWeights {
+    /// Returns the weight of the target named "name", if any
+    pub fn get(&self, name: &str) -> Option<f32> {
+        let index = self.names.iter().position(|target| target == name)?;
+        Some(self.weights[index])
+    }
+
+    /// Sets the weight of the target named "name". Returns false if there is no such target.
+    pub fn set(&mut self, name: &str, weight: f32) -> bool {
+        match self.names.iter().position(|target| target == name) {
+            Some(index) => {
+                self.weights[index] = weight;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Marks mesh entities whose mesh was copied, so that their morph targets can be applied without
+/// changing the other entities using the same mesh
+#[derive(Component)]
+pub(crate) struct MorphedMesh;
+
+/// Returns the label of the morph targets of a mesh, e.g. "MESuzanne/MorphTargets"
+pub(crate) fn morph_targets_label(mesh: &Instance) -> String {
+    format!("{}/MorphTargets", mesh.get("id").get_string("name"))
+}
+
+/// Returns the shape keys of a Blend::Instance mesh, the first one being the basis
+fn key_blocks<'a>(mesh: &Instance<'a>) -> Vec<Instance<'a>> {
+    if !mesh.is_valid("key") || !mesh.get("key").is_valid("block") {
+        return Vec::new();
+    }
+    mesh.get("key").get_iter("block").collect()
+}
+
+/// Returns true if a Blend::Instance mesh has shape keys besides its basis
+pub(crate) fn has_shape_keys(mesh: &Instance) -> bool {
+    key_blocks(mesh).len() > 1
+}
+
+/// Takes a Blend::Instance mesh and converts its shape keys to BlenderMorphTargets. Every shape
+/// key is relative to the shape key it is set relative to, usually the basis.
+pub(crate) fn instance_to_morph_targets(
+    instance: &Instance,
+    blend_version: (u8, u8, u8),
+    conversion: &Conversion,
+) -> anyhow::Result<BlenderMorphTargets> {
+    // Don't process instances of types other than mesh
+    if instance.type_name != "Mesh" {
+        return Err(anyhow::Error::new(BevyBlenderError::InvalidInstanceType {
+            expected: String::from("Mesh"),
+            found: instance.type_name.clone(),
+        }));
+    }
+
+    let (positions, _) = mesh::instance_to_vertices(instance, blend_version);
+    let face_loops = mesh::instance_to_face_loops(instance);
+    let blocks = key_blocks(instance);
+    let shapes: Vec<Vec<Vec3>> = blocks
+        .iter()
+        .map(|block| key_block_positions(block, &positions))
+        .collect();
+
+    // Normals are derived from the basis in the same way for every target, so that their deltas
+    // are only due to the target
+    let basis = shapes.first().cloned().unwrap_or_else(|| positions.clone());
+    let basis_normals = mesh::vertex_normals(&face_loops, &basis);
+
+    let mut targets = BlenderMorphTargets {
+        names: Vec::new(),
+        positions: basis.iter().map(|p| conversion.position(*p)).collect(),
+        normals: basis_normals
+            .iter()
+            .map(|n| conversion.direction(*n))
+            .collect(),
+        position_deltas: Vec::new(),
+        normal_deltas: Vec::new(),
+    };
+
+    for (block, shape) in blocks.iter().zip(&shapes).skip(1) {
+        let relative = shapes
+            .get(get_int(block, "relative").max(0) as usize)
+            .unwrap_or(&basis);
+        let position_deltas: Vec<Vec3> = shape
+            .iter()
+            .zip(relative)
+            .map(|(position, relative)| *position - *relative)
+            .collect();
+
+        // The shape of the target alone, applied to the basis
+        let target: Vec<Vec3> = basis
+            .iter()
+            .zip(&position_deltas)
+            .map(|(position, delta)| *position + *delta)
+            .collect();
+        let normal_deltas = mesh::vertex_normals(&face_loops, &target)
+            .iter()
+            .zip(&basis_normals)
+            .map(|(normal, basis)| *normal - *basis)
+            .collect::<Vec<Vec3>>();
+
+        targets.names.push(block.get_string("name"));
+        targets.position_deltas.push(
+            position_deltas
+                .iter()
+                .map(|d| conversion.position(*d))
+                .collect(),
+        );
+        targets.normal_deltas.push(
+            normal_deltas
+                .iter()
+                .map(|d| conversion.direction(*d))
+                .collect(),
+        );
+    }
+
+    Ok(targets)
+}
+
+/// Reads the vertex positions of a Blend::Instance shape key, in the Blender coordinate system.
+/// Vertices missing from the shape key keep their position in the mesh.
+fn key_block_positions(block: &Instance, mesh_positions: &[Vec3]) -> Vec<Vec3> {
+    let mut positions = mesh_positions.to_vec();
+    if !block.is_valid("data") {
+        return positions;
+    }
+
+    // The data of mesh shape keys is an array of float[3]
+    let data = block.get_u8_vec("data");
+    for (position, co) in positions.iter_mut().zip(data.chunks_exact(12)) {
+        let float = |i: usize| f32::from_le_bytes([co[i], co[i + 1], co[i + 2], co[i + 3]]);
+        *position = Vec3::new(float(0), float(4), float(8));
+    }
+    positions
+}
+
+/// Creates the MorphWeights of a Blend::Instance mesh, from the current values of its shape keys.
+/// Muted shape keys have no weight.
+pub(crate) fn instance_to_morph_weights(mesh: &Instance) -> MorphWeights {
+    let blocks = key_blocks(mesh);
+    MorphWeights {
+        names: blocks
+            .iter()
+            .skip(1)
+            .map(|block| block.get_string("name"))
+            .collect(),
+        weights: blocks
+            .iter()
+            .skip(1)
+            .map(|block| {
+                if get_int(block, "flag") & KEYBLOCK_MUTE != 0 {
+                    0.0
+                } else {
+                    block.get_f32("curval")
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Returns the MorphWeights of a Blend::Instance mesh at "frame", with the F-curves of the action of
+/// its shape keys evaluated. Muted shape keys have no weight.
+pub(crate) fn evaluated_morph_weights(mesh: &Instance, frame: f32) -> MorphWeights {
+    let mut weights = instance_to_morph_weights(mesh);
+    if !mesh.is_valid("key") {
+        return weights;
+    }
+    let fcurves = animation::object_fcurves(&mesh.get("key"));
+    for (block, weight) in key_blocks(mesh).iter().skip(1).zip(&mut weights.weights) {
+        if get_int(block, "flag") & KEYBLOCK_MUTE != 0 {
+            continue;
+        }
+        let name = block.get_string("name");
+        if let Some(fcurve) = fcurves.iter().find(|fcurve| {
+            fcurve.property() == "value"
+                && animation::quoted_name(fcurve.owner(), "key_blocks").as_deref() == Some(&name)
+        }) {
+            *weight = fcurve.evaluate(frame);
+        }
+    }
+    weights
+}
+
+// Mesh entities with morph targets whose weights changed or whose mesh was not copied yet
+type MorphTargetsQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static MorphWeights,
+        &'static Handle<BlenderMorphTargets>,
+        &'static mut Handle<Mesh>,
+        Option<&'static MorphedMesh>,
+    ),
+    Or<(Changed<MorphWeights>, Without<MorphedMesh>)>,
+>;
+
+/// Blends the morph targets of mesh entities into a copy of their mesh when their MorphWeights
+/// change, on the CPU
+pub(crate) fn apply_morph_weights(
+    mut commands: Commands,
+    mut morphed: MorphTargetsQuery,
+    targets: Res<Assets<BlenderMorphTargets>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for (entity, weights, targets_handle, mut mesh_handle, morphed_mesh) in &mut morphed {
+        let Some(targets) = targets.get(targets_handle) else {
+            continue;
+        };
+
+        // The mesh is shared by every entity using it until it is morphed
+        if morphed_mesh.is_none() {
+            let Some(mesh) = meshes.get(&*mesh_handle).cloned() else {
+                continue;
+            };
+            *mesh_handle = meshes.add(mesh);
+            commands.entity(entity).insert(MorphedMesh);
+        }
+        let Some(mesh) = meshes.get_mut(&*mesh_handle) else {
+            continue;
+        };
+
+        let mut positions = targets.positions.clone();
+        let mut normals = targets.normals.clone();
+        for (i, weight) in weights.weights.iter().enumerate() {
+            if *weight == 0.0 {
+                continue;
+            }
+            let (Some(position_deltas), Some(normal_deltas)) =
+                (targets.position_deltas.get(i), targets.normal_deltas.get(i))
+            else {
+                continue;
+            };
+            for (position, delta) in positions.iter_mut().zip(position_deltas) {
+                *position += *delta * *weight;
+            }
+            for (normal, delta) in normals.iter_mut().zip(normal_deltas) {
+                *normal += *delta * *weight;
+            }
+        }
+
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            positions
+                .into_iter()
+                .map(<[f32; 3]>::from)
+                .collect::<Vec<_>>(),
+        );
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_NORMAL,
+            normals
+                .into_iter()
+                .map(|normal| normal.normalize_or_zero().into())
+                .collect::<Vec<[f32; 3]>>(),
+        );
+    }
+}
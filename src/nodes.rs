@@ -0,0 +1,46 @@
+use blend::Instance;
+
+/// Returns the first node of a Blend::Instance node tree with the given idname
+/// (e.g. "ShaderNodeBackground"), or None if the tree does not have one
+pub(crate) fn find_node<'a>(tree: &Instance<'a>, idname: &str) -> Option<Instance<'a>> {
+    if !tree.is_valid("nodes") {
+        return None;
+    }
+
+    tree.get_iter("nodes")
+        .find(|node| node.get_string("idname") == idname)
+}
+
+/// Returns the input socket of a node with the given name, or None if the node does not have one
+pub(crate) fn input_socket<'a>(node: &Instance<'a>, name: &str) -> Option<Instance<'a>> {
+    if !node.is_valid("inputs") {
+        return None;
+    }
+
+    node.get_iter("inputs")
+        .find(|socket| socket.get_string("name") == name)
+}
+
+/// Returns true if another node is linked into the socket
+pub(crate) fn is_linked(socket: &Instance) -> bool {
+    socket.is_valid("link")
+}
+
+/// Returns the value of an unlinked color socket, or None if the socket is linked
+pub(crate) fn socket_rgba(socket: &Instance) -> Option<[f32; 4]> {
+    if is_linked(socket) || !socket.is_valid("default_value") {
+        return None;
+    }
+
+    let value = socket.get("default_value").get_f32_vec("value");
+    Some([value[0], value[1], value[2], value[3]])
+}
+
+/// Returns the value of an unlinked float socket, or None if the socket is linked
+pub(crate) fn socket_float(socket: &Instance) -> Option<f32> {
+    if is_linked(socket) || !socket.is_valid("default_value") {
+        return None;
+    }
+
+    Some(socket.get("default_value").get_f32("value"))
+}
@@ -1,8 +1,20 @@
-use crate::{right_hand_zup_to_right_hand_yup, BevyBlenderError};
+use crate::{
+    animation, armature, camera, constraint,
+    coordinates::{BlenderCoordinateSettings, Conversion},
+    get_blend_version, instancing, light, light_probe, material,
+    morph::{self, BlenderMorphTargets},
+    parenting, particles,
+    property::BlenderPropertyPlayer,
+    BevyBlenderError,
+};
+use bevy_animation::AnimationPlayer;
 use bevy_asset::{AssetServer, Handle};
+use bevy_core::Name;
 use bevy_ecs::{
     bundle::Bundle,
-    system::{Commands, ResMut},
+    entity::Entity,
+    system::{CommandQueue, Commands, EntityCommands},
+    world::World,
 };
 use bevy_hierarchy::{BuildChildren, ChildBuilder};
 use bevy_log::error;
@@ -10,11 +22,17 @@ use bevy_math::{Mat4, Vec4};
 use bevy_pbr::prelude::StandardMaterial;
 use bevy_render::{
     mesh::Mesh,
-    prelude::{ComputedVisibility, Visibility},
+    prelude::{ComputedVisibility, SpatialBundle, Visibility},
 };
 use bevy_transform::prelude::{GlobalTransform, Transform};
 use blend::{Blend, Instance};
 
+// Object.type values (DNA_object_types.h)
+pub(crate) const OB_MESH: i16 = 1;
+pub(crate) const OB_LAMP: i16 = 10;
+pub(crate) const OB_CAMERA: i16 = 11;
+pub(crate) const OB_LIGHTPROBE: i16 = 13;
+
 /// A component bundle for Blender Object entities modeled after bevy_pbr::MaterialMeshBundle
 #[derive(Bundle)]
 pub struct BlenderObjectBundle {
@@ -49,9 +67,25 @@ impl BlenderObjectBundle {
     /// Creates a new BlenderObjectBundle from a .blend file path and an object within it
     /// It will automatically apply the Blender object's transform and material if applicable
     pub fn new(
-        asset_server: &ResMut<AssetServer>,
+        asset_server: &AssetServer,
         blender_file: &str,
         object_name: &str,
+    ) -> anyhow::Result<Self> {
+        Self::new_with_settings(
+            asset_server,
+            blender_file,
+            object_name,
+            &BlenderCoordinateSettings::default(),
+        )
+    }
+
+    /// Creates a new BlenderObjectBundle like new, with its transform converted with
+    /// "coordinate_settings" instead of the default ones
+    pub fn new_with_settings(
+        asset_server: &AssetServer,
+        blender_file: &str,
+        object_name: &str,
+        coordinate_settings: &BlenderCoordinateSettings,
     ) -> anyhow::Result<Self> {
         let blend = Blend::from_path(
             std::env::current_dir()
@@ -59,16 +93,40 @@ impl BlenderObjectBundle {
                 .join(std::path::PathBuf::from("assets").join(blender_file)),
         );
 
-        Self::new_from_blend(asset_server, &blend, blender_file, object_name)
+        Self::new_from_blend_with_settings(
+            asset_server,
+            &blend,
+            blender_file,
+            object_name,
+            coordinate_settings,
+        )
     }
 
     /// Creates a new BlenderObjectBundle from a Blend object
     /// It will automatically apply the Blender object's transform and material if applicable
     pub fn new_from_blend(
-        asset_server: &ResMut<AssetServer>,
+        asset_server: &AssetServer,
         blend: &Blend,
         blender_file: &str,
         object_name: &str,
+    ) -> anyhow::Result<Self> {
+        Self::new_from_blend_with_settings(
+            asset_server,
+            blend,
+            blender_file,
+            object_name,
+            &BlenderCoordinateSettings::default(),
+        )
+    }
+
+    /// Creates a new BlenderObjectBundle like new_from_blend, with its transform converted with
+    /// "coordinate_settings" instead of the default ones
+    pub fn new_from_blend_with_settings(
+        asset_server: &AssetServer,
+        blend: &Blend,
+        blender_file: &str,
+        object_name: &str,
+        coordinate_settings: &BlenderCoordinateSettings,
     ) -> anyhow::Result<Self> {
         let obj = match get_object_by_name(blend, format!("OB{}", object_name).as_str()) {
             Some(o) => o,
@@ -82,15 +140,15 @@ impl BlenderObjectBundle {
 
         // Get the first material, if it is not a nodes based material
         // TODO: load all materials instead of just the first
+        let world_matrix = get_world_matrix(&obj);
         let mut materials = obj.get("data").get_iter("mat");
         let material: Handle<StandardMaterial> = match materials.next() {
             None => Handle::default(),
             Some(material) => {
                 if (material.get_char("use_nodes") as u8) == 0 {
-                    asset_server.load(
-                        format!("{}#{}", blender_file, material.get("id").get_string("name"))
-                            .as_str(),
-                    )
+                    let label =
+                        material_label(&material.get("id").get_string("name"), &world_matrix);
+                    asset_server.load(format!("{}#{}", blender_file, label).as_str())
                 } else {
                     Handle::default()
                 }
@@ -98,8 +156,7 @@ impl BlenderObjectBundle {
         };
 
         // Get transform
-        let world_matrix = get_world_matrix(&obj);
-        let corrected_matrix = right_hand_zup_to_right_hand_yup(&world_matrix);
+        let corrected_matrix = Conversion::new(blend, coordinate_settings).matrix(&world_matrix);
         let transform = Transform::from_matrix(corrected_matrix);
 
         return Ok(Self {
@@ -143,11 +200,80 @@ fn get_children<'a>(blend: &'a Blend, name: &str) -> Vec<Instance<'a>> {
     children
 }
 
+/// Returns the children of "object", their children and so on
+fn get_descendants<'a>(blend: &'a Blend, object: &Instance) -> Vec<Instance<'a>> {
+    let mut descendants = get_children(blend, object.get("id").get_string("name").as_str());
+    let mut i = 0;
+    while i < descendants.len() {
+        let children = get_children(blend, descendants[i].get("id").get_string("name").as_str());
+        descendants.extend(children);
+        i += 1;
+    }
+    descendants
+}
+
+/// Returns the name of an object without its "OB" prefix, which is also the Name of its entity
+pub(crate) fn object_name(object: &Instance) -> String {
+    object.get("id").get_string("name")[2..].to_string()
+}
+
+/// Returns the names of an object and its parents, starting with the top most parent, or with the
+/// parent whose ID name is "root" (e.g. "OBHouse"), which may be the object itself. Returns None
+/// if the object is not below "root".
+pub(crate) fn object_path(object: &Instance, root: Option<&str>) -> Option<Vec<String>> {
+    let mut path = Vec::new();
+    let mut current = Some(object.clone());
+    while let Some(obj) = current {
+        path.push(object_name(&obj));
+        if root == Some(obj.get("id").get_string("name").as_str()) {
+            path.reverse();
+            return Some(path);
+        }
+        current = obj.is_valid("parent").then(|| obj.get("parent"));
+    }
+    path.reverse();
+    root.is_none().then_some(path)
+}
+
+/// Returns the label of the material of an object placed at "world_matrix", which is the variant
+/// culling front faces if the object is mirrored (its matrix has a negative determinant)
+fn material_label(label: &str, world_matrix: &Mat4) -> String {
+    if world_matrix.determinant() < 0.0 {
+        material::mirrored_label(label)
+    } else {
+        label.to_string()
+    }
+}
+
 /// Get the world relative 4x4 matrix of an object
 /// This will be in Blender coordinate system (Right Handed, Z-up)
-fn get_world_matrix(object: &Instance) -> Mat4 {
-    // world matrix comes in as a flattend row major 4x4 matrix
-    let w = object.get_f32_vec("obmat");
+pub(crate) fn get_world_matrix(object: &Instance) -> Mat4 {
+    get_matrix(object, "obmat")
+}
+
+/// Get the matrix of an object relative to its parent's world matrix, "parent_matrix", with its
+/// animation evaluated at "frame" if there is one. Objects are placed by their parent inverse
+/// matrix and their transform channels like in Blender, relative to the bone or the vertices they
+/// are parented to if they are, which does not depend on their parent's matrix being invertible.
+/// This will be in Blender coordinate system (Right Handed, Z-up)
+fn child_local_matrix(child: &Instance, parent_matrix: Mat4, frame: Option<f32>) -> Mat4 {
+    parenting::local_parent_matrix(child, parent_matrix, frame)
+        * animation::evaluated_local_matrix(child, frame)
+}
+
+/// Get the world matrix of an object, with its animation evaluated at "frame" if there is one
+/// This will be in Blender coordinate system (Right Handed, Z-up)
+fn object_matrix(object: &Instance, frame: Option<f32>) -> Mat4 {
+    match frame {
+        Some(frame) => animation::evaluated_world_matrix(object, frame),
+        None => get_world_matrix(object),
+    }
+}
+
+/// Reads a 4x4 matrix field (e.g. "obmat" or "parentinv") of a Blend::Instance
+pub(crate) fn get_matrix(instance: &Instance, name: &str) -> Mat4 {
+    // matrices come in as a flattend row major 4x4 matrix
+    let w = instance.get_f32_vec(name);
 
     Mat4::from_cols(
         Vec4::from_slice(&w[0..4]),   // x axis
@@ -163,37 +289,104 @@ fn get_world_matrix(object: &Instance) -> Mat4 {
 /// BlenderObjectBundles and spawed as children of the root object. If parent_transform is Some(t),
 /// then t will be used as it's transform. If parent_transform is None, then the object's Blender transform
 /// will be used (and converted to the Bevy coordinate system).
+/// The root object gets an AnimationPlayer, playing the root object's action if it has one. Actions
+/// of its children can be played with it too, e.g. asset_server.load(blender_action!("demo.blend", "DoorOpen")).
+/// The root object also gets a BlenderAnimationClips with the clips of the actions and NLA strips of
+/// the spawned objects, by name.
+/// The object is spawned when the commands are applied, with the BlenderCoordinateSettings
+/// resource of the world at that time.
 pub fn spawn_blender_object(
     commands: &mut Commands,
-    asset_server: &ResMut<AssetServer>,
+    asset_server: &AssetServer,
     blender_file: &str,
     root_object_name: &str,
     spawn_children: bool,
     parent_transform: Option<Transform>,
 ) {
-    match spawn_blender_object_with_error(
+    spawn_blender_object_deferred(
         commands,
         asset_server,
         blender_file,
         root_object_name,
         spawn_children,
         parent_transform,
-    ) {
-        Ok(_) => {}
-        Err(e) => {
-            error!("{}", e);
+        None,
+    );
+}
+
+/// Spawns an object like spawn_blender_object, as a snapshot of its animation at "frame": the
+/// F-curves of the actions of the spawned objects and of the poses of their armatures are
+/// evaluated at that frame, instead of using their saved transforms and poses. The AnimationPlayer
+/// of the root object does not play anything, so the snapshot stays still.
+pub fn spawn_blender_object_at_frame(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    blender_file: &str,
+    root_object_name: &str,
+    spawn_children: bool,
+    parent_transform: Option<Transform>,
+    frame: f32,
+) {
+    spawn_blender_object_deferred(
+        commands,
+        asset_server,
+        blender_file,
+        root_object_name,
+        spawn_children,
+        parent_transform,
+        Some(frame),
+    );
+}
+
+/// Spawns an object once the commands are applied, when the BlenderCoordinateSettings resource
+/// can be read
+fn spawn_blender_object_deferred(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    blender_file: &str,
+    root_object_name: &str,
+    spawn_children: bool,
+    parent_transform: Option<Transform>,
+    frame: Option<f32>,
+) {
+    let asset_server = asset_server.clone();
+    let blender_file = blender_file.to_string();
+    let root_object_name = root_object_name.to_string();
+    commands.add(move |world: &mut World| {
+        let coordinate_settings = world
+            .get_resource::<BlenderCoordinateSettings>()
+            .copied()
+            .unwrap_or_default();
+        let mut queue = CommandQueue::default();
+        let result = spawn_blender_object_with_error(
+            &mut Commands::new(&mut queue, world),
+            &asset_server,
+            &blender_file,
+            &root_object_name,
+            spawn_children,
+            parent_transform,
+            frame,
+            &coordinate_settings,
+        );
+        queue.apply(world);
+        match result {
+            Ok(root) => constraint::set_constraint_roots(world, root),
+            Err(e) => error!("{}", e),
         }
-    }
+    });
 }
 
+#[allow(clippy::too_many_arguments)]
 fn spawn_blender_object_with_error(
     commands: &mut Commands,
-    asset_server: &ResMut<AssetServer>,
+    asset_server: &AssetServer,
     blender_file: &str,
     root_object_name: &str,
     spawn_children: bool,
     parent_transform: Option<Transform>,
-) -> anyhow::Result<()> {
+    frame: Option<f32>,
+    coordinate_settings: &BlenderCoordinateSettings,
+) -> anyhow::Result<Entity> {
     // Read blend file, we will pass this along to recurisive calls
     let blend = Blend::from_path(
         std::env::current_dir()
@@ -212,125 +405,361 @@ fn spawn_blender_object_with_error(
         }
     };
 
-    // Get mesh
-    let mesh = asset_server.load(
-        format!(
-            "{}#{}",
-            blender_file,
-            obj.get("data").get("id").get_string("name")
-        )
-        .as_str(),
-    );
-
-    // Get the first material, if it is not a nodes based material
-    // TODO: load all materials instead of just the first
-    let material: Handle<StandardMaterial> = if obj.get("data").is_valid("mat") {
-        let mut materials = obj.get("data").get_iter("mat");
-        match materials.next() {
-            None => asset_server
-                .load(format!("{}#{}", blender_file, "bevy_blender_missing_material").as_str()),
-            Some(material) => asset_server.load(
-                format!("{}#{}", blender_file, material.get("id").get_string("name")).as_str(),
-            ),
-        }
-    } else {
-        asset_server.load(format!("{}#{}", blender_file, "bevy_blender_missing_material").as_str())
-    };
-
     // Get the object's transform
-    let world_matrix = get_world_matrix(&obj);
+    let conversion = Conversion::new(&blend, coordinate_settings);
+    let world_matrix = object_matrix(&obj, frame);
     let transform = match parent_transform {
         Some(t) => t,
         None => {
-            let corrected_matrix = right_hand_zup_to_right_hand_yup(&world_matrix);
+            let corrected_matrix = conversion.matrix(&world_matrix);
             Transform::from_matrix(corrected_matrix)
         }
     };
 
-    // Create BlenderObjectBundle
-    let bundle = BlenderObjectBundle {
-        mesh,
-        material,
-        transform,
-        ..Default::default()
+    // Spawn the object along with the components of its data, and the animation player of the
+    // object and its children
+    let mut entity = commands.spawn(SpatialBundle::from_transform(transform));
+    insert_object_data(
+        &mut entity,
+        asset_server,
+        &blend,
+        &conversion,
+        blender_file,
+        &obj,
+        &transform.compute_matrix(),
+    );
+    match frame {
+        Some(_) => entity.insert((AnimationPlayer::default(), BlenderPropertyPlayer::default())),
+        None => entity.insert(animation::animation_player(
+            asset_server,
+            blender_file,
+            &obj,
+        )),
     };
-
-    // Spawn Bundle
-    commands.spawn(bundle).with_children(|parent| {
+    let mut animated_objects = vec![obj.clone()];
+    if spawn_children {
+        animated_objects.extend(get_descendants(&blend, &obj));
+    }
+    entity.insert(animation::animation_clips(
+        asset_server,
+        blender_file,
+        &blend,
+        &obj,
+        &animated_objects,
+    ));
+    entity.with_children(|parent| {
+        armature::spawn_joints(parent, &obj, frame, &conversion, &mut |joint, bone| {
+            if spawn_children {
+                spawn_bone_children(
+                    joint,
+                    asset_server,
+                    &blend,
+                    &conversion,
+                    blender_file,
+                    &obj,
+                    bone,
+                    frame,
+                );
+            }
+        });
+        spawn_particle_instances(
+            parent,
+            asset_server,
+            &blend,
+            &conversion,
+            blender_file,
+            &obj,
+            world_matrix,
+            frame,
+        );
         if !spawn_children {
             return;
         }
-        for child in get_children(&blend, format!("OB{}", root_object_name).as_str()) {
+        spawn_object_children(
+            parent,
+            asset_server,
+            &blend,
+            &conversion,
+            blender_file,
+            &obj,
+            world_matrix,
+            frame,
+        );
+    });
+
+    Ok(entity.id())
+}
+
+/// Inserts the Name of an object and the components of its data into the object's entity. Mesh
+/// objects get their mesh and material (and are skinned if deformed by an armature), light probes
+/// get a BlenderLightProbe, lights and cameras get a child light or camera entity and other objects
+/// (e.g. empties) nothing. The joints of armatures are spawned as children by spawn_joints.
+/// Mirrored objects, whose "world_matrix" has a negative determinant, get the mirrored variant of
+/// their material.
+fn insert_object_data(
+    entity: &mut EntityCommands,
+    asset_server: &AssetServer,
+    blend: &Blend,
+    conversion: &Conversion,
+    blender_file: &str,
+    obj: &Instance,
+    world_matrix: &Mat4,
+) {
+    // Names are used by animation clips to find the entities they animate
+    entity.insert(Name::new(object_name(obj)));
+
+    // Constraints are evaluated by the constraint system, even on empties
+    if let Some(constraints) = constraint::instance_to_constraints(obj, conversion) {
+        entity.insert(constraints);
+    }
+
+    if !obj.is_valid("data") {
+        return;
+    }
+    let data = obj.get("data");
+
+    match obj.get_i16("type") {
+        OB_MESH => {
+            // Get mesh
+            let mesh: Handle<Mesh> = asset_server
+                .load(format!("{}#{}", blender_file, data.get("id").get_string("name")).as_str());
+
+            // Get the first material, if it is not a nodes based material
+            // TODO: load all materials instead of just the first
+            let label = match data.is_valid("mat").then(|| data.get_iter("mat").next()) {
+                Some(Some(material)) => material.get("id").get_string("name"),
+                _ => String::from("bevy_blender_missing_material"),
+            };
+            let material: Handle<StandardMaterial> = asset_server.load(
+                format!("{}#{}", blender_file, material_label(&label, world_matrix)).as_str(),
+            );
+
+            entity.insert((mesh, material));
+
+            // Meshes with shape keys are morphed by the weights of their shape keys
+            if morph::has_shape_keys(&data) {
+                let morph_targets: Handle<BlenderMorphTargets> = asset_server.load(
+                    format!("{}#{}", blender_file, morph::morph_targets_label(&data)).as_str(),
+                );
+                entity.insert((morph::instance_to_morph_weights(&data), morph_targets));
+            }
+
+            // Meshes deformed by an armature are skinned by its joints
+            if let Some(armature) = armature::object_armature(obj) {
+                entity.insert(armature::pending_skinned_mesh(
+                    asset_server,
+                    blender_file,
+                    obj,
+                    &armature,
+                ));
+            }
+        }
+        OB_LAMP => light::spawn_light(entity, &data, conversion),
+        OB_CAMERA => camera::spawn_camera(
+            entity,
+            &data,
+            camera::scene_aspect_ratio(blend),
+            camera::is_scene_camera(blend, obj),
+            conversion,
+        ),
+        OB_LIGHTPROBE => match light_probe::instance_to_light_probe(data.clone(), conversion) {
+            Ok(mut light_probe) => {
+                light_probe.environment_map =
+                    light_probe::light_probe_environment_map(asset_server, blender_file, &data);
+                entity.insert(light_probe);
+            }
+            Err(e) => error!("{}", e),
+        },
+        _ => {}
+    }
+}
+
+/// Spawns all of the children of "obj", except those parented to one of its bones, which are
+/// spawned by spawn_bone_children. If "obj" instances its children at its vertices or faces, a
+/// copy of each child is spawned at every instance location instead of the child itself.
+#[allow(clippy::too_many_arguments)]
+fn spawn_object_children(
+    builder: &mut ChildBuilder,
+    asset_server: &AssetServer,
+    blend: &Blend,
+    conversion: &Conversion,
+    blender_file: &str,
+    obj: &Instance,
+    world_matrix: Mat4,
+    frame: Option<f32>,
+) {
+    let is_instancer = instancing::is_instancer(obj);
+
+    for child in get_children(blend, obj.get("id").get_string("name").as_str()) {
+        if parenting::parent_bone(&child)
+            .is_some_and(|bone| armature::find_bone(obj, &bone).is_some())
+        {
+            continue;
+        }
+
+        if is_instancer {
+            // Every instance loads the same mesh and material handles from the asset server
+            for local_matrix in instancing::instance_matrices(
+                obj,
+                &child,
+                child_local_matrix(&child, object_matrix(obj, frame), frame),
+                get_blend_version(blend),
+            ) {
+                spawn_children_objects(
+                    builder,
+                    asset_server,
+                    blend,
+                    conversion,
+                    blender_file,
+                    child.clone(),
+                    world_matrix,
+                    local_matrix,
+                    frame,
+                );
+            }
+        } else {
+            let local_matrix = child_local_matrix(&child, world_matrix, frame);
             spawn_children_objects(
-                parent,
+                builder,
                 asset_server,
-                &blend,
+                blend,
+                conversion,
                 blender_file,
                 child,
                 world_matrix,
+                local_matrix,
+                frame,
             );
         }
-    });
+    }
+}
 
-    Ok(())
+/// Spawns the children of an armature object parented to "bone" under the joint entity of the
+/// bone, relative to the tail of the bone like in Blender
+#[allow(clippy::too_many_arguments)]
+fn spawn_bone_children(
+    builder: &mut ChildBuilder,
+    asset_server: &AssetServer,
+    blend: &Blend,
+    conversion: &Conversion,
+    blender_file: &str,
+    armature: &Instance,
+    bone: &Instance,
+    frame: Option<f32>,
+) {
+    let bone_name = bone.get_string("name");
+    let joint_matrix =
+        object_matrix(armature, frame) * armature::pose_bone_matrix(armature, bone, frame);
+
+    for child in get_children(blend, armature.get("id").get_string("name").as_str()) {
+        if parenting::parent_bone(&child).as_ref() != Some(&bone_name) {
+            continue;
+        }
+        let local_matrix =
+            armature::bone_tail_offset(bone) * animation::evaluated_local_matrix(&child, frame);
+        spawn_children_objects(
+            builder,
+            asset_server,
+            blend,
+            conversion,
+            blender_file,
+            child,
+            joint_matrix,
+            local_matrix,
+            frame,
+        );
+    }
+}
+
+/// Spawns the objects instanced by the particle systems of "obj" at every particle. The
+/// instances are spawned as children of "obj" and share their mesh and material handles.
+#[allow(clippy::too_many_arguments)]
+fn spawn_particle_instances(
+    builder: &mut ChildBuilder,
+    asset_server: &AssetServer,
+    blend: &Blend,
+    conversion: &Conversion,
+    blender_file: &str,
+    obj: &Instance,
+    world_matrix: Mat4,
+    frame: Option<f32>,
+) {
+    for (instance, local_matrix) in particles::particle_instances(obj, get_blend_version(blend)) {
+        spawn_children_objects(
+            builder,
+            asset_server,
+            blend,
+            conversion,
+            blender_file,
+            instance,
+            world_matrix,
+            local_matrix,
+            frame,
+        );
+    }
 }
 
-/// Helper recursive function called by spawn_blender_object to spawn children
+/// Helper recursive function called by spawn_blender_object to spawn children. "local_matrix" is
+/// the matrix the object is spawned at relative to its parent entity, placed at "parent_matrix",
+/// which differs from its Blender one for instances.
+#[allow(clippy::too_many_arguments)]
 fn spawn_children_objects(
     builder: &mut ChildBuilder,
-    asset_server: &ResMut<AssetServer>,
+    asset_server: &AssetServer,
     blend: &Blend,
+    conversion: &Conversion,
     blender_file: &str,
     obj: Instance,
     parent_matrix: Mat4,
+    local_matrix: Mat4,
+    frame: Option<f32>,
 ) {
-    // Get mesh
-    let mesh = asset_server.load(
-        format!(
-            "{}#{}",
-            blender_file,
-            obj.get("data").get("id").get_string("name")
-        )
-        .as_str(),
-    );
-
-    // Get the first material, if it is not a nodes based material
-    // TODO: load all materials instead of just the first
-    let mut materials = obj.get("data").get_iter("mat");
-    let material: Handle<StandardMaterial> = match materials.next() {
-        None => asset_server
-            .load(format!("{}#{}", blender_file, "bevy_blender_missing_material").as_str()),
-        Some(material) => asset_server
-            .load(format!("{}#{}", blender_file, material.get("id").get_string("name")).as_str()),
-    };
-
-    // Get the global transform matrix
-    let world_matrix = get_world_matrix(&obj);
-    // Calculate local matrix from global matrix and parent matrix
-    // L = P' * W
-    let local_matrix = parent_matrix.inverse().mul_mat4(&world_matrix);
-    let corrected_local_matrix = right_hand_zup_to_right_hand_yup(&local_matrix);
+    let corrected_local_matrix = conversion.matrix(&local_matrix);
     let transform = Transform::from_matrix(corrected_local_matrix);
 
-    // Create BlenderObjectBundle
-    let bundle = BlenderObjectBundle {
-        mesh,
-        material,
-        transform,
-        ..Default::default()
-    };
-
-    // Spawn Bundle
-    builder.spawn(bundle).with_children(|parent| {
-        for child in get_children(&blend, obj.get("id").get_string("name").as_str()) {
-            spawn_children_objects(
-                parent,
+    // Spawn the object, its children are placed relative to the object's Blender world matrix
+    let mut entity = builder.spawn(SpatialBundle::from_transform(transform));
+    insert_object_data(
+        &mut entity,
+        asset_server,
+        blend,
+        conversion,
+        blender_file,
+        &obj,
+        &(parent_matrix * local_matrix),
+    );
+    entity.with_children(|parent| {
+        armature::spawn_joints(parent, &obj, frame, conversion, &mut |joint, bone| {
+            spawn_bone_children(
+                joint,
                 asset_server,
-                &blend,
+                blend,
+                conversion,
                 blender_file,
-                child,
-                world_matrix,
+                &obj,
+                bone,
+                frame,
             );
-        }
+        });
+        spawn_particle_instances(
+            parent,
+            asset_server,
+            blend,
+            conversion,
+            blender_file,
+            &obj,
+            object_matrix(&obj, frame),
+            frame,
+        );
+        spawn_object_children(
+            parent,
+            asset_server,
+            blend,
+            conversion,
+            blender_file,
+            &obj,
+            object_matrix(&obj, frame),
+            frame,
+        );
     });
 }
@@ -0,0 +1,112 @@
+use bevy_math::{Mat3, Mat4, Vec3};
+use blend::Instance;
+
+use crate::{
+    armature::{self, OB_ARMATURE},
+    mesh,
+    object::OB_MESH,
+};
+
+// Object.partype values (DNA_object_types.h), PARTYPE masks the type out of the flags
+const PARTYPE: i16 = 15;
+pub(crate) const PARSKEL: i16 = 4;
+pub(crate) const PARVERT1: i16 = 5;
+pub(crate) const PARVERT3: i16 = 6;
+pub(crate) const PARBONE: i16 = 7;
+
+/// Returns how an object is parented, one of the Object.partype values
+pub(crate) fn parent_type(obj: &Instance) -> i16 {
+    obj.get_i16("partype") & PARTYPE
+}
+
+/// Returns the name of the bone an object is parented to, if it is parented to a bone of an
+/// armature object
+pub(crate) fn parent_bone(obj: &Instance) -> Option<String> {
+    if !obj.is_valid("parent") || parent_type(obj) != PARBONE {
+        return None;
+    }
+    if obj.get("parent").get_i16("type") != OB_ARMATURE {
+        return None;
+    }
+    Some(obj.get_string("parsubstr")).filter(|name| !name.is_empty())
+}
+
+/// Returns the matrix that the parent inverse matrix and the transform channels of an object are
+/// relative to, from the world matrix of its parent (like BKE_object_get_parent_matrix). Objects
+/// parented to a bone follow the tail of the bone, posed like spawn_joints at "frame", and objects
+/// parented to vertices follow a vertex, or a triangle of three vertices, of the parent mesh.
+/// This will be in Blender coordinate system (Right Handed, Z-up)
+pub(crate) fn parent_matrix(obj: &Instance, parent_world: Mat4, frame: Option<f32>) -> Mat4 {
+    // A single vertex only moves its children, without rotating them
+    match (
+        parent_type(obj),
+        parent_vertex(&obj.get("parent"), obj.get_i32("par1")),
+    ) {
+        (PARVERT1, Some(position)) => {
+            Mat4::from_translation(parent_world.transform_point3(position))
+        }
+        _ => parent_world * local_parent_matrix(obj, parent_world, frame),
+    }
+}
+
+/// Returns the parent matrix of an object (see parent_matrix) relative to the world matrix of its
+/// parent, without inverting the world matrix. Only objects parented to a single vertex, which do
+/// not follow the rotation and scale of their parent, depend on it, and keep them if it is not
+/// invertible.
+/// This will be in Blender coordinate system (Right Handed, Z-up)
+pub(crate) fn local_parent_matrix(obj: &Instance, parent_world: Mat4, frame: Option<f32>) -> Mat4 {
+    let parent = obj.get("parent");
+    let vertex = |field: &str| parent_vertex(&parent, obj.get_i32(field));
+
+    match parent_type(obj) {
+        PARBONE => match parent_bone(obj).and_then(|name| armature::find_bone(&parent, &name)) {
+            Some(bone) => {
+                armature::pose_bone_matrix(&parent, &bone, frame)
+                    * armature::bone_tail_offset(&bone)
+            }
+            None => Mat4::IDENTITY,
+        },
+        PARVERT1 => match vertex("par1") {
+            Some(position) => {
+                let linear = Mat3::from_mat4(parent_world);
+                if linear.determinant() == 0.0 {
+                    Mat4::from_translation(position)
+                } else {
+                    Mat4::from_translation(position) * Mat4::from_mat3(linear.inverse())
+                }
+            }
+            None => Mat4::IDENTITY,
+        },
+        PARVERT3 => match (vertex("par1"), vertex("par2"), vertex("par3")) {
+            (Some(v1), Some(v2), Some(v3)) => triangle_matrix(v1, v2, v3),
+            _ => Mat4::IDENTITY,
+        },
+        _ => Mat4::IDENTITY,
+    }
+}
+
+/// Returns the position of a vertex of the mesh of a mesh object, if there is one at "index"
+fn parent_vertex(parent: &Instance, index: i32) -> Option<Vec3> {
+    if parent.get_i16("type") != OB_MESH || !parent.is_valid("data") || index < 0 {
+        return None;
+    }
+    mesh::vertex_position(&parent.get("data"), index as usize)
+}
+
+/// Returns the matrix at the center of a triangle, whose Z axis is the normal of the triangle and
+/// whose X axis points from its first vertex to its second one (like Blender's tri_to_quat)
+fn triangle_matrix(v1: Vec3, v2: Vec3, v3: Vec3) -> Mat4 {
+    let center = (v1 + v2 + v3) / 3.0;
+    let (Some(x), Some(z)) = (
+        (v2 - v1).try_normalize(),
+        (v2 - v1).cross(v3 - v1).try_normalize(),
+    ) else {
+        return Mat4::from_translation(center);
+    };
+    Mat4::from_cols(
+        x.extend(0.0),
+        z.cross(x).extend(0.0),
+        z.extend(0.0),
+        center.extend(1.0),
+    )
+}
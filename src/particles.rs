@@ -0,0 +1,219 @@
+use bevy_math::{Mat3, Mat4, Quat, Vec3};
+use blend::Instance;
+
+use crate::{get_int, mesh, object::get_world_matrix};
+
+// ParticleSettings.type values (DNA_particle_types.h)
+const PART_HAIR: i16 = 2;
+
+// ParticleSettings.from values
+const PART_FROM_VERT: i16 = 0;
+
+// ParticleSettings.ren_as values
+const PART_DRAW_OB: i16 = 7;
+const PART_DRAW_GR: i16 = 8;
+
+// ParticleSettings.draw bits
+const PART_DRAW_ROTATE_OB: i32 = 1 << 7;
+const PART_DRAW_WHOLE_GR: i32 = 1 << 14;
+const PART_DRAW_NO_SCALE_OB: i32 = 1 << 16;
+
+/// Returns every object instanced by the particle systems of "emitter" together with the matrix
+/// (in Blender coordinates) of each instance relative to the world matrix of "emitter". Hair
+/// particles are placed at the root of each hair and emitter particles at their emission location,
+/// both on the emitter mesh.
+pub(crate) fn particle_instances<'a>(
+    emitter: &Instance<'a>,
+    blend_version: (u8, u8, u8),
+) -> Vec<(Instance<'a>, Mat4)> {
+    let mut instances = Vec::new();
+
+    if !emitter.is_valid("particlesystem") || !emitter.is_valid("data") {
+        return instances;
+    }
+
+    let emitter_mesh = emitter.get("data");
+    if emitter_mesh.type_name != "Mesh" {
+        return instances;
+    }
+    let (positions, normals) = mesh::instance_to_vertices(&emitter_mesh, blend_version);
+    // Particles emitted from faces are on the tessellated faces of the mesh
+    let faces = mesh::instance_to_tessellated_faces(&emitter_mesh);
+
+    for particle_system in emitter.get_iter("particlesystem") {
+        if !particle_system.is_valid("part") || !particle_system.is_valid("particles") {
+            continue;
+        }
+        let settings = particle_system.get("part");
+
+        let objects = instanced_objects(&settings);
+        if objects.is_empty() {
+            continue;
+        }
+
+        let draw = get_int(&settings, "draw");
+        let is_hair = settings.get_i16("type") == PART_HAIR;
+        let from_vertices = settings.get_i16("from") == PART_FROM_VERT;
+        let whole_collection =
+            settings.get_i16("ren_as") == PART_DRAW_GR && draw & PART_DRAW_WHOLE_GR != 0;
+        let collection_offset = instanced_collection(&settings)
+            .map(|collection| collection_offset(&collection))
+            .unwrap_or(Vec3::ZERO);
+
+        for (index, particle) in particle_system.get_iter("particles").enumerate() {
+            let num = particle.get_i32("num");
+            if num < 0 {
+                continue;
+            }
+            let fuv = particle.get_f32_vec("fuv");
+
+            // Find the particle's root on the emitter, along with the hair space of that root
+            let (location, hair_space) = if from_vertices {
+                match positions.get(num as usize) {
+                    Some(position) => (*position, normal_space(normals[num as usize])),
+                    None => continue,
+                }
+            } else {
+                match faces.get(num as usize) {
+                    Some(face) if face.len() >= 3 => {
+                        let coords: Vec<Vec3> =
+                            face.iter().map(|v| positions[*v as usize]).collect();
+                        // fuv holds the interpolation weights of the (up to) four vertices
+                        let location = coords
+                            .iter()
+                            .zip(fuv.iter())
+                            .map(|(co, weight)| *co * *weight)
+                            .sum::<Vec3>();
+                        (location, face_space(&coords))
+                    }
+                    _ => continue,
+                }
+            };
+
+            // Hair instances follow the hair direction, emitter instances the emission normal
+            let direction = if is_hair && particle.is_valid("hair") {
+                let keys = particle.get_iter("hair").collect::<Vec<_>>();
+                match (keys.first(), keys.last()) {
+                    (Some(first), Some(last)) => hair_space.mul_vec3(
+                        Vec3::from_slice(&last.get_f32_vec("co"))
+                            - Vec3::from_slice(&first.get_f32_vec("co")),
+                    ),
+                    _ => hair_space.z_axis,
+                }
+            } else {
+                hair_space.z_axis
+            };
+
+            // Without particle rotation Blender aligns the X axis of the instance to the particle
+            let rotation = if direction.length_squared() > 0.0 {
+                Quat::from_rotation_arc(Vec3::X, direction.normalize())
+            } else {
+                Quat::IDENTITY
+            };
+
+            // The particle size already includes the random size variation
+            let size = match particle.get_f32("size") {
+                size if size > 0.0 => size,
+                _ => settings.get_f32("size"),
+            };
+
+            let particle_matrix =
+                Mat4::from_scale_rotation_translation(Vec3::splat(size), rotation, location);
+
+            let picked: Vec<&Instance<'a>> = if whole_collection {
+                objects.iter().collect()
+            } else {
+                vec![&objects[index % objects.len()]]
+            };
+
+            for object in picked {
+                // The instance keeps the scale, and optionally the rotation, of the object. Objects
+                // of a whole collection also keep their offset from the origin of the collection,
+                // scaled by the particle size like the object.
+                let (scale, object_rotation, object_translation) =
+                    get_world_matrix(object).to_scale_rotation_translation();
+                let mut object_transform = Mat4::IDENTITY;
+                if whole_collection {
+                    object_transform =
+                        Mat4::from_translation(object_translation - collection_offset);
+                }
+                if draw & PART_DRAW_ROTATE_OB != 0 {
+                    object_transform = object_transform.mul_mat4(&Mat4::from_quat(object_rotation));
+                }
+                if draw & PART_DRAW_NO_SCALE_OB == 0 {
+                    object_transform = object_transform.mul_mat4(&Mat4::from_scale(scale));
+                }
+
+                instances.push((object.clone(), particle_matrix.mul_mat4(&object_transform)));
+            }
+        }
+    }
+
+    instances
+}
+
+/// Returns the objects a particle system renders as, either a single object or the objects of
+/// a collection. Older versions of Blender call these "dup_ob" and "dup_group".
+fn instanced_objects<'a>(settings: &Instance<'a>) -> Vec<Instance<'a>> {
+    match settings.get_i16("ren_as") {
+        PART_DRAW_OB => pick_field(settings, "instance_object", "dup_ob")
+            .into_iter()
+            .collect(),
+        PART_DRAW_GR => match instanced_collection(settings) {
+            Some(collection) if collection.is_valid("gobject") => collection
+                .get_iter("gobject")
+                .filter(|collection_object| collection_object.is_valid("ob"))
+                .map(|collection_object| collection_object.get("ob"))
+                .collect(),
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// Hair space of a face root, the Z axis follows the face normal and the Y axis its first edge
+fn face_space(coords: &[Vec3]) -> Mat3 {
+    let normal = (coords[1] - coords[0])
+        .cross(coords[2] - coords[0])
+        .normalize_or_zero();
+    let y_axis = (coords[1] - coords[0]).normalize_or_zero();
+
+    Mat3::from_cols(y_axis.cross(normal), y_axis, normal)
+}
+
+/// Hair space of a vertex root, the Z axis follows the vertex normal
+fn normal_space(normal: Vec3) -> Mat3 {
+    match normal.try_normalize() {
+        Some(normal) => Mat3::from_quat(Quat::from_rotation_arc(Vec3::Z, normal)),
+        None => Mat3::IDENTITY,
+    }
+}
+
+/// Returns the field "new" of "instance", or the field "old" of older versions of Blender, if it
+/// is set
+fn pick_field<'a>(instance: &Instance<'a>, new: &str, old: &str) -> Option<Instance<'a>> {
+    if instance.is_valid(new) {
+        Some(instance.get(new))
+    } else if instance.is_valid(old) {
+        Some(instance.get(old))
+    } else {
+        None
+    }
+}
+
+/// Returns the collection a particle system renders as, if it renders as a collection
+fn instanced_collection<'a>(settings: &Instance<'a>) -> Option<Instance<'a>> {
+    (settings.get_i16("ren_as") == PART_DRAW_GR)
+        .then(|| pick_field(settings, "instance_collection", "dup_group"))
+        .flatten()
+}
+
+/// Returns the instance offset of a collection, the point of the collection placed at the
+/// particles. Older versions of Blender call it "dupli_ofs".
+fn collection_offset(collection: &Instance) -> Vec3 {
+    ["instance_offset", "dupli_ofs"]
+        .into_iter()
+        .find(|field| collection.fields.contains_key(*field))
+        .map(|field| Vec3::from_slice(&collection.get_f32_vec(field)))
+        .unwrap_or(Vec3::ZERO)
+}
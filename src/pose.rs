@@ -0,0 +1,168 @@
+use bevy_asset::{Assets, Handle};
+use bevy_core::Name;
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    system::{Query, Res},
+};
+use bevy_hierarchy::{Children, HierarchyQueryExt};
+use bevy_reflect::TypeUuid;
+use bevy_transform::prelude::Transform;
+use blend::{Blend, Instance};
+use std::collections::HashMap;
+
+use crate::{
+    animation::{self, TransformChannels},
+    armature,
+    coordinates::Conversion,
+    fcurve,
+};
+
+/// A pose asset, i.e. an action marked as an asset in Blender with all of its keyframes on the
+/// same frame (e.g. a hand shape or a facial expression). Holds the Transform of the joints of the
+/// posed bones, whose channels that are not keyed are at rest, and is labeled like the action
+/// followed by "/Pose", e.g. "robot.blend#ACFist/Pose".
+#[derive(Debug, Clone, Default, TypeUuid)]
+#[uuid = "ad099761-9eb7-4fcd-9a45-1087f0253b1f"]
+pub struct BlenderPose {
+    /// Transform of the joint of every posed bone, by bone name
+    pub bones: HashMap<String, Transform>,
+}
+
+/// Poses the joints below its entity, the armature, with BlenderPoses, applied in order and
+/// blended by their weight. Poses are applied after the AnimationPlayer, so they blend over the
+/// clip it plays, or over the Transform the joints had before being posed otherwise, which they
+/// get back once their poses are removed.
+#[derive(Component, Debug, Clone, Default)]
+pub struct BlenderPosePlayer {
+    /// Poses and their weight, from 0 (not applied) to 1 (fully applied)
+    pub poses: Vec<(Handle<BlenderPose>, f32)>,
+    // The Transform last given to every posed joint, and the Transform it was blended from
+    applied: HashMap<Entity, (Transform, Transform)>,
+}
+
+impl BlenderPosePlayer {
+    /// Replaces the poses by "pose", fully applied
+    pub fn apply(&mut self, pose: Handle<BlenderPose>) -> &mut Self {
+        self.poses = vec![(pose, 1.0)];
+        self
+    }
+
+    /// Blends "pose" by "weight" over the other poses
+    pub fn blend(&mut self, pose: Handle<BlenderPose>, weight: f32) -> &mut Self {
+        self.poses.push((pose, weight));
+        self
+    }
+
+    /// Removes every pose
+    pub fn clear(&mut self) -> &mut Self {
+        self.poses.clear();
+        self
+    }
+}
+
+/// Returns the label of the pose of an action, from the label of the action
+pub(crate) fn pose_label(label: &str) -> String {
+    format!("{}/Pose", label)
+}
+
+/// Converts a Blend::Instance action to a BlenderPose if it is marked as an asset and all of its
+/// keyframes are on the same frame. The bones are the ones of the first armature with all of the
+/// bones the action animates.
+pub(crate) fn instance_to_pose(
+    action: &Instance,
+    blend: &Blend,
+    conversion: &Conversion,
+) -> Option<BlenderPose> {
+    // Only Blender 3.0 and later have assets
+    let id = action.get("id");
+    if !id.fields.contains_key("asset_data") || !id.is_valid("asset_data") {
+        return None;
+    }
+
+    let fcurves = fcurve::instance_to_fcurves(action);
+    let mut frames = fcurves
+        .iter()
+        .flat_map(|fcurve| fcurve.keyframes.iter().map(|keyframe| keyframe.frame));
+    let frame = frames.next()?;
+    if frames.any(|other| other != frame) {
+        return None;
+    }
+    let armature = animation::matching_armature(blend, &fcurves)?;
+
+    let mut pose = BlenderPose::default();
+    for bone in armature::bones(&armature.get("data")) {
+        let name = bone.get_string("name");
+        let path = animation::pose_bone_path(&name);
+        if !fcurves.iter().any(|fcurve| fcurve.owner() == path) {
+            continue;
+        }
+
+        // Channels that are not keyed stay at rest
+        let rest = TransformChannels {
+            rotation_mode: armature::pose_channels(&armature, &name)
+                .unwrap_or_default()
+                .rotation_mode,
+            ..Default::default()
+        };
+        let channels = animation::evaluate_owner_channels(&fcurves, &path, rest, frame);
+        let matrix = armature::bone_rest_matrix(&bone) * channels.matrix();
+        pose.bones
+            .insert(name, Transform::from_matrix(conversion.matrix(&matrix)));
+    }
+    (!pose.bones.is_empty()).then_some(pose)
+}
+
+/// Blends two transforms by "weight"
+fn blend_transform(from: &Transform, to: &Transform, weight: f32) -> Transform {
+    Transform {
+        translation: from.translation.lerp(to.translation, weight),
+        rotation: from.rotation.slerp(to.rotation, weight),
+        scale: from.scale.lerp(to.scale, weight),
+    }
+}
+
+/// Poses the joints below every entity with a BlenderPosePlayer
+pub(crate) fn apply_poses(
+    mut players: Query<(Entity, &mut BlenderPosePlayer)>,
+    poses: Res<Assets<BlenderPose>>,
+    children: Query<&Children>,
+    mut joints: Query<(&Name, &mut Transform)>,
+) {
+    for (armature, mut player) in &mut players {
+        let player = &mut *player;
+        for joint in children.iter_descendants(armature) {
+            let Ok((name, mut transform)) = joints.get_mut(joint) else {
+                continue;
+            };
+
+            // Joints keep being posed from the same Transform, unless something else (e.g. the
+            // AnimationPlayer) changed it since they were posed
+            let base = match player.applied.get(&joint) {
+                Some((applied, base)) if applied == &*transform => *base,
+                _ => *transform,
+            };
+            let mut posed = None;
+            for (handle, weight) in &player.poses {
+                if let Some(bone) = poses
+                    .get(handle)
+                    .and_then(|pose| pose.bones.get(name.as_str()))
+                {
+                    posed = Some(blend_transform(&posed.unwrap_or(base), bone, *weight));
+                }
+            }
+
+            let posed = match posed {
+                Some(posed) => {
+                    player.applied.insert(joint, (posed, base));
+                    posed
+                }
+                None if player.applied.remove(&joint).is_some() => base,
+                None => continue,
+            };
+            if *transform != posed {
+                *transform = posed;
+            }
+        }
+    }
+}
@@ -0,0 +1,514 @@
+use bevy_animation::{AnimationPlayer, EntityPath};
+use bevy_asset::{Assets, Handle};
+use bevy_core::Name;
+use bevy_ecs::{
+    change_detection::DetectChangesMut,
+    component::Component,
+    entity::Entity,
+    event::EventWriter,
+    system::{Commands, Local, Query, Res, ResMut, SystemParam},
+};
+use bevy_hierarchy::Children;
+use bevy_pbr::{AlphaMode, DirectionalLight, PointLight, SpotLight, StandardMaterial};
+use bevy_reflect::TypeUuid;
+use bevy_render::{
+    camera::{Projection, ScalingMode},
+    color::Color,
+    prelude::Visibility,
+};
+use bevy_time::Time;
+use std::collections::HashSet;
+
+use crate::{
+    camera::BlenderCamera,
+    marker::{BlenderMarker, BlenderMarkerReached},
+    morph::MorphWeights,
+};
+
+/// A property of an entity, other than its Transform, animated by a BlenderPropertyClip. Light and
+/// camera properties animate the light or camera child of the entity of their object.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlenderPropertyChannel {
+    /// Weight of a morph target in the MorphWeights of the entity, named like its shape key
+    MorphWeight(String),
+    /// Component of the base color of the StandardMaterial of the entity, from red (0) to alpha (3)
+    MaterialBaseColor(usize),
+    /// Component of the emissive color of the StandardMaterial of the entity, from red (0) to
+    /// blue (2)
+    MaterialEmissive(usize),
+    /// Metallic of the StandardMaterial of the entity
+    MaterialMetallic,
+    /// Perceptual roughness of the StandardMaterial of the entity
+    MaterialRoughness,
+    /// Reflectance of the StandardMaterial of the entity
+    MaterialReflectance,
+    /// Component of the color of the light, from red (0) to blue (2)
+    LightColor(usize),
+    /// Intensity of the light, in lumens for point and spot lights and in lux for directional
+    /// lights
+    LightIntensity,
+    /// Focal length of a perspective camera, in millimeters, converted to the field of view of its
+    /// Projection by its BlenderCamera
+    CameraFocalLength,
+    /// Size of the view of an orthographic camera, in world units
+    CameraOrthographicScale,
+    /// Distance of the near clipping plane of the camera
+    CameraNear,
+    /// Distance of the far clipping plane of the camera
+    CameraFar,
+    /// Visibility of the entity, which is hidden when the value is above 0.5
+    Hidden,
+}
+
+/// Keyframes of a property of the entity at "path", interpolated linearly
+#[derive(Debug, Clone)]
+pub struct BlenderPropertyCurve {
+    /// Names of the entity and its parents, starting with the entity of the player
+    pub path: EntityPath,
+    /// The animated property
+    pub channel: BlenderPropertyChannel,
+    /// Time of each keyframe, in seconds
+    pub keyframe_timestamps: Vec<f32>,
+    /// Value of each keyframe
+    pub values: Vec<f32>,
+}
+
+impl BlenderPropertyCurve {
+    /// Returns the value of the curve at "time", in seconds
+    pub fn sample(&self, time: f32) -> f32 {
+        let next = self
+            .keyframe_timestamps
+            .partition_point(|timestamp| *timestamp <= time);
+        match next {
+            0 => self.values.first().copied().unwrap_or_default(),
+            next if next >= self.values.len() => self.values.last().copied().unwrap_or_default(),
+            next => {
+                let (a, b) = (
+                    self.keyframe_timestamps[next - 1],
+                    self.keyframe_timestamps[next],
+                );
+                let t = if b > a { (time - a) / (b - a) } else { 1.0 };
+                self.values[next - 1] + (self.values[next] - self.values[next - 1]) * t
+            }
+        }
+    }
+}
+
+/// The animations of the properties of entities that an AnimationClip can not animate, like the
+/// weights of morph targets, materials, lights, cameras and visibility, and the markers reached
+/// along the way. Every action has one, labeled like its AnimationClip followed by
+/// "/Properties", e.g. "demo.blend#ACSmile/Properties". The markers of the timeline of every
+/// scene have one too, e.g. "demo.blend#SCScene/Markers".
+#[derive(Debug, Clone, Default, TypeUuid)]
+#[uuid = "2f7b9c1e-6a0d-4b8e-9f34-1c5d7e2a8b60"]
+pub struct BlenderPropertyClip {
+    curves: Vec<BlenderPropertyCurve>,
+    markers: Vec<BlenderMarker>,
+    duration: f32,
+}
+
+impl BlenderPropertyClip {
+    /// The curves of the clip
+    pub fn curves(&self) -> &[BlenderPropertyCurve] {
+        &self.curves
+    }
+
+    /// Duration of the clip, in seconds
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    /// Adds a curve to the clip
+    pub fn add_curve(&mut self, curve: BlenderPropertyCurve) {
+        if let Some(last) = curve.keyframe_timestamps.last() {
+            self.duration = self.duration.max(*last);
+        }
+        self.curves.push(curve);
+    }
+
+    /// The markers of the clip
+    pub fn markers(&self) -> &[BlenderMarker] {
+        &self.markers
+    }
+
+    /// Adds a marker to the clip
+    pub fn add_marker(&mut self, marker: BlenderMarker) {
+        self.duration = self.duration.max(marker.time);
+        self.markers.push(marker);
+    }
+
+    /// Makes the clip last at least "duration" seconds, like the AnimationClip it is played with
+    pub(crate) fn extend_duration(&mut self, duration: f32) {
+        self.duration = self.duration.max(duration);
+    }
+}
+
+/// Plays a BlenderPropertyClip along with the AnimationPlayer of the same entity, following its
+/// elapsed time, and sends a BlenderMarkerReached event for every marker it reaches. Added to
+/// spawned root objects next to their AnimationPlayer. Without an AnimationPlayer, the clip is
+/// played on its own, e.g. to play the markers of a scene.
+#[derive(Component, Debug, Clone, Default)]
+pub struct BlenderPropertyPlayer {
+    /// The clip being played, usually the one of the action played by the AnimationPlayer
+    pub clip: Option<Handle<BlenderPropertyClip>>,
+    /// Whether the clip repeats, like the AnimationPlayer
+    pub repeat: bool,
+    elapsed: f32,
+    // The clip and the elapsed time the markers were last checked at
+    previous: Option<(Handle<BlenderPropertyClip>, f32)>,
+}
+
+impl BlenderPropertyPlayer {
+    /// Time elapsed playing the clip, in seconds. Follows the AnimationPlayer of the entity, if
+    /// any.
+    pub fn elapsed(&self) -> f32 {
+        self.elapsed
+    }
+
+    /// Plays a clip from its start
+    pub fn play(&mut self, clip: Handle<BlenderPropertyClip>) -> &mut Self {
+        self.clip = Some(clip);
+        self.elapsed = 0.0;
+        self.previous = None;
+        self
+    }
+}
+
+/// Marks entities whose material was copied, so that it can be animated without changing the
+/// other entities using the same material
+#[derive(Component)]
+pub(crate) struct AnimatedMaterial;
+
+/// The components animated by BlenderPropertyClips
+#[derive(SystemParam)]
+pub(crate) struct PropertyTargets<'w, 's> {
+    commands: Commands<'w, 's>,
+    morph_weights: Query<'w, 's, &'static mut MorphWeights>,
+    visibilities: Query<'w, 's, &'static mut Visibility>,
+    materials: Query<
+        'w,
+        's,
+        (
+            &'static mut Handle<StandardMaterial>,
+            Option<&'static AnimatedMaterial>,
+        ),
+    >,
+    material_assets: ResMut<'w, Assets<StandardMaterial>>,
+    // Entities whose material was copied in this run, before their AnimatedMaterial is inserted
+    copied_materials: Local<'s, HashSet<Entity>>,
+    point_lights: Query<'w, 's, &'static mut PointLight>,
+    spot_lights: Query<'w, 's, &'static mut SpotLight>,
+    directional_lights: Query<'w, 's, &'static mut DirectionalLight>,
+    cameras: Query<'w, 's, (&'static mut Projection, &'static BlenderCamera)>,
+}
+
+impl<'w, 's> PropertyTargets<'w, 's> {
+    /// Sets the value of a channel of an entity, or of its light or camera child
+    fn set(
+        &mut self,
+        entity: Entity,
+        children: &Query<&Children>,
+        channel: &BlenderPropertyChannel,
+        value: f32,
+    ) {
+        // Only write values that change, to keep change detection meaningful
+        match channel {
+            BlenderPropertyChannel::MorphWeight(target) => {
+                if let Ok(mut weights) = self.morph_weights.get_mut(entity) {
+                    if weights.get(target).is_some_and(|weight| weight != value) {
+                        weights.set(target, value);
+                    }
+                }
+            }
+            BlenderPropertyChannel::Hidden => {
+                if let Ok(mut visibility) = self.visibilities.get_mut(entity) {
+                    let hidden = if value > 0.5 {
+                        Visibility::Hidden
+                    } else {
+                        Visibility::Inherited
+                    };
+                    if *visibility != hidden {
+                        *visibility = hidden;
+                    }
+                }
+            }
+            BlenderPropertyChannel::MaterialBaseColor(_)
+            | BlenderPropertyChannel::MaterialEmissive(_)
+            | BlenderPropertyChannel::MaterialMetallic
+            | BlenderPropertyChannel::MaterialRoughness
+            | BlenderPropertyChannel::MaterialReflectance => {
+                self.set_material(entity, channel, value)
+            }
+            BlenderPropertyChannel::LightColor(_) | BlenderPropertyChannel::LightIntensity => {
+                for entity in with_children(entity, children) {
+                    self.set_light(entity, channel, value);
+                }
+            }
+            BlenderPropertyChannel::CameraFocalLength
+            | BlenderPropertyChannel::CameraOrthographicScale
+            | BlenderPropertyChannel::CameraNear
+            | BlenderPropertyChannel::CameraFar => {
+                for entity in with_children(entity, children) {
+                    self.set_camera(entity, channel, value);
+                }
+            }
+        }
+    }
+
+    /// Sets a material channel. The material of the entity is copied the first time it changes.
+    fn set_material(&mut self, entity: Entity, channel: &BlenderPropertyChannel, value: f32) {
+        let Ok((mut handle, animated)) = self.materials.get_mut(entity) else {
+            return;
+        };
+        let Some(current) = self.material_assets.get(&*handle) else {
+            return;
+        };
+        if material_value(current, channel) == Some(value) {
+            return;
+        }
+        if animated.is_none() && self.copied_materials.insert(entity) {
+            let copy = current.clone();
+            *handle = self.material_assets.add(copy);
+            self.commands.entity(entity).insert(AnimatedMaterial);
+        }
+        let Some(material) = self.material_assets.get_mut(&*handle) else {
+            return;
+        };
+
+        match channel {
+            BlenderPropertyChannel::MaterialBaseColor(index) => {
+                set_color_component(&mut material.base_color, *index, value);
+                // Materials fading out need to be blended
+                if *index == 3 && value < 1.0 && material.alpha_mode == AlphaMode::Opaque {
+                    material.alpha_mode = AlphaMode::Blend;
+                }
+            }
+            BlenderPropertyChannel::MaterialEmissive(index) => {
+                set_color_component(&mut material.emissive, *index, value)
+            }
+            BlenderPropertyChannel::MaterialMetallic => material.metallic = value,
+            BlenderPropertyChannel::MaterialRoughness => material.perceptual_roughness = value,
+            BlenderPropertyChannel::MaterialReflectance => material.reflectance = value,
+            _ => {}
+        }
+    }
+
+    /// Sets a light channel of the light of an entity, if it has one
+    fn set_light(&mut self, entity: Entity, channel: &BlenderPropertyChannel, value: f32) {
+        if let Ok(mut light) = self.point_lights.get_mut(entity) {
+            let inner = light.bypass_change_detection();
+            if set_light_value(&mut inner.color, &mut inner.intensity, channel, value) {
+                light.set_changed();
+            }
+        } else if let Ok(mut light) = self.spot_lights.get_mut(entity) {
+            let inner = light.bypass_change_detection();
+            if set_light_value(&mut inner.color, &mut inner.intensity, channel, value) {
+                light.set_changed();
+            }
+        } else if let Ok(mut light) = self.directional_lights.get_mut(entity) {
+            let inner = light.bypass_change_detection();
+            if set_light_value(&mut inner.color, &mut inner.illuminance, channel, value) {
+                light.set_changed();
+            }
+        }
+    }
+
+    /// Sets a camera channel of the Projection of an entity, if it is a Blender camera
+    fn set_camera(&mut self, entity: Entity, channel: &BlenderPropertyChannel, value: f32) {
+        let Ok((mut projection, camera)) = self.cameras.get_mut(entity) else {
+            return;
+        };
+
+        let changed = match (projection.bypass_change_detection(), channel) {
+            (Projection::Perspective(perspective), BlenderPropertyChannel::CameraFocalLength) => {
+                let fov = camera.vertical_fov(value, perspective.aspect_ratio);
+                set_if_changed(&mut perspective.fov, fov)
+            }
+            (Projection::Perspective(perspective), BlenderPropertyChannel::CameraNear) => {
+                set_if_changed(&mut perspective.near, value)
+            }
+            (Projection::Perspective(perspective), BlenderPropertyChannel::CameraFar) => {
+                set_if_changed(&mut perspective.far, value)
+            }
+            (
+                Projection::Orthographic(orthographic),
+                BlenderPropertyChannel::CameraOrthographicScale,
+            ) => {
+                let changed = match orthographic.scaling_mode {
+                    ScalingMode::AutoMax { max_width, .. } => max_width != value,
+                    ScalingMode::FixedHorizontal(scale) | ScalingMode::FixedVertical(scale) => {
+                        scale != value
+                    }
+                    _ => true,
+                };
+                if changed {
+                    orthographic.scaling_mode = camera.scaling_mode(value);
+                }
+                changed
+            }
+            (Projection::Orthographic(orthographic), BlenderPropertyChannel::CameraNear) => {
+                set_if_changed(&mut orthographic.near, value)
+            }
+            (Projection::Orthographic(orthographic), BlenderPropertyChannel::CameraFar) => {
+                set_if_changed(&mut orthographic.far, value)
+            }
+            _ => false,
+        };
+        if changed {
+            projection.set_changed();
+        }
+    }
+}
+
+/// Sets a light channel of the color and intensity of a light. Returns true if it changed.
+fn set_light_value(
+    color: &mut Color,
+    intensity: &mut f32,
+    channel: &BlenderPropertyChannel,
+    value: f32,
+) -> bool {
+    match channel {
+        BlenderPropertyChannel::LightColor(index) => {
+            if color.as_rgba_f32().get(*index) == Some(&value) {
+                return false;
+            }
+            set_color_component(color, *index, value);
+            true
+        }
+        BlenderPropertyChannel::LightIntensity => set_if_changed(intensity, value),
+        _ => false,
+    }
+}
+
+/// Sets a value if it differs from "value". Returns true if it changed.
+fn set_if_changed(field: &mut f32, value: f32) -> bool {
+    if *field == value {
+        return false;
+    }
+    *field = value;
+    true
+}
+
+/// Returns the current value of a material channel
+fn material_value(material: &StandardMaterial, channel: &BlenderPropertyChannel) -> Option<f32> {
+    match channel {
+        BlenderPropertyChannel::MaterialBaseColor(index) => {
+            material.base_color.as_rgba_f32().get(*index).copied()
+        }
+        BlenderPropertyChannel::MaterialEmissive(index) => {
+            material.emissive.as_rgba_f32().get(*index).copied()
+        }
+        BlenderPropertyChannel::MaterialMetallic => Some(material.metallic),
+        BlenderPropertyChannel::MaterialRoughness => Some(material.perceptual_roughness),
+        BlenderPropertyChannel::MaterialReflectance => Some(material.reflectance),
+        _ => None,
+    }
+}
+
+/// Sets a component of a color, from red (0) to alpha (3)
+fn set_color_component(color: &mut Color, index: usize, value: f32) {
+    match index {
+        0 => color.set_r(value),
+        1 => color.set_g(value),
+        2 => color.set_b(value),
+        3 => color.set_a(value),
+        _ => color,
+    };
+}
+
+/// Returns an entity followed by its children
+fn with_children(entity: Entity, children: &Query<&Children>) -> Vec<Entity> {
+    let mut entities = vec![entity];
+    if let Ok(children) = children.get(entity) {
+        entities.extend(children.iter().copied());
+    }
+    entities
+}
+
+/// Returns the entity at "path" below "root", whose name is the first part of the path
+fn find_entity(
+    root: Entity,
+    path: &EntityPath,
+    names: &Query<&Name>,
+    children: &Query<&Children>,
+) -> Option<Entity> {
+    let mut current = root;
+    for part in path.parts.iter().skip(1) {
+        current = children
+            .get(current)
+            .ok()?
+            .iter()
+            .copied()
+            .find(|child| names.get(*child).is_ok_and(|name| name == part))?;
+    }
+    Some(current)
+}
+
+/// Returns true if a clip of "duration" seconds reaches the time "time" of a marker after "from"
+/// and until "to" seconds were elapsed playing it
+fn reaches(time: f32, from: f32, to: f32, duration: f32, repeat: bool) -> bool {
+    if repeat && duration > 0.0 {
+        // Whether a repetition reaches the marker in between
+        ((to - time) / duration).floor() > ((from - time) / duration).floor().max(-1.0)
+    } else {
+        time > from && time <= to
+    }
+}
+
+/// Applies the BlenderPropertyClip of every BlenderPropertyPlayer at the elapsed time of its
+/// AnimationPlayer, or at its own elapsed time, and sends the markers it reached since the last
+/// update
+pub(crate) fn play_property_clips(
+    mut players: Query<(Entity, &mut BlenderPropertyPlayer, Option<&AnimationPlayer>)>,
+    clips: Res<Assets<BlenderPropertyClip>>,
+    names: Query<&Name>,
+    children: Query<&Children>,
+    time: Res<Time>,
+    mut reached: EventWriter<BlenderMarkerReached>,
+    mut targets: PropertyTargets,
+) {
+    targets.copied_materials.clear();
+    for (root, mut player, animation_player) in &mut players {
+        let Some(handle) = player.clip.clone() else {
+            continue;
+        };
+        let Some(clip) = clips.get(&handle) else {
+            continue;
+        };
+
+        player.elapsed = match animation_player {
+            Some(animation_player) => animation_player.elapsed(),
+            None => player.elapsed + time.delta_seconds(),
+        };
+        let elapsed = player.elapsed;
+        let time = if player.repeat && clip.duration > 0.0 {
+            elapsed.rem_euclid(clip.duration)
+        } else {
+            elapsed.clamp(0.0, clip.duration)
+        };
+
+        for curve in &clip.curves {
+            let Some(entity) = find_entity(root, &curve.path, &names, &children) else {
+                continue;
+            };
+            targets.set(entity, &children, &curve.channel, curve.sample(time));
+        }
+
+        // Markers are reached from the start when the clip changes or restarts
+        let from = match &player.previous {
+            Some((previous, from)) if *previous == handle && *from <= elapsed => *from,
+            _ => -f32::EPSILON,
+        };
+        for marker in &clip.markers {
+            if reaches(marker.time, from, elapsed, clip.duration, player.repeat) {
+                reached.send(BlenderMarkerReached {
+                    name: marker.name.clone(),
+                    frame: marker.frame,
+                    entity: root,
+                    camera: marker.camera.clone(),
+                });
+            }
+        }
+        player.previous = Some((handle, elapsed));
+    }
+}
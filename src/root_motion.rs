@@ -0,0 +1,183 @@
+use bevy_animation::AnimationPlayer;
+use bevy_asset::{Assets, Handle};
+use bevy_ecs::{
+    component::Component,
+    system::{Query, Res},
+};
+use bevy_math::{Mat4, Quat, Vec3};
+use bevy_reflect::TypeUuid;
+use bevy_transform::prelude::Transform;
+
+/// The horizontal translation and the yaw stripped from the root bone of an action, relative to
+/// the first frame of the clip and in the space of the armature. Loaded with the clips of actions
+/// and NLA strips when the BlenderAnimationSettings name a root motion bone, labeled like the
+/// AnimationClip followed by "/RootMotion", e.g. "robot.blend#ACWalk/RootMotion".
+#[derive(Debug, Clone, Default, TypeUuid)]
+#[uuid = "b4e7d2a9-1c36-4f85-8e0b-6a2f9d3c5e17"]
+pub struct BlenderRootMotion {
+    /// Times of the keyframes, in seconds
+    pub keyframe_timestamps: Vec<f32>,
+    /// Horizontal translation at every keyframe
+    pub translations: Vec<Vec3>,
+    /// Rotation around the vertical axis at every keyframe
+    pub rotations: Vec<Quat>,
+}
+
+impl BlenderRootMotion {
+    /// Duration of the root motion, in seconds
+    pub fn duration(&self) -> f32 {
+        self.keyframe_timestamps.last().copied().unwrap_or(0.0)
+    }
+
+    /// Returns the root motion at "time", interpolated linearly between keyframes
+    pub fn sample(&self, time: f32) -> Transform {
+        let index = self
+            .keyframe_timestamps
+            .partition_point(|timestamp| *timestamp <= time);
+        let transform = |i: usize| {
+            Transform::from_translation(self.translations[i]).with_rotation(self.rotations[i])
+        };
+        if self.keyframe_timestamps.is_empty() {
+            return Transform::IDENTITY;
+        }
+        if index == 0 {
+            return transform(0);
+        }
+        if index == self.keyframe_timestamps.len() {
+            return transform(index - 1);
+        }
+
+        let (start, end) = (
+            self.keyframe_timestamps[index - 1],
+            self.keyframe_timestamps[index],
+        );
+        let t = (time - start) / (end - start);
+        Transform::from_translation(self.translations[index - 1].lerp(self.translations[index], t))
+            .with_rotation(self.rotations[index - 1].slerp(self.rotations[index], t))
+    }
+
+    /// Returns the motion from "from" to "to" seconds elapsed playing the root motion, which goes
+    /// on from where the previous repetition ended when it repeats
+    fn motion(&self, from: f32, to: f32, repeat: bool) -> Mat4 {
+        let duration = self.duration();
+        let matrix = |time: f32| self.sample(time).compute_matrix();
+        if !repeat || duration <= 0.0 {
+            return matrix(from).inverse() * matrix(to);
+        }
+
+        let (from_cycle, to_cycle) = ((from / duration).floor(), (to / duration).floor());
+        let (from, to) = (from.rem_euclid(duration), to.rem_euclid(duration));
+        if from_cycle == to_cycle {
+            return matrix(from).inverse() * matrix(to);
+        }
+        let mut motion = matrix(from).inverse() * matrix(duration);
+        for _ in 0..(to_cycle - from_cycle - 1.0) as usize {
+            motion *= matrix(duration);
+        }
+        motion * matrix(to)
+    }
+}
+
+/// Moves the Transform of its entity by the BlenderRootMotion of the clip played by the
+/// AnimationPlayer of the entity, following its elapsed time. The root motion is applied in the
+/// space of the entity, which is expected to be the armature.
+#[derive(Component, Debug, Clone, Default)]
+pub struct BlenderRootMotionPlayer {
+    /// The root motion being played, usually the one of the clip played by the AnimationPlayer
+    pub root_motion: Option<Handle<BlenderRootMotion>>,
+    /// Whether the root motion repeats, like the AnimationPlayer
+    pub repeat: bool,
+    // The root motion and the elapsed time it was last applied at
+    previous: Option<(Handle<BlenderRootMotion>, f32)>,
+}
+
+impl BlenderRootMotionPlayer {
+    /// Plays a root motion from its start
+    pub fn play(&mut self, root_motion: Handle<BlenderRootMotion>) -> &mut Self {
+        self.root_motion = Some(root_motion);
+        self.previous = None;
+        self
+    }
+}
+
+/// Returns the label of the root motion of a clip, from the label of its AnimationClip
+pub(crate) fn root_motion_label(label: &str) -> String {
+    format!("{}/RootMotion", label)
+}
+
+/// Returns the rotation of "rotation" around the vertical axis
+fn yaw(rotation: Quat) -> Quat {
+    // The twist of the rotation around Y
+    let twist = Quat::from_xyzw(0.0, rotation.y, 0.0, rotation.w);
+    if twist.length_squared() > f32::EPSILON {
+        twist.normalize()
+    } else {
+        Quat::IDENTITY
+    }
+}
+
+/// Strips the horizontal translation and the yaw from the keyframes of a root bone, in the Bevy
+/// coordinate system, and returns them as a BlenderRootMotion. The bone keeps the horizontal
+/// position and the yaw of its first keyframe.
+pub(crate) fn extract_root_motion(
+    timestamps: &[f32],
+    translations: &mut [Vec3],
+    rotations: &mut [Quat],
+) -> BlenderRootMotion {
+    let mut root_motion = BlenderRootMotion {
+        keyframe_timestamps: timestamps.to_vec(),
+        ..Default::default()
+    };
+    let (Some(first_translation), Some(first_rotation)) =
+        (translations.first().copied(), rotations.first().copied())
+    else {
+        return root_motion;
+    };
+    let first_yaw = yaw(first_rotation);
+
+    for (translation, rotation) in translations.iter_mut().zip(rotations.iter_mut()) {
+        // The root motion moves the stripped bone to where the bone was
+        let motion_rotation = yaw(*rotation) * first_yaw.inverse();
+        let stripped = Vec3::new(first_translation.x, translation.y, first_translation.z);
+        root_motion
+            .translations
+            .push(*translation - motion_rotation * stripped);
+        root_motion.rotations.push(motion_rotation);
+
+        *translation = stripped;
+        *rotation = motion_rotation.inverse() * *rotation;
+    }
+    root_motion
+}
+
+/// Moves the Transform of every entity with a BlenderRootMotionPlayer by the root motion played
+/// since the last update
+pub(crate) fn apply_root_motion(
+    mut players: Query<(
+        &mut BlenderRootMotionPlayer,
+        &AnimationPlayer,
+        &mut Transform,
+    )>,
+    root_motions: Res<Assets<BlenderRootMotion>>,
+) {
+    for (mut player, animation_player, mut transform) in &mut players {
+        let Some(handle) = player.root_motion.clone() else {
+            continue;
+        };
+        let Some(root_motion) = root_motions.get(&handle) else {
+            continue;
+        };
+
+        // The root motion starts over when the clip changes or restarts
+        let elapsed = animation_player.elapsed();
+        let from = match &player.previous {
+            Some((previous, from)) if *previous == handle && *from <= elapsed => *from,
+            _ => 0.0,
+        };
+        let motion = root_motion.motion(from, elapsed, player.repeat);
+        if from != elapsed {
+            *transform = Transform::from_matrix(transform.compute_matrix() * motion);
+        }
+        player.previous = Some((handle, elapsed));
+    }
+}
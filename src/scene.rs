@@ -0,0 +1,130 @@
+use bevy_asset::{AssetEvent, Assets, HandleId, LabelId, SourcePathId};
+use bevy_ecs::{
+    event::EventReader,
+    system::{Commands, Local, Res, Resource},
+};
+use bevy_reflect::TypeUuid;
+use blend::{Blend, Instance};
+use std::path::Path;
+
+use crate::{animation::BlenderAnimationSettings, get_int};
+
+/// Label of the timing of the first scene of a blend file, which times its actions, e.g.
+/// "demo.blend#Timing"
+pub(crate) const BLEND_TIMING_LABEL: &str = "Timing";
+
+/// Frame rate and frame range of a Blender scene, used to convert the frames of its actions and
+/// markers to seconds. The timing of every scene is loaded as a labeled asset, e.g.
+/// "demo.blend#SCScene/Timing", and the timing of the first scene of a file, which times its
+/// actions, is also labeled "demo.blend#Timing". The timing of the file chosen by the timing_file
+/// of the BlenderAnimationSettings, or of the first file loaded, replaces the resource, which has
+/// Blender's default timing until then.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, TypeUuid)]
+#[uuid = "8c3e1f52-7b4d-4a90-b6e2-3d9a5f0c1e74"]
+pub struct BlenderSceneTiming {
+    /// Frames per second, from the frame rate and its base
+    pub frames_per_second: f32,
+    /// First frame of the scene
+    pub start_frame: i32,
+    /// Last frame of the scene
+    pub end_frame: i32,
+}
+
+impl Default for BlenderSceneTiming {
+    fn default() -> Self {
+        // Blender's defaults
+        Self {
+            frames_per_second: 24.0,
+            start_frame: 1,
+            end_frame: 250,
+        }
+    }
+}
+
+impl BlenderSceneTiming {
+    /// Converts a number of frames to seconds
+    pub fn frames_to_seconds(&self, frames: f32) -> f32 {
+        frames / self.frames_per_second
+    }
+
+    /// Converts seconds to a number of frames
+    pub fn seconds_to_frames(&self, seconds: f32) -> f32 {
+        seconds * self.frames_per_second
+    }
+
+    /// Returns the time of a frame since the start of the frame range, in seconds
+    pub fn frame_time(&self, frame: f32) -> f32 {
+        self.frames_to_seconds(frame - self.start_frame as f32)
+    }
+
+    /// Returns the duration of the frame range, in seconds
+    pub fn duration(&self) -> f32 {
+        self.frame_time(self.end_frame as f32).max(0.0)
+    }
+}
+
+/// Returns the label of the timing of a scene, e.g. "SCScene/Timing"
+pub(crate) fn scene_timing_label(scene: &Instance) -> String {
+    format!("{}/Timing", scene.get("id").get_string("name"))
+}
+
+/// Takes a Blend::Instance scene and reads its BlenderSceneTiming from its render settings
+pub(crate) fn instance_to_scene_timing(scene: &Instance) -> BlenderSceneTiming {
+    let render = scene.get("r");
+    let base = render.get_f32("frs_sec_base");
+    let frames_per_second = get_int(&render, "frs_sec") as f32 / base;
+    let start_frame = render.get_i32("sfra");
+
+    BlenderSceneTiming {
+        frames_per_second: if frames_per_second.is_finite() && frames_per_second > 0.0 {
+            frames_per_second
+        } else {
+            BlenderSceneTiming::default().frames_per_second
+        },
+        start_frame,
+        end_frame: render.get_i32("efra").max(start_frame),
+    }
+}
+
+/// Returns the BlenderSceneTiming of the first scene of a blend file, which times its actions
+pub(crate) fn blend_scene_timing(blend: &Blend) -> BlenderSceneTiming {
+    blend
+        .get_by_code(*b"SC")
+        .into_iter()
+        .next()
+        .map(|scene| instance_to_scene_timing(&scene))
+        .unwrap_or_default()
+}
+
+/// Inserts the BlenderSceneTiming resource when the timing of the chosen blend file is loaded
+pub(crate) fn insert_scene_timing(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<BlenderSceneTiming>>,
+    timings: Res<Assets<BlenderSceneTiming>>,
+    settings: Res<BlenderAnimationSettings>,
+    mut first_file: Local<Option<SourcePathId>>,
+) {
+    for event in events.iter() {
+        let (AssetEvent::Created { handle } | AssetEvent::Modified { handle }) = event else {
+            continue;
+        };
+        // Only the timing of whole files is inserted, not the one of every scene
+        let HandleId::AssetPathId(id) = handle.id() else {
+            continue;
+        };
+        if id.label_id() != LabelId::from(Some(BLEND_TIMING_LABEL)) {
+            continue;
+        }
+
+        let file = match &settings.timing_file {
+            Some(path) => SourcePathId::from(Path::new(path)),
+            None => *first_file.get_or_insert(id.source_path_id()),
+        };
+        if id.source_path_id() != file {
+            continue;
+        }
+        if let Some(timing) = timings.get(handle) {
+            commands.insert_resource(*timing);
+        }
+    }
+}
@@ -0,0 +1,481 @@
+use bevy_asset::{Assets, Handle, LoadContext, LoadedAsset};
+use bevy_ecs::{
+    component::Component,
+    system::{Query, Res},
+};
+use bevy_math::{Mat4, Vec3};
+use bevy_reflect::TypeUuid;
+use bevy_render::{
+    mesh::{Mesh, MeshVertexAttribute},
+    render_resource::{Extent3d, TextureDimension, TextureFormat, VertexFormat},
+    texture::{Image, ImageSampler},
+};
+use bevy_time::Time;
+use blend::{Blend, Instance};
+use std::path::{Path, PathBuf};
+
+use crate::{coordinates::Conversion, get_int, mesh, morph, object, scene, BevyBlenderError};
+
+/// UV of the texel of every vertex in the first frame of its BlenderVertexAnimation, added to the
+/// vertex animation mesh as its second UV channel
+pub const ATTRIBUTE_VERTEX_ANIMATION_UV: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_Uv_1", 288_450_617, VertexFormat::Float32x2);
+
+/// Widest vertex animation texture, larger meshes wrap their vertices over several rows per frame
+const MAX_TEXTURE_WIDTH: u32 = 4096;
+
+// PointCache.flag bits (DNA_object_force_types.h)
+const PTCACHE_DISK_CACHE: i32 = 1 << 6;
+const PTCACHE_EXTERNAL: i32 = 1 << 9;
+
+/// A mesh object to bake to vertex animation textures when its .blend file is loaded, listed in
+/// the vertex_animations of the BlenderAnimationSettings
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlenderVertexAnimationBake {
+    /// Name of the object, without its "OB" prefix
+    pub object: String,
+    /// Path of the .blend file of the object (e.g. "demo.blend"). If None, the object is baked
+    /// from every loaded .blend file that has an object of that name, and skipped in the others.
+    pub file: Option<String>,
+    /// First and last frames to sample, the frame range of the scene if None
+    pub frame_range: Option<(i32, i32)>,
+    /// Number of frames between two samples
+    pub frame_step: u32,
+}
+
+impl BlenderVertexAnimationBake {
+    /// Bakes every frame of the scene of the object named "object"
+    pub fn new(object: impl Into<String>) -> Self {
+        Self {
+            object: object.into(),
+            file: None,
+            frame_range: None,
+            frame_step: 1,
+        }
+    }
+}
+
+/// The deformation of a mesh object by its animated shape keys or by its cloth point cache, baked
+/// to textures for the GPU to play it on many instances. Labeled like the object followed by
+/// "/VertexAnimation", e.g. "demo.blend#OBFlag/VertexAnimation".
+///
+/// Both textures have one texel per vertex and frame: vertex i of frame f is at column
+/// i % columns and row f * rows_per_frame + i / columns. The vertices of the mesh hold the UV of
+/// their texel in the first frame as ATTRIBUTE_VERTEX_ANIMATION_UV, which frame_offset moves to
+/// other frames.
+#[derive(Debug, Clone, TypeUuid)]
+#[uuid = "f3e6919d-159b-413f-88dd-5cac103ad4b3"]
+pub struct BlenderVertexAnimation {
+    /// Offsets of the vertices from their position in the mesh, in Rgba32Float texels
+    pub positions: Handle<Image>,
+    /// Normals of the deformed vertices, in Rgba32Float texels
+    pub normals: Handle<Image>,
+    /// Mesh of the object, with the ATTRIBUTE_VERTEX_ANIMATION_UV of its vertices
+    pub mesh: Handle<Mesh>,
+    /// Number of vertices of the mesh
+    pub vertex_count: u32,
+    /// Width of the textures
+    pub columns: u32,
+    /// Number of texture rows holding the vertices of a frame
+    pub rows_per_frame: u32,
+    /// Number of sampled frames
+    pub frame_count: u32,
+    /// Number of sampled frames per second of animation
+    pub frames_per_second: f32,
+}
+
+impl BlenderVertexAnimation {
+    /// Returns the duration of the animation in seconds, from its first to its last frame
+    pub fn duration(&self) -> f32 {
+        self.frame_count.saturating_sub(1) as f32 / self.frames_per_second
+    }
+
+    /// Returns the offset to add to the V of ATTRIBUTE_VERTEX_ANIMATION_UV to sample "frame"
+    pub fn frame_offset(&self, frame: u32) -> f32 {
+        frame.min(self.frame_count.saturating_sub(1)) as f32 / self.frame_count.max(1) as f32
+    }
+}
+
+/// Plays a BlenderVertexAnimation by advancing its frame every update, for the material of the
+/// entity to sample the textures of the animation at
+#[derive(Component, Debug, Clone)]
+pub struct BlenderVertexAnimationPlayer {
+    /// The animation played
+    pub animation: Handle<BlenderVertexAnimation>,
+    /// Speed of the animation, 1 being its normal speed
+    pub speed: f32,
+    /// Whether the animation starts over after its last frame
+    pub repeat: bool,
+    /// Whether the animation is paused
+    pub paused: bool,
+    /// Time since the animation started, in seconds
+    pub elapsed: f32,
+    /// Current frame, whose fractional part is the blend factor towards the next frame
+    pub frame: f32,
+}
+
+impl BlenderVertexAnimationPlayer {
+    /// Plays "animation" from its first frame, repeating it
+    pub fn new(animation: Handle<BlenderVertexAnimation>) -> Self {
+        Self {
+            animation,
+            speed: 1.0,
+            repeat: true,
+            paused: false,
+            elapsed: 0.0,
+            frame: 0.0,
+        }
+    }
+}
+
+/// Where the deformed vertex positions of a baked object come from
+enum Deformation {
+    /// Positions of the vertices at every sampled frame, in Bevy coordinates
+    Sampled(Vec<Vec<Vec3>>),
+    /// Point cache file of every sampled frame, along with the matrix from the Blender world
+    /// coordinates of the cache to the ones of the object
+    PointCache(Vec<PathBuf>, Mat4),
+}
+
+/// A vertex animation sampled from a .blend file, whose textures are made once its point cache
+/// files (if any) are read
+pub(crate) struct PendingVertexAnimation {
+    label: String,
+    mesh: Mesh,
+    /// Vertex positions of the mesh, in Bevy coordinates
+    rest: Vec<Vec3>,
+    face_loops: Vec<Vec<u32>>,
+    frames_per_second: f32,
+    deformation: Deformation,
+    conversion: Conversion,
+}
+
+impl PendingVertexAnimation {
+    /// Returns the label of the BlenderVertexAnimation
+    pub(crate) fn label(&self) -> &str {
+        &self.label
+    }
+}
+
+/// Samples the deformation of the object of "bake" in a Blend, by its cloth point cache if it is
+/// on disk, or by its animated shape keys otherwise. "blend_path" is the path of the .blend file,
+/// which point caches are relative to. Returns None if the bake is not for this file, or if it is
+/// for any file and the object is not in this one.
+pub(crate) fn sample_vertex_animation(
+    blend: &Blend,
+    bake: &BlenderVertexAnimationBake,
+    blend_path: &Path,
+    blend_version: (u8, u8, u8),
+    conversion: &Conversion,
+) -> anyhow::Result<Option<PendingVertexAnimation>> {
+    if bake
+        .file
+        .as_ref()
+        .is_some_and(|file| Path::new(file) != blend_path)
+    {
+        return Ok(None);
+    }
+    let Some(obj) = blend
+        .get_by_code(*b"OB")
+        .into_iter()
+        .find(|obj| object::object_name(obj) == bake.object)
+    else {
+        if bake.file.is_none() {
+            return Ok(None);
+        }
+        return Err(anyhow::Error::new(BevyBlenderError::MissingAsset {
+            asset_name: format!("OB{}", bake.object),
+            blend_file: blend_path.to_string_lossy().into_owned(),
+        }));
+    };
+    if obj.get_i16("type") != object::OB_MESH || !obj.is_valid("data") {
+        return Err(anyhow::Error::new(BevyBlenderError::InvalidInstanceType {
+            expected: String::from("Mesh"),
+            found: format!("object type {}", obj.get_i16("type")),
+        }));
+    }
+    let data = obj.get("data");
+
+    let timing = scene::blend_scene_timing(blend);
+    let (start, end) = bake
+        .frame_range
+        .unwrap_or((timing.start_frame, timing.end_frame));
+    let step = bake.frame_step.max(1);
+    let frames: Vec<i32> = (start..=end.max(start)).step_by(step as usize).collect();
+
+    let (positions, _) = mesh::instance_to_vertices(&data, blend_version);
+    let rest: Vec<Vec3> = positions.iter().map(|p| conversion.position(*p)).collect();
+
+    let deformation = if let Some(files) = point_cache_files(&obj, blend_path, &frames) {
+        Deformation::PointCache(files, object::get_world_matrix(&obj).inverse())
+    } else if morph::has_shape_keys(&data) {
+        let targets = morph::instance_to_morph_targets(&data, blend_version, conversion)?;
+        Deformation::Sampled(
+            frames
+                .iter()
+                .map(|frame| {
+                    let weights = morph::evaluated_morph_weights(&data, *frame as f32);
+                    let mut positions = targets.positions.clone();
+                    for (weight, deltas) in weights.weights.iter().zip(&targets.position_deltas) {
+                        for (position, delta) in positions.iter_mut().zip(deltas) {
+                            *position += *delta * *weight;
+                        }
+                    }
+                    positions
+                })
+                .collect(),
+        )
+    } else {
+        return Err(anyhow::Error::new(BevyBlenderError::UnsupportedAsset {
+            asset_type: String::from("vertex animation without shape keys or disk point cache"),
+        }));
+    };
+
+    Ok(Some(PendingVertexAnimation {
+        label: format!("{}/VertexAnimation", obj.get("id").get_string("name")),
+        mesh: mesh::instance_to_mesh(data.clone(), blend_version, conversion)?,
+        rest,
+        face_loops: mesh::instance_to_face_loops(&data),
+        frames_per_second: timing.frames_per_second / step as f32,
+        deformation,
+        conversion: *conversion,
+    }))
+}
+
+/// Returns the point cache file of every frame of the cloth simulation of a Blend::Instance
+/// object, if it is cached on disk
+fn point_cache_files(obj: &Instance, blend_path: &Path, frames: &[i32]) -> Option<Vec<PathBuf>> {
+    if !obj.is_valid("modifiers") {
+        return None;
+    }
+    let cache = obj
+        .get_iter("modifiers")
+        .find(|modifier| modifier.type_name == "ClothModifierData")
+        .filter(|modifier| modifier.is_valid("point_cache"))?
+        .get("point_cache");
+    if get_int(&cache, "flag") & PTCACHE_DISK_CACHE == 0 {
+        return None;
+    }
+
+    // Caches are named after their object (its name in hexadecimal) unless they have a name
+    let name = match cache.get_string("name") {
+        name if name.is_empty() => object::object_name(obj)
+            .bytes()
+            .map(|byte| format!("{:02X}", byte))
+            .collect(),
+        name => name,
+    };
+    let blend_directory = blend_path.parent().unwrap_or_else(|| Path::new(""));
+    let directory = if get_int(&cache, "flag") & PTCACHE_EXTERNAL != 0 {
+        let path = cache.get_string("path");
+        match path.strip_prefix("//") {
+            Some(relative) => blend_directory.join(relative),
+            None => PathBuf::from(path),
+        }
+    } else {
+        let stem = blend_path.file_stem().unwrap_or_default().to_string_lossy();
+        blend_directory.join(format!("blendcache_{}", stem))
+    };
+    let index = get_int(&cache, "index").max(0);
+
+    Some(
+        frames
+            .iter()
+            .map(|frame| directory.join(format!("{}_{:06}_{:02}.bphys", name, frame, index)))
+            .collect(),
+    )
+}
+
+/// Reads the vertex locations of an uncompressed point cache file (.bphys), in Blender world
+/// coordinates
+fn read_point_cache(bytes: &[u8]) -> anyhow::Result<Vec<Vec3>> {
+    // Size of every BPHYS_DATA_* type, whose data is stored point by point in this order
+    const DATA_SIZES: [usize; 8] = [4, 12, 12, 16, 12, 4, 12, 20];
+    const BPHYS_DATA_LOCATION: usize = 1;
+    const PTCACHE_TYPEFLAG_COMPRESS: u32 = 1 << 16;
+
+    let invalid = || {
+        anyhow::Error::new(BevyBlenderError::UnsupportedAsset {
+            asset_type: String::from("compressed or invalid point cache"),
+        })
+    };
+    let read_u32 = |offset: usize| {
+        bytes
+            .get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    // "BPHYSICS", the type and flags of the cache, its number of points and its data types
+    if bytes.get(0..8) != Some(b"BPHYSICS".as_slice()) {
+        return Err(invalid());
+    }
+    let (Some(typeflag), Some(points), Some(data_types)) =
+        (read_u32(8), read_u32(12), read_u32(16))
+    else {
+        return Err(invalid());
+    };
+    if typeflag & PTCACHE_TYPEFLAG_COMPRESS != 0 || data_types & (1 << BPHYS_DATA_LOCATION) == 0 {
+        return Err(invalid());
+    }
+
+    let sizes = DATA_SIZES
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| data_types & (1 << i) != 0);
+    let point_size: usize = sizes.clone().map(|(_, size)| size).sum();
+    let location_offset: usize = sizes
+        .take_while(|(i, _)| *i < BPHYS_DATA_LOCATION)
+        .map(|(_, size)| size)
+        .sum();
+
+    (0..points as usize)
+        .map(|point| {
+            let offset = 20 + point * point_size + location_offset;
+            let float = |i: usize| read_u32(offset + i).map(f32::from_bits);
+            match (float(0), float(4), float(8)) {
+                (Some(x), Some(y), Some(z)) => Ok(Vec3::new(x, y, z)),
+                _ => Err(invalid()),
+            }
+        })
+        .collect()
+}
+
+/// Reads the point cache files of a PendingVertexAnimation, if any, and bakes it to a
+/// BlenderVertexAnimation with its textures and mesh. Compressed point cache files are not
+/// supported, and their frames keep the previous shape like frames that are not cached.
+pub(crate) async fn load_vertex_animation<'a, 'b>(
+    pending: PendingVertexAnimation,
+    load_context: &'a mut LoadContext<'b>,
+) -> anyhow::Result<BlenderVertexAnimation> {
+    let frames = match pending.deformation {
+        Deformation::Sampled(frames) => frames,
+        Deformation::PointCache(files, world_to_object) => {
+            // Frames that are not cached, or not readable, keep the previous shape, the mesh before
+            // the simulation
+            let mut frames: Vec<Vec<Vec3>> = Vec::new();
+            for file in files {
+                let cached = match load_context.read_asset_bytes(&file).await {
+                    Ok(bytes) => read_point_cache(&bytes).unwrap_or_default(),
+                    Err(_) => Vec::new(),
+                };
+                let positions = if cached.len() == pending.rest.len() {
+                    cached
+                        .iter()
+                        .map(|p| {
+                            pending
+                                .conversion
+                                .position(world_to_object.transform_point3(*p))
+                        })
+                        .collect()
+                } else {
+                    frames.last().unwrap_or(&pending.rest).clone()
+                };
+                frames.push(positions);
+            }
+            frames
+        }
+    };
+
+    let vertex_count = pending.rest.len() as u32;
+    let columns = vertex_count.clamp(1, MAX_TEXTURE_WIDTH);
+    let rows_per_frame = (vertex_count + columns - 1) / columns;
+    let frame_count = frames.len() as u32;
+    let height = (rows_per_frame * frame_count).max(1);
+
+    // Texels of the vertices past the last one in a frame stay empty
+    let texel_count = (columns * height) as usize;
+    let mut position_data = vec![0; texel_count * 16];
+    let mut normal_data = vec![0; texel_count * 16];
+    for (frame, positions) in frames.iter().enumerate() {
+        let normals = mesh::vertex_normals(&pending.face_loops, positions);
+        for (i, (position, rest)) in positions.iter().zip(&pending.rest).enumerate() {
+            let texel = frame * (rows_per_frame * columns) as usize + i;
+            let normal = normals[i].normalize_or_zero();
+            write_texel(&mut position_data, texel, *position - *rest);
+            write_texel(&mut normal_data, texel, normal);
+        }
+    }
+
+    let uvs: Vec<[f32; 2]> = (0..vertex_count)
+        .map(|i| {
+            [
+                ((i % columns) as f32 + 0.5) / columns as f32,
+                ((i / columns) as f32 + 0.5) / height as f32,
+            ]
+        })
+        .collect();
+    let mut mesh = pending.mesh;
+    mesh.insert_attribute(ATTRIBUTE_VERTEX_ANIMATION_UV, uvs);
+
+    let label = pending.label;
+    Ok(BlenderVertexAnimation {
+        positions: load_context.set_labeled_asset(
+            format!("{}/Positions", label).as_str(),
+            LoadedAsset::new(vertex_animation_image(columns, height, position_data)),
+        ),
+        normals: load_context.set_labeled_asset(
+            format!("{}/Normals", label).as_str(),
+            LoadedAsset::new(vertex_animation_image(columns, height, normal_data)),
+        ),
+        mesh: load_context
+            .set_labeled_asset(format!("{}/Mesh", label).as_str(), LoadedAsset::new(mesh)),
+        vertex_count,
+        columns,
+        rows_per_frame,
+        frame_count,
+        frames_per_second: pending.frames_per_second,
+    })
+}
+
+fn write_texel(data: &mut [u8], texel: usize, value: Vec3) {
+    for (channel, value) in [value.x, value.y, value.z, 0.0].into_iter().enumerate() {
+        let offset = texel * 16 + channel * 4;
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Creates a vertex animation texture from Rgba32Float data, which is sampled without filtering
+/// so that the texels of neighbouring vertices are not blended
+fn vertex_animation_image(width: u32, height: u32, data: Vec<u8>) -> Image {
+    let mut image = Image {
+        data,
+        sampler_descriptor: ImageSampler::nearest(),
+        ..Default::default()
+    };
+    image.texture_descriptor.size = Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    image.texture_descriptor.dimension = TextureDimension::D2;
+    image.texture_descriptor.format = TextureFormat::Rgba32Float;
+
+    image
+}
+
+/// Advances the frame of every BlenderVertexAnimationPlayer
+pub(crate) fn play_vertex_animations(
+    time: Res<Time>,
+    animations: Res<Assets<BlenderVertexAnimation>>,
+    mut players: Query<&mut BlenderVertexAnimationPlayer>,
+) {
+    for mut player in &mut players {
+        let Some(animation) = animations.get(&player.animation) else {
+            continue;
+        };
+        if !player.paused {
+            player.elapsed += time.delta_seconds() * player.speed;
+        }
+
+        let duration = animation.duration();
+        let elapsed = if player.repeat && duration > 0.0 {
+            player.elapsed.rem_euclid(duration)
+        } else {
+            player.elapsed.clamp(0.0, duration)
+        };
+        let frame = elapsed * animation.frames_per_second;
+        if player.frame != frame {
+            player.frame = frame;
+        }
+    }
+}
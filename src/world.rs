@@ -0,0 +1,300 @@
+use bevy_asset::{AssetEvent, Assets, Handle, LoadContext, LoadedAsset};
+use bevy_core_pipeline::clear_color::ClearColor;
+use bevy_ecs::{
+    entity::Entity,
+    event::EventReader,
+    query::Changed,
+    system::{Commands, Query, Res},
+};
+use bevy_pbr::{AmbientLight, EnvironmentMapLight, FogFalloff, FogSettings};
+use bevy_reflect::TypeUuid;
+use bevy_render::{
+    color::Color,
+    texture::{CompressedImageFormats, Image, ImageType},
+};
+use blend::Instance;
+use std::path::{Path, PathBuf};
+
+use crate::{coordinates::Conversion, environment, get_int, nodes, BevyBlenderError};
+
+// World.mode bit for enabled mist (DNA_world_types.h)
+const WO_MIST: i32 = 1 << 0;
+
+// World.mistype values
+const MIST_QUADRATIC: i16 = 0;
+const MIST_LINEAR: i16 = 1;
+
+/// Takes a .blend file location and a world name and generates
+/// an appropriate asset_loader string. For example,
+/// blender_world!("demo.blend", "World") turns to "demo.blend#WOWorld".
+#[macro_export]
+macro_rules! blender_world {
+    ($blend_file:literal, $world_name:literal) => {
+        format!("{}#WO{}", $blend_file, $world_name).as_str()
+    };
+}
+
+/// The look of a Blender world (background, ambient light, mist and environment lighting), loaded
+/// as a labeled asset.
+/// Adding a Handle<BlenderWorld> to a camera applies the world to the app and to that camera.
+#[derive(Clone, TypeUuid)]
+#[uuid = "0b1a8bf9-357d-4831-aac3-aff9e0547f97"]
+pub struct BlenderWorld {
+    /// Suggested clear color, from the world's background color
+    pub clear_color: Color,
+    /// Ambient light, from the world's background color and strength
+    pub ambient_light: AmbientLight,
+    /// Fog, from the world's mist settings. None if mist is disabled.
+    pub fog: Option<FogSettings>,
+    /// Image based lighting, from the world's Environment Texture node. None if the world does
+    /// not have one.
+    pub environment_map: Option<EnvironmentMapLight>,
+}
+
+/// Where the image of an Environment Texture node can be read from
+pub(crate) enum EnvironmentImage {
+    /// The image is packed in the .blend file, along with the image's file extension
+    Packed(Vec<u8>, String),
+    /// The image is an external file, relative to the asset folder
+    External(PathBuf),
+}
+
+/// Takes a Blend::Instance world and converts it to a BlenderWorld. If the world uses nodes, the
+/// color and strength of a Background node with constant inputs will be used.
+pub(crate) fn instance_to_world(
+    instance: Instance,
+    _blend_version: (u8, u8, u8),
+    conversion: &Conversion,
+) -> anyhow::Result<BlenderWorld> {
+    // Don't process instances of types other than world
+    if instance.type_name != "World" {
+        return Err(anyhow::Error::new(BevyBlenderError::InvalidInstanceType {
+            expected: String::from("World"),
+            found: instance.type_name,
+        }));
+    }
+
+    // World colors are stored in linear color space
+    let mut color = [
+        instance.get_f32("horr"),
+        instance.get_f32("horg"),
+        instance.get_f32("horb"),
+    ];
+    let mut strength = 1.0;
+
+    // A node based world overrides the horizon color with its Background node
+    if get_int(&instance, "use_nodes") != 0 && instance.is_valid("nodetree") {
+        let background = nodes::find_node(&instance.get("nodetree"), "ShaderNodeBackground");
+        if let Some(background) = background {
+            if let Some(c) =
+                nodes::input_socket(&background, "Color").and_then(|s| nodes::socket_rgba(&s))
+            {
+                color = [c[0], c[1], c[2]];
+            }
+            if let Some(s) =
+                nodes::input_socket(&background, "Strength").and_then(|s| nodes::socket_float(&s))
+            {
+                strength = s;
+            }
+        }
+    }
+
+    let background = Color::rgb_linear(color[0], color[1], color[2]);
+
+    let fog = if get_int(&instance, "mode") & WO_MIST != 0 {
+        let start = conversion.length(instance.get_f32("miststa"));
+        let depth = conversion.length(instance.get_f32("mistdist"));
+        // Bevy has no quadratic falloffs with a start distance, so those use an exponential fog
+        // that becomes (nearly) opaque at the end of the mist
+        let falloff = match instance.get_i16("mistype") {
+            MIST_LINEAR => FogFalloff::Linear {
+                start,
+                end: start + depth,
+            },
+            MIST_QUADRATIC => FogFalloff::from_visibility_squared(start + depth),
+            _ => FogFalloff::from_visibility(start + depth),
+        };
+
+        Some(FogSettings {
+            color: background,
+            falloff,
+            ..Default::default()
+        })
+    } else {
+        None
+    };
+
+    Ok(BlenderWorld {
+        clear_color: Color::rgb_linear(
+            color[0] * strength,
+            color[1] * strength,
+            color[2] * strength,
+        ),
+        ambient_light: AmbientLight {
+            color: background,
+            brightness: strength,
+        },
+        fog,
+        environment_map: None,
+    })
+}
+
+/// Finds the image of the Environment Texture node of a Blend::Instance world. "blend_path" is the
+/// path of the .blend file, which relative image paths ("//") are relative to.
+pub(crate) fn instance_to_environment_image(
+    instance: &Instance,
+    blend_path: &Path,
+) -> Option<EnvironmentImage> {
+    if get_int(instance, "use_nodes") == 0 || !instance.is_valid("nodetree") {
+        return None;
+    }
+
+    let node = nodes::find_node(&instance.get("nodetree"), "ShaderNodeTexEnvironment")?;
+    if !node.is_valid("id") {
+        return None;
+    }
+    Some(instance_to_image_file(&node.get("id"), blend_path))
+}
+
+/// Finds where the pixels of a Blend::Instance image are, packed in the .blend file or in an
+/// external file. "blend_path" is the path of the .blend file, which relative image paths ("//")
+/// are relative to.
+pub(crate) fn instance_to_image_file(image: &Instance, blend_path: &Path) -> EnvironmentImage {
+    // Older versions of Blender call the image path "name"
+    let filepath = if image.is_valid("filepath") {
+        image.get_string("filepath")
+    } else {
+        image.get_string("name")
+    };
+    let extension = Path::new(&filepath)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    // Packed images are stored in "packedfiles" since Blender 2.8, and "packedfile" before
+    let packed_file = if image.is_valid("packedfiles") {
+        image
+            .get_iter("packedfiles")
+            .find(|packed| packed.is_valid("packedfile"))
+            .map(|packed| packed.get("packedfile"))
+    } else if image.is_valid("packedfile") {
+        Some(image.get("packedfile"))
+    } else {
+        None
+    };
+
+    if let Some(packed_file) = packed_file {
+        let mut data = packed_file.get_u8_vec("data");
+        data.truncate(packed_file.get_i32("size") as usize);
+        return EnvironmentImage::Packed(data, extension);
+    }
+
+    let path = match filepath.strip_prefix("//") {
+        Some(relative) => blend_path
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(relative.replace('\\', "/")),
+        None => PathBuf::from(filepath),
+    };
+    EnvironmentImage::External(path)
+}
+
+/// Reads the equirectangular image of a world's Environment Texture node (or of a light probe) and
+/// converts it to diffuse and specular cubemaps, which are added as labeled assets next to the
+/// world
+pub(crate) async fn load_environment_map<'a, 'b>(
+    label: &str,
+    image: EnvironmentImage,
+    conversion: &Conversion,
+    load_context: &'a mut LoadContext<'b>,
+) -> anyhow::Result<EnvironmentMapLight> {
+    let (bytes, extension) = match image {
+        EnvironmentImage::Packed(bytes, extension) => (bytes, extension),
+        EnvironmentImage::External(path) => {
+            let extension = path
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            (load_context.read_asset_bytes(&path).await?, extension)
+        }
+    };
+
+    // High dynamic range images are already in linear color space
+    let is_srgb = !matches!(extension.as_str(), "hdr" | "exr");
+    let image = Image::from_buffer(
+        &bytes,
+        ImageType::Extension(&extension),
+        CompressedImageFormats::NONE,
+        is_srgb,
+    )?;
+    let equirectangular = environment::Equirectangular::from_image(&image, *conversion)?;
+
+    let (diffuse_label, specular_label) = environment_map_labels(label);
+    Ok(EnvironmentMapLight {
+        diffuse_map: load_context.set_labeled_asset(
+            diffuse_label.as_str(),
+            LoadedAsset::new(environment::diffuse_cubemap(&equirectangular)),
+        ),
+        specular_map: load_context.set_labeled_asset(
+            specular_label.as_str(),
+            LoadedAsset::new(environment::specular_cubemap(&equirectangular)),
+        ),
+    })
+}
+
+/// Returns the labels of the diffuse and specular cubemaps loaded by load_environment_map for the
+/// asset "label"
+pub(crate) fn environment_map_labels(label: &str) -> (String, String) {
+    (
+        format!("{}/EnvironmentDiffuse", label),
+        format!("{}/EnvironmentSpecular", label),
+    )
+}
+
+/// Applies a BlenderWorld to the app (ClearColor and AmbientLight) and its fog and environment map
+/// to the camera holding its handle, whenever the handle or the world asset changes
+pub(crate) fn apply_blender_world(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<BlenderWorld>>,
+    worlds: Res<Assets<BlenderWorld>>,
+    cameras: Query<(Entity, &Handle<BlenderWorld>)>,
+    changed_cameras: Query<Entity, Changed<Handle<BlenderWorld>>>,
+) {
+    let changed_worlds: Vec<Handle<BlenderWorld>> = events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                Some(handle.clone_weak())
+            }
+            AssetEvent::Removed { .. } => None,
+        })
+        .collect();
+
+    for (entity, handle) in &cameras {
+        if !changed_cameras.contains(entity) && !changed_worlds.contains(handle) {
+            continue;
+        }
+        let Some(world) = worlds.get(handle) else {
+            continue;
+        };
+
+        commands.insert_resource(ClearColor(world.clear_color));
+        commands.insert_resource(world.ambient_light.clone());
+        match &world.fog {
+            Some(fog) => {
+                commands.entity(entity).insert(fog.clone());
+            }
+            None => {
+                commands.entity(entity).remove::<FogSettings>();
+            }
+        }
+        match &world.environment_map {
+            Some(environment_map) => {
+                commands.entity(entity).insert(environment_map.clone());
+            }
+            None => {
+                commands.entity(entity).remove::<EnvironmentMapLight>();
+            }
+        }
+    }
+}